@@ -6,7 +6,8 @@
 //! OAuth2 authentication provider using GitHub.
 //!
 //! Allows users to log in using their GitHub accounts.
-//! Supports restricting access to members of a specific GitHub Organization.
+//! Supports restricting access to members of a specific GitHub Organization,
+//! and granting scopes by organization or team with a [`ScopeMapper`].
 //!
 //! ## Usage
 //!
@@ -17,10 +18,27 @@
 //!     client_secret: "secret".to_string(),
 //!     redirect_uri: "http://localhost:3000/auth/callback".to_string(),
 //!     required_org: Some("MyGameStudio".to_string()),
+//!     scope_mapper: None,
 //! };
 //!
 //! let auth = GithubAuthProvider::new(Some(config));
 //! ```
+//!
+//! ## Scopes
+//!
+//! Users get `read` and `write` by default. With a `scope_mapper`, their organizations (`org`)
+//! and teams (`org/team`) are mapped to scopes instead:
+//!
+//! ```no_run
+//! # use aquila_auth_github::GithubConfig;
+//! # use aquila_core::prelude::*;
+//! # use std::sync::Arc;
+//! let scopes: ScopeTable = "MyGameStudio=read, MyGameStudio/artists=write".parse().unwrap();
+//! let config = GithubConfig {
+//!     scope_mapper: Some(Arc::new(scopes)),
+//!     ..Default::default()
+//! };
+//! ```
 
 use aquila_core::prelude::*;
 use reqwest::{Client, StatusCode};
@@ -35,6 +53,17 @@ struct GithubUser {
     login: String,
 }
 
+#[derive(Deserialize, Debug, Clone)]
+struct GithubOrg {
+    login: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct GithubTeam {
+    slug: String,
+    organization: GithubOrg,
+}
+
 struct CachedUser {
     user: User,
     expires_at: Instant,
@@ -46,6 +75,10 @@ pub struct GithubConfig {
     pub client_secret: String,
     pub redirect_uri: String,
     pub required_org: Option<String>,
+    /// If set, maps the organizations (`org`) and teams (`org/team`) of a user to scopes.
+    ///
+    /// Defaults to `None`, every user gets `read` and `write`.
+    pub scope_mapper: Option<Arc<dyn ScopeMapper>>,
 }
 
 #[derive(Clone)]
@@ -135,6 +168,49 @@ impl GithubAuthProvider {
             .map_err(|_| AuthError::Generic("Failed to parse GitHub response".into()))
     }
 
+    /// Fetches the organizations (`org`) and teams (`org/team`) of the user, see
+    /// [`GithubConfig::scope_mapper`].
+    async fn fetch_claims(&self, token: &str) -> Result<Vec<String>, AuthError> {
+        let orgs: Vec<GithubOrg> = self.fetch_list(token, "user/orgs").await?;
+        let teams: Vec<GithubTeam> = self.fetch_list(token, "user/teams").await?;
+
+        Ok(orgs
+            .into_iter()
+            .map(|org| org.login)
+            .chain(
+                teams
+                    .into_iter()
+                    .map(|team| format!("{}/{}", team.organization.login, team.slug)),
+            )
+            .collect())
+    }
+
+    /// Private helper to fetch the first 100 entries of a list endpoint, e.g. `user/orgs`.
+    async fn fetch_list<T: serde::de::DeserializeOwned>(
+        &self,
+        token: &str,
+        path: &str,
+    ) -> Result<Vec<T>, AuthError> {
+        let res = self
+            .client
+            .get(format!("https://api.github.com/{path}?per_page=100"))
+            .header("Authorization", format!("Bearer {}", token))
+            .send()
+            .await
+            .map_err(|e| AuthError::Generic(format!("GitHub API error: {}", e)))?;
+
+        if !res.status().is_success() {
+            return Err(AuthError::Generic(format!(
+                "GitHub returned {} for {path}",
+                res.status()
+            )));
+        }
+
+        res.json()
+            .await
+            .map_err(|_| AuthError::Generic(format!("Failed to parse GitHub {path} response")))
+    }
+
     async fn check_org_membership(
         &self,
         token: &str,
@@ -185,9 +261,14 @@ impl AuthProvider for GithubAuthProvider {
                 .await?;
         }
 
+        let scopes = match self.config.as_ref().and_then(|c| c.scope_mapper.as_ref()) {
+            Some(mapper) => mapper.map(&self.fetch_claims(token).await?),
+            None => vec!["read".to_string(), "write".to_string()],
+        };
+
         let user = User {
             id: gh_user.login,
-            scopes: vec!["read".to_string(), "write".to_string()],
+            scopes,
         };
 
         {
@@ -3,9 +3,9 @@
 //! [![Downloads](https://img.shields.io/crates/d/aquila_auth_mock.svg)](https://crates.io/crates/aquila_auth_mock)
 //! [![Docs](https://docs.rs/aquila_auth_mock/badge.svg)](https://docs.rs/aquila_auth_mock/)
 //!
-//! A dummy authentication provider for development and testing.
+//! Dummy authentication providers for development and testing.
 //!
-//! **WARNING**: This provider allows ANY token to pass as a valid user with full admin permissions.
+//! **WARNING**: [`AllowAllAuth`] allows ANY token to pass as a valid user with full admin permissions.
 //!
 //! **DO NOT use this in production!!!**
 //!
@@ -17,8 +17,20 @@
 //! let auth = AllowAllAuth;
 //! # }
 //! ```
+//!
+//! To test scope enforcement, [`MockAuth`] only accepts known tokens with fixed scopes:
+//!
+//! ```rust
+//! # use aquila_auth_mock::MockAuth;
+//! # fn main() {
+//! let auth = MockAuth::default()
+//!     .with_token("reader", ["read"])
+//!     .with_token("admin", ["admin"]);
+//! # }
+//! ```
 
 use aquila_core::prelude::*;
+use std::collections::HashMap;
 
 #[derive(Clone)]
 pub struct AllowAllAuth;
@@ -31,3 +43,53 @@ impl AuthProvider for AllowAllAuth {
         })
     }
 }
+
+/// Accepts a fixed set of tokens, each with its own scopes, e.g. to test scope enforcement.
+///
+/// The token doubles as the user id, unknown tokens are rejected.
+///
+/// ```rust
+/// # use aquila_auth_mock::MockAuth;
+/// # use aquila_core::prelude::*;
+/// # #[tokio::main]
+/// # async fn main() {
+/// let auth = MockAuth::default().with_token("reader", ["read"]);
+///
+/// let user = auth.verify("reader").await.unwrap();
+/// assert_eq!(user.scopes, vec!["read".to_string()]);
+///
+/// assert!(matches!(auth.verify("unknown").await, Err(AuthError::InvalidToken)));
+/// # }
+/// ```
+#[derive(Clone, Default)]
+pub struct MockAuth {
+    tokens: HashMap<String, Vec<String>>,
+}
+
+impl MockAuth {
+    /// Creates a provider from a map of token to scopes.
+    pub fn new(tokens: HashMap<String, Vec<String>>) -> Self {
+        Self { tokens }
+    }
+
+    /// Accepts `token` with the given scopes.
+    pub fn with_token(
+        mut self,
+        token: impl Into<String>,
+        scopes: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.tokens
+            .insert(token.into(), scopes.into_iter().map(Into::into).collect());
+        self
+    }
+}
+
+impl AuthProvider for MockAuth {
+    async fn verify(&self, token: &str) -> Result<User, AuthError> {
+        let scopes = self.tokens.get(token).ok_or(AuthError::InvalidToken)?;
+        Ok(User {
+            id: token.to_string(),
+            scopes: scopes.clone(),
+        })
+    }
+}
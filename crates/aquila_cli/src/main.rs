@@ -40,9 +40,14 @@
 //!     ```bash
 //!     aquila generate-secret
 //!     ```
+//! * **Generate a manifest signing key** (for server setup):
+//!     ```bash
+//!     aquila generate-signing-key
+//!     ```
 
 use aquila_client::AquilaClient;
 use aquila_core::manifest::{AssetInfo, AssetManifest};
+use aquila_core::signing::{generate_signing_key, verifying_key_from_hex};
 use chrono::Utc;
 use clap::{Parser, Subcommand};
 use rand::Rng;
@@ -107,11 +112,17 @@ enum Commands {
     /// Fetch and display a manifest for a specific version
     GetManifest {
         version: String,
+
+        /// Verify the manifest signature with this hex encoded verifying key
+        #[arg(long, env = "AQUILA_PUBLIC_KEY")]
+        public_key: Option<String>,
     },
     Login,
     GenerateSecret,
+    /// Generate an Ed25519 key pair for signing manifests
+    GenerateSigningKey,
     MintToken {
-        /// The subject name (e.g. "game_client_v1")
+        /// The subject name (e.g. "game_client_v1"), your own id on multi-tenant servers
         subject: String,
 
         /// Duration in seconds (default: 1 year)
@@ -142,6 +153,19 @@ async fn main() -> anyhow::Result<()> {
             println!("Copy this value and set it on your server:");
             println!("set AQUILA_JWT_SECRET=\"{}\"", secret);
         }
+        Commands::GenerateSigningKey => {
+            let key = generate_signing_key();
+
+            println!("🔑 Generated manifest signing key:");
+            println!("\n    {}\n", hex::encode(key.to_bytes()));
+            println!("Keep this value private and configure it on your server:");
+            println!("set AQUILA_SIGNING_KEY=\"{}\"", hex::encode(key.to_bytes()));
+            println!("\nClients verify manifests with the public key:");
+            println!(
+                "set AQUILA_PUBLIC_KEY=\"{}\"",
+                hex::encode(key.verifying_key().to_bytes())
+            );
+        }
         Commands::Login => {
             let login_url = format!("{}/auth/login", cli.url.trim_end_matches('/'));
             println!("🌐 To authenticate, please visit:");
@@ -217,6 +241,7 @@ async fn main() -> anyhow::Result<()> {
                 published_at: Utc::now(),
                 published_by: whoami::username()?,
                 assets,
+                ..Default::default()
             };
 
             let latest = !no_latest;
@@ -240,10 +265,21 @@ async fn main() -> anyhow::Result<()> {
 
             println!("✅ Saved to {output:?}");
         }
-        Commands::GetManifest { version } => {
+        Commands::GetManifest {
+            version,
+            public_key,
+        } => {
             println!("🔍 Fetching manifest for version '{}'...", version);
-            let manifest = client.fetch_manifest(&version).await?;
-            println!("{}", serde_json::to_string_pretty(&manifest)?);
+            let manifest = match public_key {
+                Some(key) => {
+                    let key = verifying_key_from_hex(&key)?;
+                    let manifest = client.fetch_manifest_verified(&version, &key).await?;
+                    println!("✅ Signature verified");
+                    manifest
+                }
+                None => client.fetch_manifest(&version).await?,
+            };
+            println!("{}", manifest.to_pretty_string()?);
         }
         Commands::MintToken {
             subject,
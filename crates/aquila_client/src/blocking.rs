@@ -0,0 +1,169 @@
+//! A blocking facade over the async [`AquilaClient`](crate::AquilaClient).
+//!
+//! Modeled on `reqwest::blocking`: every call is driven to completion on a dedicated
+//! current-thread Tokio runtime owned by the client.
+//!
+//! **NOTE:** The blocking client must not be constructed or used inside an async context
+//! (e.g. within `#[tokio::main]`), as blocking on a runtime from within another runtime panics.
+//! Use the async client there instead.
+//!
+//! ```no_run
+//! use aquila_client::blocking::AquilaClient;
+//! use std::path::Path;
+//!
+//! let client = AquilaClient::new("http://localhost:3000", Some("my-token".into()));
+//! let hash = client.upload_file(Path::new("test.png")).unwrap();
+//! ```
+
+use crate::Result;
+use aquila_core::manifest::{
+    AssetInfo, AssetManifest, ManifestStats, PruneReport, PublishReport, ServerCapabilities,
+    StorageStats,
+};
+use aquila_core::signing::VerifyingKey;
+use aquila_core::traits::BlobInfo;
+use futures::TryStreamExt;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::runtime;
+
+#[derive(Clone)]
+pub struct AquilaClient {
+    inner: crate::AquilaClient,
+    runtime: Arc<runtime::Runtime>,
+}
+
+impl AquilaClient {
+    pub fn new(base_url: impl Into<String>, token: Option<String>) -> Self {
+        Self::from_async(crate::AquilaClient::new(base_url, token))
+    }
+
+    /// Wraps an already configured async client.
+    pub fn from_async(inner: crate::AquilaClient) -> Self {
+        let runtime = runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("Failed to create Tokio runtime for blocking AquilaClient");
+
+        Self {
+            inner,
+            runtime: Arc::new(runtime),
+        }
+    }
+
+    pub fn fetch_manifest(&self, version: &str) -> Result<AssetManifest> {
+        self.runtime.block_on(self.inner.fetch_manifest(version))
+    }
+
+    pub fn fetch_latest_manifest(&self) -> Result<(AssetManifest, String)> {
+        self.runtime.block_on(self.inner.fetch_latest_manifest())
+    }
+
+    pub fn fetch_manifest_verified(
+        &self,
+        version: &str,
+        public_key: &VerifyingKey,
+    ) -> Result<AssetManifest> {
+        self.runtime
+            .block_on(self.inner.fetch_manifest_verified(version, public_key))
+    }
+
+    pub fn fetch_manifest_stats(&self, version: &str) -> Result<ManifestStats> {
+        self.runtime
+            .block_on(self.inner.fetch_manifest_stats(version))
+    }
+
+    pub fn capabilities(&self) -> Result<ServerCapabilities> {
+        self.runtime.block_on(self.inner.capabilities())
+    }
+
+    pub fn fetch_storage_stats(&self) -> Result<StorageStats> {
+        self.runtime.block_on(self.inner.fetch_storage_stats())
+    }
+
+    pub fn prune_manifests(
+        &self,
+        keep: usize,
+        keep_versions: &[&str],
+        dry_run: bool,
+    ) -> Result<PruneReport> {
+        self.runtime
+            .block_on(self.inner.prune_manifests(keep, keep_versions, dry_run))
+    }
+
+    /// Collects all manifest versions, see [`AquilaClient::list_manifests`](crate::AquilaClient::list_manifests).
+    pub fn list_manifests(&self) -> Result<Vec<String>> {
+        self.runtime
+            .block_on(self.inner.list_manifests().try_collect())
+    }
+
+    /// Collects all stored blobs, see [`AquilaClient::list_blobs`](crate::AquilaClient::list_blobs).
+    pub fn list_blobs(&self) -> Result<Vec<BlobInfo>> {
+        self.runtime.block_on(self.inner.list_blobs().try_collect())
+    }
+
+    pub fn mint_token(
+        &self,
+        subject: &str,
+        duration_seconds: Option<u64>,
+        scopes: Option<Vec<String>>,
+    ) -> Result<String> {
+        self.runtime
+            .block_on(self.inner.mint_token(subject, duration_seconds, scopes))
+    }
+
+    pub fn filter_existing(&self, hashes: &[String]) -> Result<Vec<String>> {
+        self.runtime.block_on(self.inner.filter_existing(hashes))
+    }
+
+    pub fn upload_file(&self, path: &Path) -> Result<String> {
+        self.runtime.block_on(self.inner.upload_file(path))
+    }
+
+    pub fn upload_stream(&self, path: &Path) -> Result<String> {
+        self.runtime.block_on(self.inner.upload_stream(path))
+    }
+
+    pub fn publish_manifest(&self, manifest: &AssetManifest, latest: bool) -> Result<()> {
+        self.runtime
+            .block_on(self.inner.publish_manifest(manifest, latest))
+    }
+
+    pub fn publish_manifest_from_path(&self, path: &Path, latest: bool) -> Result<()> {
+        self.runtime
+            .block_on(self.inner.publish_manifest_from_path(path, latest))
+    }
+
+    pub fn release(&self, manifest: &AssetManifest, files: &[PathBuf]) -> Result<()> {
+        self.runtime.block_on(self.inner.release(manifest, files))
+    }
+
+    pub fn validate_manifest(&self, manifest: &AssetManifest) -> Result<PublishReport> {
+        self.runtime
+            .block_on(self.inner.validate_manifest(manifest))
+    }
+
+    pub fn patch_manifest(
+        &self,
+        version: &str,
+        added: HashMap<String, AssetInfo>,
+        removed: Vec<String>,
+    ) -> Result<AssetManifest> {
+        self.runtime
+            .block_on(self.inner.patch_manifest(version, added, removed))
+    }
+
+    pub fn unpublish(&self, version: &str) -> Result<()> {
+        self.runtime.block_on(self.inner.unpublish(version))
+    }
+
+    pub fn download_file(&self, hash: &str) -> Result<Vec<u8>> {
+        self.runtime.block_on(self.inner.download_file(hash))
+    }
+
+    pub fn download_archive(&self, version: &str, dest: &Path) -> Result<()> {
+        self.runtime
+            .block_on(self.inner.download_archive(version, dest))
+    }
+}
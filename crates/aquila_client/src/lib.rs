@@ -9,6 +9,8 @@
 //! publish manifests, and mint authentication tokens, as well as to fetch manifests
 //! for specific versions.
 //!
+//! A synchronous facade is available in the `blocking` module behind the `blocking` feature.
+//!
 //! ## Example: Publishing a Manifest
 //!
 //! ```no_run
@@ -37,19 +39,34 @@
 //!         assets,
 //!         ..Default::default()
 //!     };
-//!     client.publish_manifest(&manifest).await?;
+//!     client.publish_manifest(&manifest, true).await?;
 //!     Ok(())
 //! }
 //! ```
 
-use aquila_core::manifest::AssetManifest;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+
+use aquila_core::error::ManifestError;
+use aquila_core::format::ManifestFormat;
+use aquila_core::manifest::{
+    AssetInfo, AssetManifest, ManifestPatch, ManifestStats, PruneReport, PublishReport,
+    ServerCapabilities, StorageStats,
+};
+use aquila_core::signing::VerifyingKey;
+use aquila_core::traits::{BlobInfo, Page};
+use futures::{Stream, TryStreamExt, stream};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, USER_AGENT};
 use reqwest::{Client, StatusCode};
 use sha2::{Digest, Sha256};
-use std::path::Path;
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
 
 use tokio::fs::File;
-use tokio::io::AsyncReadExt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tokio_util::io::ReaderStream;
@@ -67,6 +84,12 @@ pub enum AquilaClientError {
 
     #[error("Validation error: {0}")]
     Validation(String),
+
+    #[error("Configuration error: {0}")]
+    Config(String),
+
+    #[error("Manifest verification failed: {0}")]
+    Verification(ManifestError),
 }
 
 pub type Result<T> = std::result::Result<T, AquilaClientError>;
@@ -76,6 +99,50 @@ pub struct AquilaClient {
     base_url: String,
     client: Client,
     token: Option<String>,
+    default_headers: HeaderMap,
+}
+
+/// The default `User-Agent` sent by the client.
+pub const DEFAULT_USER_AGENT: &str = concat!("aquila-client/", env!("CARGO_PKG_VERSION"));
+
+#[derive(Deserialize, Default)]
+struct CredentialsProfile {
+    url: Option<String>,
+    token: Option<String>,
+}
+
+fn credentials_path() -> Option<PathBuf> {
+    env::var_os("AQUILA_CREDENTIALS")
+        .map(PathBuf::from)
+        .or_else(|| env::home_dir().map(|home| home.join(".aquila").join("credentials")))
+}
+
+/// Loads a profile from the credentials file.
+///
+/// A missing file is only an error if a profile was explicitly requested.
+fn load_credentials_profile(name: Option<&str>) -> Result<Option<CredentialsProfile>> {
+    let path = credentials_path();
+    let content = match path.as_ref().map(std::fs::read_to_string) {
+        Some(Ok(content)) => content,
+        Some(Err(e)) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ if name.is_some() => {
+            return Err(AquilaClientError::Config(format!(
+                "AQUILA_PROFILE is set but no credentials file was found at {path:?}"
+            )));
+        }
+        _ => return Ok(None),
+    };
+
+    let mut profiles: HashMap<String, CredentialsProfile> = toml::from_str(&content)
+        .map_err(|e| AquilaClientError::Config(format!("Invalid credentials file: {e}")))?;
+
+    let name = name.unwrap_or("default");
+    match profiles.remove(name) {
+        Some(profile) => Ok(Some(profile)),
+        None => Err(AquilaClientError::Config(format!(
+            "Profile '{name}' not found in credentials file"
+        ))),
+    }
 }
 
 #[derive(Serialize)]
@@ -94,14 +161,68 @@ struct CreateTokenResponse {
 
 impl AquilaClient {
     pub fn new(base_url: impl Into<String>, token: Option<String>) -> Self {
+        let mut default_headers = HeaderMap::new();
+        default_headers.insert(USER_AGENT, HeaderValue::from_static(DEFAULT_USER_AGENT));
+
         Self {
             base_url: base_url.into(),
             client: Client::new(),
             token,
+            default_headers,
         }
     }
 
+    /// Creates a client configured from the environment.
+    ///
+    /// - `AQUILA_URL` and `AQUILA_TOKEN` are used if set.
+    /// - Otherwise (or if `AQUILA_PROFILE` is set), the profile is read from the TOML file at
+    ///   `~/.aquila/credentials` (or `AQUILA_CREDENTIALS`). The profile defaults to `default`.
+    ///
+    /// Environment variables take precedence over values from the credentials file.
+    ///
+    /// ```toml
+    /// [default]
+    /// url = "http://localhost:3000"
+    /// token = "..."
+    ///
+    /// [production]
+    /// url = "https://assets.example.com"
+    /// token = "..."
+    /// ```
+    pub fn from_env() -> Result<Self> {
+        let env_url = env::var("AQUILA_URL").ok();
+        let env_token = env::var("AQUILA_TOKEN").ok();
+        let profile_name = env::var("AQUILA_PROFILE").ok();
+
+        let profile = if env_url.is_none() || profile_name.is_some() {
+            load_credentials_profile(profile_name.as_deref())?
+        } else {
+            None
+        }
+        .unwrap_or_default();
+
+        let url = env_url.or(profile.url).ok_or_else(|| {
+            AquilaClientError::Config(
+                "AQUILA_URL is not set and no credentials profile provides a url".into(),
+            )
+        })?;
+
+        Ok(Self::new(url, env_token.or(profile.token)))
+    }
+
+    /// Adds a header that is sent with every request, e.g. a proxy token.
+    pub fn with_default_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.default_headers.insert(name, value);
+        self
+    }
+
+    /// Overrides the `User-Agent`, defaults to [`DEFAULT_USER_AGENT`].
+    pub fn with_user_agent(self, user_agent: HeaderValue) -> Self {
+        self.with_default_header(USER_AGENT, user_agent)
+    }
+
     fn auth_request(&self, builder: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        let builder = builder.headers(self.default_headers.clone());
         if let Some(token) = &self.token {
             builder.header("Authorization", format!("Bearer {token}"))
         } else {
@@ -110,6 +231,14 @@ impl AquilaClient {
     }
 
     pub async fn fetch_manifest(&self, version: &str) -> Result<AssetManifest> {
+        let value = self.fetch_manifest_json(version).await?;
+
+        AssetManifest::migrate(value)
+            .map_err(|e| AquilaClientError::Validation(format!("Failed to parse manifest: {e}")))
+    }
+
+    /// Private helper to fetch a manifest as received, e.g. to verify it before parsing.
+    async fn fetch_manifest_json(&self, version: &str) -> Result<serde_json::Value> {
         let url = format!("{}/manifest/{version}", self.base_url);
         let response = self.auth_request(self.client.get(&url)).send().await?;
 
@@ -119,12 +248,194 @@ impl AquilaClient {
             return Err(AquilaClientError::ServerError(status, text));
         }
 
-        let manifest: AssetManifest = response
+        response
+            .json()
+            .await
+            .map_err(|e| AquilaClientError::Validation(format!("Failed to parse manifest: {e}")))
+    }
+
+    /// Fetches the `latest` manifest and the version it resolved to, e.g. to record which
+    /// version a client is on.
+    ///
+    /// The version is read from the `X-Aquila-Resolved-Version` header, or the manifest itself
+    /// for servers without it.
+    pub async fn fetch_latest_manifest(&self) -> Result<(AssetManifest, String)> {
+        let url = format!("{}/manifest/latest", self.base_url);
+        let response = self.auth_request(self.client.get(&url)).send().await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(AquilaClientError::ServerError(status, text));
+        }
+
+        let resolved = response
+            .headers()
+            .get("X-Aquila-Resolved-Version")
+            .and_then(|val| val.to_str().ok())
+            .map(String::from);
+
+        let value: serde_json::Value = response
             .json()
             .await
             .map_err(|e| AquilaClientError::Validation(format!("Failed to parse manifest: {e}")))?;
+        let manifest = AssetManifest::migrate(value)
+            .map_err(|e| AquilaClientError::Validation(format!("Failed to parse manifest: {e}")))?;
+
+        let version = resolved.unwrap_or_else(|| manifest.version.clone());
+        Ok((manifest, version))
+    }
+
+    /// Fetches a manifest and verifies its signature with the server's verifying key.
+    ///
+    /// Fails with [`AquilaClientError::Verification`] if the manifest is unsigned or was tampered with.
+    pub async fn fetch_manifest_verified(
+        &self,
+        version: &str,
+        public_key: &VerifyingKey,
+    ) -> Result<AssetManifest> {
+        // Verified before parsing, so fields unknown to this version are covered too.
+        let value = self.fetch_manifest_json(version).await?;
+        AssetManifest::verify_json(&value, public_key).map_err(AquilaClientError::Verification)?;
+
+        AssetManifest::migrate(value)
+            .map_err(|e| AquilaClientError::Validation(format!("Failed to parse manifest: {e}")))
+    }
+
+    /// Fetches aggregated stats (asset count, total size, ...) for a manifest version.
+    pub async fn fetch_manifest_stats(&self, version: &str) -> Result<ManifestStats> {
+        let url = format!("{}/manifest/{version}/stats", self.base_url);
+        let response = self.auth_request(self.client.get(&url)).send().await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(AquilaClientError::ServerError(status, text));
+        }
+
+        response
+            .json()
+            .await
+            .map_err(|e| AquilaClientError::Validation(format!("Failed to parse stats: {e}")))
+    }
+
+    /// Fetches the [`ServerCapabilities`] of the deployment, e.g. to check for login support.
+    pub async fn capabilities(&self) -> Result<ServerCapabilities> {
+        let url = format!("{}/capabilities", self.base_url);
+        let response = self.auth_request(self.client.get(&url)).send().await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(AquilaClientError::ServerError(status, text));
+        }
+
+        response.json().await.map_err(|e| {
+            AquilaClientError::Validation(format!("Failed to parse capabilities: {e}"))
+        })
+    }
+
+    /// Fetches content store wide [`StorageStats`], requires the `admin` scope.
+    pub async fn fetch_storage_stats(&self) -> Result<StorageStats> {
+        let url = format!("{}/admin/stats", self.base_url);
+        let response = self.auth_request(self.client.get(&url)).send().await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(AquilaClientError::ServerError(status, text));
+        }
+
+        response
+            .json()
+            .await
+            .map_err(|e| AquilaClientError::Validation(format!("Failed to parse stats: {e}")))
+    }
+
+    /// Unpublishes all but the `keep` newest manifest versions, never `latest` or one of
+    /// `keep_versions`, e.g. tagged releases. Requires the `admin` scope.
+    pub async fn prune_manifests(
+        &self,
+        keep: usize,
+        keep_versions: &[&str],
+        dry_run: bool,
+    ) -> Result<PruneReport> {
+        let url = format!("{}/admin/prune-manifests", self.base_url);
+        let response = self
+            .auth_request(self.client.post(&url))
+            .query(&[
+                ("keep", keep.to_string()),
+                ("keep_versions", keep_versions.join(",")),
+                ("dry_run", dry_run.to_string()),
+            ])
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(AquilaClientError::ServerError(status, text));
+        }
+
+        response
+            .json()
+            .await
+            .map_err(|e| AquilaClientError::Validation(format!("Failed to parse report: {e}")))
+    }
+
+    /// Lists all manifest versions, fetching further pages as the stream is consumed.
+    pub fn list_manifests(&self) -> impl Stream<Item = Result<String>> + '_ {
+        self.paginate("manifests")
+    }
+
+    /// Lists all stored blobs, fetching further pages as the stream is consumed.
+    /// Requires the `admin` scope.
+    pub fn list_blobs(&self) -> impl Stream<Item = Result<BlobInfo>> + '_ {
+        self.paginate("admin/blobs")
+    }
+
+    /// Follows the `next_cursor` of a paginated listing until the last page.
+    fn paginate<T: DeserializeOwned>(
+        &self,
+        path: &'static str,
+    ) -> impl Stream<Item = Result<T>> + '_ {
+        stream::try_unfold(
+            Some(None),
+            move |cursor: Option<Option<String>>| async move {
+                let Some(cursor) = cursor else {
+                    return Ok::<_, AquilaClientError>(None);
+                };
+
+                let page: Page<T> = self.fetch_page(path, cursor.as_deref()).await?;
+                let items = stream::iter(page.items.into_iter().map(Ok));
+                Ok(Some((items, page.next_cursor.map(Some))))
+            },
+        )
+        .try_flatten()
+    }
+
+    async fn fetch_page<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        cursor: Option<&str>,
+    ) -> Result<Page<T>> {
+        let url = format!("{}/{path}", self.base_url);
+        let mut request = self.client.get(&url);
+        if let Some(cursor) = cursor {
+            request = request.query(&[("cursor", cursor)]);
+        }
+        let response = self.auth_request(request).send().await?;
 
-        Ok(manifest)
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(AquilaClientError::ServerError(status, text));
+        }
+
+        response
+            .json()
+            .await
+            .map_err(|e| AquilaClientError::Validation(format!("Failed to parse page: {e}")))
     }
 
     pub async fn mint_token(
@@ -161,6 +472,34 @@ impl AquilaClient {
         Ok(data.token)
     }
 
+    /// Returns the subset of `hashes` the server already stores, so their upload can be skipped.
+    pub async fn filter_existing(&self, hashes: &[String]) -> Result<Vec<String>> {
+        let url = format!("{}/assets/exists", self.base_url);
+
+        let mut existing = Vec::new();
+        // The server accepts at most 1000 hashes per request.
+        for chunk in hashes.chunks(1000) {
+            let response = self
+                .auth_request(self.client.post(&url))
+                .json(chunk)
+                .send()
+                .await?;
+
+            if !response.status().is_success() {
+                let status = response.status();
+                let text = response.text().await.unwrap_or_default();
+                return Err(AquilaClientError::ServerError(status, text));
+            }
+
+            let found: Vec<String> = response.json().await.map_err(|e| {
+                AquilaClientError::Validation(format!("Failed to parse response: {e}"))
+            })?;
+            existing.extend(found);
+        }
+
+        Ok(existing)
+    }
+
     pub async fn upload_file(&self, path: &Path) -> Result<String> {
         let mut file = File::open(path).await?;
         let mut buffer = Vec::new();
@@ -173,6 +512,7 @@ impl AquilaClient {
         let url = format!("{}/assets", self.base_url);
         let response = self
             .auth_request(self.client.post(&url))
+            .header("X-Content-SHA256", &local_hash)
             .body(buffer)
             .send()
             .await?;
@@ -193,20 +533,7 @@ impl AquilaClient {
 
     /// Streams a file. Required for very large files.
     pub async fn upload_stream(&self, path: &Path) -> Result<String> {
-        let mut file = File::open(path).await?;
-        let mut hasher = Sha256::new();
-        // 64KB chunk buffer
-        let mut buffer = [0u8; 64 * 1024];
-
-        loop {
-            let n = file.read(&mut buffer).await?;
-            if n == 0 {
-                break;
-            }
-            hasher.update(&buffer[..n]);
-        }
-
-        let local_hash = hex::encode(hasher.finalize());
+        let local_hash = hash_file(path).await?;
         let file = File::open(path).await?;
         let size = file.metadata().await?.len();
         let body = reqwest::Body::wrap_stream(ReaderStream::new(file));
@@ -215,6 +542,7 @@ impl AquilaClient {
         let response = self
             .auth_request(self.client.put(&url))
             .header("Content-Length", size)
+            .header("X-Content-SHA256", &local_hash)
             .body(body)
             .send()
             .await?;
@@ -246,6 +574,125 @@ impl AquilaClient {
         Ok(())
     }
 
+    /// Publishes a manifest file, in the [`ManifestFormat`] of its extension, e.g. `release.yaml`.
+    ///
+    /// The file is parsed locally and sent as JSON, so the server doesn't need the format.
+    /// YAML and TOML files require the `yaml` and `toml` features.
+    pub async fn publish_manifest_from_path(&self, path: &Path, latest: bool) -> Result<()> {
+        let format = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(ManifestFormat::from_extension)
+            .ok_or_else(|| {
+                AquilaClientError::Validation(format!(
+                    "Unsupported manifest format: {}",
+                    path.display()
+                ))
+            })?;
+
+        let data = tokio::fs::read(path).await?;
+        let manifest = AssetManifest::from_slice(format, &data)
+            .map_err(|e| AquilaClientError::Validation(format!("{}: {e}", path.display())))?;
+        self.publish_manifest(&manifest, latest).await
+    }
+
+    /// Uploads the missing blobs among `files`, then publishes `manifest` and moves `latest`.
+    ///
+    /// The publish is all or nothing: if a blob referenced by the manifest is still missing,
+    /// e.g. because its file wasn't passed, the server rejects it without writing anything.
+    /// Blobs uploaded before are kept, they are content addressed and harmless until referenced.
+    pub async fn release(&self, manifest: &AssetManifest, files: &[PathBuf]) -> Result<()> {
+        let mut hashes = Vec::with_capacity(files.len());
+        for path in files {
+            hashes.push(hash_file(path).await?);
+        }
+        let existing = self.filter_existing(&hashes).await?;
+
+        for (path, hash) in files.iter().zip(&hashes) {
+            if !existing.contains(hash) {
+                self.upload_stream(path).await?;
+            }
+        }
+
+        let url = format!("{}/manifest", self.base_url);
+        let response = self
+            .auth_request(self.client.post(&url))
+            .query(&[("latest", true), ("require_blobs", true)])
+            .json(manifest)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(AquilaClientError::ServerError(status, text));
+        }
+
+        Ok(())
+    }
+
+    /// Validates a manifest without publishing it, reporting e.g. hashes missing on the server.
+    pub async fn validate_manifest(&self, manifest: &AssetManifest) -> Result<PublishReport> {
+        let url = format!("{}/manifest", self.base_url);
+        let response = self
+            .auth_request(self.client.post(&url))
+            .query(&[("dry_run", true)])
+            .json(manifest)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(AquilaClientError::ServerError(status, text));
+        }
+
+        response
+            .json()
+            .await
+            .map_err(|e| AquilaClientError::Validation(format!("Failed to parse report: {e}")))
+    }
+
+    /// Adds/replaces and removes assets of an already published manifest.
+    pub async fn patch_manifest(
+        &self,
+        version: &str,
+        added: HashMap<String, AssetInfo>,
+        removed: Vec<String>,
+    ) -> Result<AssetManifest> {
+        let url = format!("{}/manifest/{version}", self.base_url);
+        let response = self
+            .auth_request(self.client.patch(&url))
+            .json(&ManifestPatch { added, removed })
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(AquilaClientError::ServerError(status, text));
+        }
+
+        response
+            .json()
+            .await
+            .map_err(|e| AquilaClientError::Validation(format!("Failed to parse manifest: {e}")))
+    }
+
+    /// Unpublishes a manifest version, the server repoints `latest` if it pointed to it.
+    pub async fn unpublish(&self, version: &str) -> Result<()> {
+        let url = format!("{}/manifest/{version}", self.base_url);
+        let response = self.auth_request(self.client.delete(&url)).send().await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(AquilaClientError::ServerError(status, text));
+        }
+
+        Ok(())
+    }
+
     pub async fn download_file(&self, hash: &str) -> Result<Vec<u8>> {
         let url = format!("{}/assets/{hash}", self.base_url);
         let response = self.auth_request(self.client.get(&url)).send().await?;
@@ -259,4 +706,47 @@ impl AquilaClient {
         let bytes = response.bytes().await?;
         Ok(bytes.to_vec())
     }
+
+    /// Downloads all assets of a version as a single tar archive and writes it to `dest`.
+    ///
+    /// The archive is streamed to disk, entries are named by their manifest paths.
+    pub async fn download_archive(&self, version: &str, dest: &Path) -> Result<()> {
+        let url = format!("{}/manifest/{version}/archive", self.base_url);
+        let mut response = self
+            .auth_request(self.client.get(&url).query(&[("format", "tar")]))
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(AquilaClientError::ServerError(
+                response.status(),
+                "Archive download failed".to_string(),
+            ));
+        }
+
+        let mut file = File::create(dest).await?;
+        while let Some(chunk) = response.chunk().await? {
+            file.write_all(&chunk).await?;
+        }
+        file.flush().await?;
+
+        Ok(())
+    }
+}
+
+/// Hashes a file in chunks, without reading it into memory.
+async fn hash_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path).await?;
+    let mut hasher = Sha256::new();
+    // 64KB chunk buffer
+    let mut buffer = [0u8; 64 * 1024];
+
+    loop {
+        let n = file.read(&mut buffer).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+
+    Ok(hex::encode(hasher.finalize()))
 }
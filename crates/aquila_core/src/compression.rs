@@ -0,0 +1,51 @@
+//! Transparent gzip compression of stored manifests.
+//!
+//! Storage backends compress manifests on write if enabled, and decompress them on read.
+//! Compressed manifests start with [`MARKER`], so manifests written before enabling
+//! compression are still read as is. Without compression enabled, nothing is decompressed,
+//! so e.g. encrypted manifests are never mistaken for compressed ones.
+
+use crate::error::StorageError;
+use bytes::Bytes;
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use std::io::{Read, Write};
+
+/// Prefix of compressed manifests, followed by the gzip stream.
+///
+/// JSON never starts with it, and random data, e.g. the nonce of an encrypted manifest, only
+/// with negligible probability.
+pub const MARKER: &[u8] = b"aquila-gzip\n";
+
+/// Gzips a manifest for storage, prefixed with [`MARKER`].
+pub fn compress_manifest(data: &[u8]) -> Result<Bytes, StorageError> {
+    let mut encoder = GzEncoder::new(MARKER.to_vec(), Compression::default());
+    encoder.write_all(data)?;
+    Ok(Bytes::from(encoder.finish()?))
+}
+
+/// Decompresses a stored manifest, returns it as is if it doesn't start with [`MARKER`].
+///
+/// ```
+/// # use aquila_core::compression::*;
+/// let json = br#"{"version":"v1"}"#;
+/// let compressed = compress_manifest(json).unwrap();
+/// assert!(compressed.starts_with(MARKER));
+///
+/// assert_eq!(decompress_manifest(compressed).unwrap(), &json[..]);
+/// assert_eq!(decompress_manifest(json[..].into()).unwrap(), &json[..]);
+///
+/// // Data that merely looks like gzip is left alone.
+/// let gzip_like = &[0x1f, 0x8b, 0x08, 0x00][..];
+/// assert_eq!(decompress_manifest(gzip_like.into()).unwrap(), gzip_like);
+/// ```
+pub fn decompress_manifest(data: Bytes) -> Result<Bytes, StorageError> {
+    let Some(compressed) = data.strip_prefix(MARKER) else {
+        return Ok(data);
+    };
+
+    let mut decompressed = Vec::new();
+    GzDecoder::new(compressed).read_to_end(&mut decompressed)?;
+    Ok(Bytes::from(decompressed))
+}
@@ -0,0 +1,393 @@
+//! Object safe versions of [`StorageBackend`] and [`AuthProvider`], to pick backends at runtime.
+//!
+//! The traits return `impl Future`, so they can't be used as trait objects and every
+//! combination of backends is a separate type. [`BoxedStorage`] and [`BoxedAuthProvider`]
+//! erase the concrete type behind an `Arc<dyn ...>` and implement the traits again, so they can
+//! be passed to the server like any other backend, at the cost of one allocation per call.
+//!
+//! ```
+//! use aquila_core::dynamic::BoxedStorage;
+//! use aquila_core::traits::StorageBackend;
+//!
+//! fn select(kind: &str, fs: impl StorageBackend, s3: impl StorageBackend) -> BoxedStorage {
+//!     match kind {
+//!         "s3" => BoxedStorage::new(s3),
+//!         _ => BoxedStorage::new(fs),
+//!     }
+//! }
+//! ```
+
+use crate::error::*;
+use crate::traits::*;
+use bytes::Bytes;
+use futures::Stream;
+use futures::future::BoxFuture;
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// A boxed upload stream, see [`StorageBackend::write_stream`].
+pub type ByteStream = Pin<Box<dyn Stream<Item = Result<Bytes, std::io::Error>> + Send>>;
+
+/// Object safe version of [`StorageBackend`], implemented for every backend.
+///
+/// Use it through [`BoxedStorage`].
+pub trait DynStorageBackend: Send + Sync + 'static {
+    fn write_blob<'a>(
+        &'a self,
+        hash: &'a str,
+        data: Bytes,
+    ) -> BoxFuture<'a, Result<bool, StorageError>>;
+
+    fn write_stream<'a>(
+        &'a self,
+        hash: &'a str,
+        stream: ByteStream,
+        content_length: Option<u64>,
+    ) -> BoxFuture<'a, Result<bool, StorageError>>;
+
+    fn put_object<'a>(
+        &'a self,
+        path: &'a str,
+        data: Bytes,
+    ) -> BoxFuture<'a, Result<(), StorageError>>;
+
+    fn write_manifest<'a>(
+        &'a self,
+        version: &'a str,
+        data: Bytes,
+    ) -> BoxFuture<'a, Result<(), StorageError>>;
+
+    fn read_file<'a>(&'a self, path: &'a str) -> BoxFuture<'a, Result<Bytes, StorageError>>;
+
+    fn exists<'a>(&'a self, path: &'a str) -> BoxFuture<'a, Result<bool, StorageError>>;
+
+    fn exists_many<'a>(
+        &'a self,
+        paths: &'a [String],
+    ) -> BoxFuture<'a, Result<Vec<bool>, StorageError>>;
+
+    fn stat<'a>(&'a self, path: &'a str) -> BoxFuture<'a, Result<ObjectMeta, StorageError>>;
+
+    fn capabilities(&self) -> StorageCapabilities;
+
+    fn get_manifest_path(&self, version: &str) -> String;
+
+    fn get_download_url<'a>(
+        &'a self,
+        path: &'a str,
+    ) -> BoxFuture<'a, Result<Option<String>, StorageError>>;
+
+    fn get_download_url_as<'a>(
+        &'a self,
+        path: &'a str,
+        filename: &'a str,
+    ) -> BoxFuture<'a, Result<Option<String>, StorageError>>;
+
+    fn get_download_url_unchecked<'a>(
+        &'a self,
+        path: &'a str,
+        filename: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<Option<String>, StorageError>>;
+
+    fn list_blobs(&self) -> BoxFuture<'_, Result<Vec<BlobInfo>, StorageError>>;
+
+    fn list_manifests(&self) -> BoxFuture<'_, Result<Vec<String>, StorageError>>;
+
+    fn list_blobs_page<'a>(
+        &'a self,
+        cursor: Option<&'a str>,
+        limit: usize,
+    ) -> BoxFuture<'a, Result<Page<BlobInfo>, StorageError>>;
+
+    fn list_manifests_page<'a>(
+        &'a self,
+        cursor: Option<&'a str>,
+        limit: usize,
+    ) -> BoxFuture<'a, Result<Page<String>, StorageError>>;
+
+    fn delete_file<'a>(&'a self, path: &'a str) -> BoxFuture<'a, Result<(), StorageError>>;
+
+    fn with_context(&self, ctx: &RequestContext) -> Arc<dyn DynStorageBackend>;
+}
+
+impl<S: StorageBackend> DynStorageBackend for S {
+    fn write_blob<'a>(
+        &'a self,
+        hash: &'a str,
+        data: Bytes,
+    ) -> BoxFuture<'a, Result<bool, StorageError>> {
+        Box::pin(StorageBackend::write_blob(self, hash, data))
+    }
+
+    fn write_stream<'a>(
+        &'a self,
+        hash: &'a str,
+        stream: ByteStream,
+        content_length: Option<u64>,
+    ) -> BoxFuture<'a, Result<bool, StorageError>> {
+        Box::pin(StorageBackend::write_stream(
+            self,
+            hash,
+            stream,
+            content_length,
+        ))
+    }
+
+    fn put_object<'a>(
+        &'a self,
+        path: &'a str,
+        data: Bytes,
+    ) -> BoxFuture<'a, Result<(), StorageError>> {
+        Box::pin(StorageBackend::put_object(self, path, data))
+    }
+
+    fn write_manifest<'a>(
+        &'a self,
+        version: &'a str,
+        data: Bytes,
+    ) -> BoxFuture<'a, Result<(), StorageError>> {
+        Box::pin(StorageBackend::write_manifest(self, version, data))
+    }
+
+    fn read_file<'a>(&'a self, path: &'a str) -> BoxFuture<'a, Result<Bytes, StorageError>> {
+        Box::pin(StorageBackend::read_file(self, path))
+    }
+
+    fn exists<'a>(&'a self, path: &'a str) -> BoxFuture<'a, Result<bool, StorageError>> {
+        Box::pin(StorageBackend::exists(self, path))
+    }
+
+    fn exists_many<'a>(
+        &'a self,
+        paths: &'a [String],
+    ) -> BoxFuture<'a, Result<Vec<bool>, StorageError>> {
+        Box::pin(StorageBackend::exists_many(self, paths))
+    }
+
+    fn stat<'a>(&'a self, path: &'a str) -> BoxFuture<'a, Result<ObjectMeta, StorageError>> {
+        Box::pin(StorageBackend::stat(self, path))
+    }
+
+    fn capabilities(&self) -> StorageCapabilities {
+        StorageBackend::capabilities(self)
+    }
+
+    fn get_manifest_path(&self, version: &str) -> String {
+        StorageBackend::get_manifest_path(self, version)
+    }
+
+    fn get_download_url<'a>(
+        &'a self,
+        path: &'a str,
+    ) -> BoxFuture<'a, Result<Option<String>, StorageError>> {
+        Box::pin(StorageBackend::get_download_url(self, path))
+    }
+
+    fn get_download_url_as<'a>(
+        &'a self,
+        path: &'a str,
+        filename: &'a str,
+    ) -> BoxFuture<'a, Result<Option<String>, StorageError>> {
+        Box::pin(StorageBackend::get_download_url_as(self, path, filename))
+    }
+
+    fn get_download_url_unchecked<'a>(
+        &'a self,
+        path: &'a str,
+        filename: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<Option<String>, StorageError>> {
+        Box::pin(StorageBackend::get_download_url_unchecked(
+            self, path, filename,
+        ))
+    }
+
+    fn list_blobs(&self) -> BoxFuture<'_, Result<Vec<BlobInfo>, StorageError>> {
+        Box::pin(StorageBackend::list_blobs(self))
+    }
+
+    fn list_manifests(&self) -> BoxFuture<'_, Result<Vec<String>, StorageError>> {
+        Box::pin(StorageBackend::list_manifests(self))
+    }
+
+    fn list_blobs_page<'a>(
+        &'a self,
+        cursor: Option<&'a str>,
+        limit: usize,
+    ) -> BoxFuture<'a, Result<Page<BlobInfo>, StorageError>> {
+        Box::pin(StorageBackend::list_blobs_page(self, cursor, limit))
+    }
+
+    fn list_manifests_page<'a>(
+        &'a self,
+        cursor: Option<&'a str>,
+        limit: usize,
+    ) -> BoxFuture<'a, Result<Page<String>, StorageError>> {
+        Box::pin(StorageBackend::list_manifests_page(self, cursor, limit))
+    }
+
+    fn delete_file<'a>(&'a self, path: &'a str) -> BoxFuture<'a, Result<(), StorageError>> {
+        Box::pin(StorageBackend::delete_file(self, path))
+    }
+
+    fn with_context(&self, ctx: &RequestContext) -> Arc<dyn DynStorageBackend> {
+        Arc::new(StorageBackend::with_context(self, ctx))
+    }
+}
+
+/// A [`StorageBackend`] whose concrete type is picked at runtime.
+#[derive(Clone)]
+pub struct BoxedStorage(Arc<dyn DynStorageBackend>);
+
+impl BoxedStorage {
+    pub fn new(storage: impl StorageBackend) -> Self {
+        Self(Arc::new(storage))
+    }
+}
+
+impl StorageBackend for BoxedStorage {
+    async fn write_blob(&self, hash: &str, data: Bytes) -> Result<bool, StorageError> {
+        self.0.write_blob(hash, data).await
+    }
+
+    async fn write_stream(
+        &self,
+        hash: &str,
+        stream: ByteStream,
+        content_length: Option<u64>,
+    ) -> Result<bool, StorageError> {
+        self.0.write_stream(hash, stream, content_length).await
+    }
+
+    async fn put_object(&self, path: &str, data: Bytes) -> Result<(), StorageError> {
+        self.0.put_object(path, data).await
+    }
+
+    async fn write_manifest(&self, version: &str, data: Bytes) -> Result<(), StorageError> {
+        self.0.write_manifest(version, data).await
+    }
+
+    async fn read_file(&self, path: &str) -> Result<Bytes, StorageError> {
+        self.0.read_file(path).await
+    }
+
+    async fn exists(&self, path: &str) -> Result<bool, StorageError> {
+        self.0.exists(path).await
+    }
+
+    async fn exists_many(&self, paths: &[String]) -> Result<Vec<bool>, StorageError> {
+        self.0.exists_many(paths).await
+    }
+
+    async fn stat(&self, path: &str) -> Result<ObjectMeta, StorageError> {
+        self.0.stat(path).await
+    }
+
+    fn capabilities(&self) -> StorageCapabilities {
+        self.0.capabilities()
+    }
+
+    fn get_manifest_path(&self, version: &str) -> String {
+        self.0.get_manifest_path(version)
+    }
+
+    async fn get_download_url(&self, path: &str) -> Result<Option<String>, StorageError> {
+        self.0.get_download_url(path).await
+    }
+
+    async fn get_download_url_as(
+        &self,
+        path: &str,
+        filename: &str,
+    ) -> Result<Option<String>, StorageError> {
+        self.0.get_download_url_as(path, filename).await
+    }
+
+    async fn get_download_url_unchecked(
+        &self,
+        path: &str,
+        filename: Option<&str>,
+    ) -> Result<Option<String>, StorageError> {
+        self.0.get_download_url_unchecked(path, filename).await
+    }
+
+    async fn list_blobs(&self) -> Result<Vec<BlobInfo>, StorageError> {
+        self.0.list_blobs().await
+    }
+
+    async fn list_manifests(&self) -> Result<Vec<String>, StorageError> {
+        self.0.list_manifests().await
+    }
+
+    async fn list_blobs_page(
+        &self,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<Page<BlobInfo>, StorageError> {
+        self.0.list_blobs_page(cursor, limit).await
+    }
+
+    async fn list_manifests_page(
+        &self,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<Page<String>, StorageError> {
+        self.0.list_manifests_page(cursor, limit).await
+    }
+
+    async fn delete_file(&self, path: &str) -> Result<(), StorageError> {
+        self.0.delete_file(path).await
+    }
+
+    fn with_context(&self, ctx: &RequestContext) -> Self {
+        Self(self.0.with_context(ctx))
+    }
+}
+
+/// Object safe version of [`AuthProvider`], implemented for every provider.
+///
+/// Use it through [`BoxedAuthProvider`].
+pub trait DynAuthProvider: Send + Sync + 'static {
+    fn verify<'a>(&'a self, token: &'a str) -> BoxFuture<'a, Result<User, AuthError>>;
+
+    fn get_login_url(&self) -> Option<String>;
+
+    fn exchange_code<'a>(&'a self, code: &'a str) -> BoxFuture<'a, Result<User, AuthError>>;
+}
+
+impl<A: AuthProvider> DynAuthProvider for A {
+    fn verify<'a>(&'a self, token: &'a str) -> BoxFuture<'a, Result<User, AuthError>> {
+        Box::pin(AuthProvider::verify(self, token))
+    }
+
+    fn get_login_url(&self) -> Option<String> {
+        AuthProvider::get_login_url(self)
+    }
+
+    fn exchange_code<'a>(&'a self, code: &'a str) -> BoxFuture<'a, Result<User, AuthError>> {
+        Box::pin(AuthProvider::exchange_code(self, code))
+    }
+}
+
+/// An [`AuthProvider`] whose concrete type is picked at runtime.
+#[derive(Clone)]
+pub struct BoxedAuthProvider(Arc<dyn DynAuthProvider>);
+
+impl BoxedAuthProvider {
+    pub fn new(auth: impl AuthProvider) -> Self {
+        Self(Arc::new(auth))
+    }
+}
+
+impl AuthProvider for BoxedAuthProvider {
+    async fn verify(&self, token: &str) -> Result<User, AuthError> {
+        self.0.verify(token).await
+    }
+
+    fn get_login_url(&self) -> Option<String> {
+        self.0.get_login_url()
+    }
+
+    async fn exchange_code(&self, code: &str) -> Result<User, AuthError> {
+        self.0.exchange_code(code).await
+    }
+}
@@ -1,3 +1,4 @@
+use crate::format::ManifestFormat;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -11,10 +12,28 @@ pub enum StorageError {
     #[error("Path not found: {0}")]
     NotFound(String),
 
+    /// A listing cursor that was not issued by this backend.
+    #[error("Invalid cursor: {0}")]
+    InvalidCursor(String),
+
     #[error("Storage backend error: {0}")]
     Generic(String),
 }
 
+#[derive(Error, Debug)]
+pub enum TransformError {
+    /// The parameters are not supported, e.g. an unknown format.
+    #[error("Invalid transform parameters: {0}")]
+    InvalidParams(String),
+
+    /// The asset can't be transformed, e.g. it is not an image.
+    #[error("Unsupported asset: {0}")]
+    Unsupported(String),
+
+    #[error("Transform error: {0}")]
+    Generic(String),
+}
+
 #[derive(Error, Debug)]
 pub enum AuthError {
     #[error("Invalid token")]
@@ -25,4 +44,30 @@ pub enum AuthError {
 
     #[error("Authentication provider error: {0}")]
     Generic(String),
+
+    /// The provider could not be reached in time, e.g. a slow identity provider.
+    #[error("Authentication system error: {0}")]
+    System(String),
+}
+
+#[derive(Error, Debug)]
+pub enum ManifestError {
+    #[error("Manifest serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+
+    #[error("Unsupported manifest schema version: {0}")]
+    UnsupportedSchema(u64),
+
+    #[error("Manifest is not signed")]
+    MissingSignature,
+
+    #[error("Invalid manifest signature")]
+    InvalidSignature,
+
+    #[error("Invalid key: {0}")]
+    InvalidKey(String),
+
+    /// A manifest that can't be parsed or written in a non-JSON format.
+    #[error("{0} manifest error: {1}")]
+    Format(ManifestFormat, String),
 }
@@ -0,0 +1,162 @@
+//! Manifest formats besides JSON, e.g. for manifests maintained by hand.
+//!
+//! YAML and TOML require the `yaml` and `toml` features. Manifests are always stored and
+//! served as canonical JSON, see [`AssetManifest::to_canonical_vec`].
+
+use crate::error::ManifestError;
+use crate::manifest::AssetManifest;
+use std::fmt;
+
+/// A serialization format of [`AssetManifest`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManifestFormat {
+    Json,
+    #[cfg(feature = "yaml")]
+    Yaml,
+    #[cfg(feature = "toml")]
+    Toml,
+}
+
+impl ManifestFormat {
+    /// All formats enabled by features.
+    pub const ALL: &[ManifestFormat] = &[
+        ManifestFormat::Json,
+        #[cfg(feature = "yaml")]
+        ManifestFormat::Yaml,
+        #[cfg(feature = "toml")]
+        ManifestFormat::Toml,
+    ];
+
+    /// The format of a file extension, e.g. `yml`. Case insensitive.
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "json" => Some(Self::Json),
+            #[cfg(feature = "yaml")]
+            "yaml" | "yml" => Some(Self::Yaml),
+            #[cfg(feature = "toml")]
+            "toml" => Some(Self::Toml),
+            _ => None,
+        }
+    }
+
+    /// The format of a `Content-Type`, ignoring parameters like `charset`.
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let essence = content_type.split(';').next().unwrap_or_default().trim();
+        match essence.to_ascii_lowercase().as_str() {
+            "application/json" => Some(Self::Json),
+            #[cfg(feature = "yaml")]
+            "application/yaml" | "application/x-yaml" | "text/yaml" | "text/x-yaml" => {
+                Some(Self::Yaml)
+            }
+            #[cfg(feature = "toml")]
+            "application/toml" | "text/toml" => Some(Self::Toml),
+            _ => None,
+        }
+    }
+
+    /// The `Content-Type` sent for this format.
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            #[cfg(feature = "yaml")]
+            Self::Yaml => "application/yaml",
+            #[cfg(feature = "toml")]
+            Self::Toml => "application/toml",
+        }
+    }
+}
+
+impl fmt::Display for ManifestFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Json => "JSON",
+            #[cfg(feature = "yaml")]
+            Self::Yaml => "YAML",
+            #[cfg(feature = "toml")]
+            Self::Toml => "TOML",
+        })
+    }
+}
+
+impl AssetManifest {
+    /// Parses a manifest in `format`, upgrading older schema versions, see
+    /// [`migrate`](Self::migrate).
+    ///
+    /// TOML dates may be written as strings or as native TOML datetimes.
+    pub fn from_slice(format: ManifestFormat, data: &[u8]) -> Result<Self, ManifestError> {
+        let value = match format {
+            ManifestFormat::Json => serde_json::from_slice(data)?,
+            #[cfg(feature = "yaml")]
+            ManifestFormat::Yaml => serde_yaml_ng::from_slice(data)
+                .map_err(|e| ManifestError::Format(format, e.to_string()))?,
+            #[cfg(feature = "toml")]
+            ManifestFormat::Toml => {
+                let text = std::str::from_utf8(data)
+                    .map_err(|e| ManifestError::Format(format, e.to_string()))?;
+                let table: toml::Table = toml::from_str(text)
+                    .map_err(|e| ManifestError::Format(format, e.to_string()))?;
+                toml_to_json(toml::Value::Table(table))
+            }
+        };
+        Self::migrate(value)
+    }
+
+    /// Serializes the manifest to `format` with sorted keys, for files maintained by hand.
+    ///
+    /// ```
+    /// # use aquila_core::prelude::*;
+    /// let manifest = AssetManifest { version: "v1".into(), ..Default::default() };
+    ///
+    /// for format in ManifestFormat::ALL {
+    ///     let text = manifest.to_string_as(*format).unwrap();
+    ///     let parsed = AssetManifest::from_slice(*format, text.as_bytes()).unwrap();
+    ///     assert_eq!(parsed.to_canonical_vec().unwrap(), manifest.to_canonical_vec().unwrap());
+    /// }
+    /// ```
+    pub fn to_string_as(&self, format: ManifestFormat) -> Result<String, ManifestError> {
+        match format {
+            ManifestFormat::Json => self.to_pretty_string(),
+            #[cfg(feature = "yaml")]
+            ManifestFormat::Yaml => serde_yaml_ng::to_string(&self.canonical_value()?)
+                .map_err(|e| ManifestError::Format(format, e.to_string())),
+            #[cfg(feature = "toml")]
+            ManifestFormat::Toml => {
+                // TOML has no null, absent fields are omitted instead.
+                let mut value = self.canonical_value()?;
+                strip_nulls(&mut value);
+                toml::to_string_pretty(&value)
+                    .map_err(|e| ManifestError::Format(format, e.to_string()))
+            }
+        }
+    }
+}
+
+/// Converts TOML to JSON, writing datetimes as RFC 3339 strings.
+#[cfg(feature = "toml")]
+fn toml_to_json(value: toml::Value) -> serde_json::Value {
+    use serde_json::Value;
+    match value {
+        toml::Value::String(s) => Value::String(s),
+        toml::Value::Integer(i) => Value::from(i),
+        toml::Value::Float(f) => Value::from(f),
+        toml::Value::Boolean(b) => Value::Bool(b),
+        toml::Value::Datetime(dt) => Value::String(dt.to_string()),
+        toml::Value::Array(items) => items.into_iter().map(toml_to_json).collect(),
+        toml::Value::Table(table) => table
+            .into_iter()
+            .map(|(key, value)| (key, toml_to_json(value)))
+            .collect(),
+    }
+}
+
+#[cfg(feature = "toml")]
+fn strip_nulls(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            map.retain(|_, value| !value.is_null());
+            map.values_mut().for_each(strip_nulls);
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(strip_nulls),
+        _ => {}
+    }
+}
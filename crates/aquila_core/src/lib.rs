@@ -10,13 +10,23 @@
 //! - **[`AssetManifest`](manifest::AssetManifest)**: The source of truth for a game version. Maps logical paths (e.g., `textures/test.png`) to physical content hashes.
 //! - **[`StorageBackend`](traits::StorageBackend)**: Trait for implementing storage layers (e.g., S3, Filesystem).
 //! - **[`AuthProvider`](traits::AuthProvider)**: Trait for implementing user verification strategies.
+//! - **[`ManifestFormat`](format::ManifestFormat)**: YAML and TOML manifests, behind the `yaml` and `toml` features.
+//! - **[`BoxedStorage`](dynamic::BoxedStorage)**: Type erased backends, to pick them at runtime.
 
+pub mod compression;
+pub mod dynamic;
 pub mod error;
+pub mod format;
 pub mod manifest;
+#[cfg(feature = "schema")]
+pub mod schema;
+pub mod signing;
 pub mod traits;
 
 pub mod prelude {
     pub use super::error::*;
+    pub use super::format::*;
     pub use super::manifest::*;
+    pub use super::signing::*;
     pub use super::traits::*;
 }
@@ -1,11 +1,33 @@
+use crate::error::ManifestError;
+use crate::signing::{SigningKey, VerifyingKey};
+use crate::traits::StorageCapabilities;
 use chrono::{DateTime, Utc};
+use ed25519_dalek::{Signature, Signer, Verifier};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+
+/// The current wire schema version of [`AssetManifest`].
+///
+/// Within a schema version, changes must be additive (new optional/defaulted fields only),
+/// so older clients can keep deserializing newer manifests. Any breaking change to the shape
+/// bumps this version and adds a migration step to [`AssetManifest::migrate`].
+pub const MANIFEST_SCHEMA_VERSION: u32 = 1;
+
+fn default_schema_version() -> u32 {
+    MANIFEST_SCHEMA_VERSION
+}
 
 /// The "Manifest" is the source of truth for a game version.
 /// It maps file paths ("textures/test.png") to content hashes ("x1b2c3...").
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct AssetManifest {
+    /// The wire schema version, see [`MANIFEST_SCHEMA_VERSION`].
+    ///
+    /// Manifests written before this field existed are schema version 1.
+    #[serde(default = "default_schema_version")]
+    pub schema_version: u32,
+
     /// The Version ID e.g., "v1.0" or P4 Changelist "1205" or Git SHA "a8f3b".
     pub version: String,
 
@@ -15,12 +37,27 @@ pub struct AssetManifest {
     /// Who triggered the build.
     pub published_by: String,
 
+    /// Set when the manifest has been patched after publishing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime<Utc>>,
+
     /// - Key: Game Path e.g., "assets/textures/test.png"
     /// - Value: Metadata
     pub assets: HashMap<String, AssetInfo>,
+
+    /// Optional: Base URL assets can be fetched from directly as `{base_url}/{hash}`, e.g. a CDN.
+    ///
+    /// May contain `{{var}}` placeholders, see [`AssetManifest::substitute`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
+
+    /// Hex encoded Ed25519 signature of the server, see [`AssetManifest::sign`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct AssetInfo {
     /// The SHA256 hash. This is the filename in the blob storage.
     pub hash: String,
@@ -31,3 +68,351 @@ pub struct AssetInfo {
     /// Optional: Media Type
     pub mime_type: Option<String>,
 }
+
+/// A partial update to an existing manifest.
+///
+/// Entries in `added` are inserted or replace existing paths,
+/// paths in `removed` are dropped from the manifest.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ManifestPatch {
+    #[serde(default)]
+    pub added: HashMap<String, AssetInfo>,
+
+    #[serde(default)]
+    pub removed: Vec<String>,
+}
+
+impl Default for AssetManifest {
+    fn default() -> Self {
+        Self {
+            schema_version: MANIFEST_SCHEMA_VERSION,
+            version: Default::default(),
+            published_at: Default::default(),
+            published_by: Default::default(),
+            updated_at: None,
+            assets: Default::default(),
+            base_url: None,
+            signature: None,
+        }
+    }
+}
+
+impl AssetManifest {
+    /// Parses a manifest of any known schema version, upgrading it to the current one.
+    ///
+    /// Fails for manifests with a schema version newer than [`MANIFEST_SCHEMA_VERSION`].
+    pub fn migrate(value: serde_json::Value) -> Result<AssetManifest, ManifestError> {
+        let schema_version = value
+            .get("schema_version")
+            .and_then(|v| v.as_u64())
+            .unwrap_or(MANIFEST_SCHEMA_VERSION.into());
+
+        match schema_version {
+            // Future shape changes upgrade `value` step by step here, e.g. `1 => migrate_v1(value)`.
+            1 => Ok(serde_json::from_value(value)?),
+            v => Err(ManifestError::UnsupportedSchema(v)),
+        }
+    }
+
+    /// Applies a [`ManifestPatch`] and records the update time.
+    ///
+    /// Removals are applied before additions, so a path present in both is replaced.
+    /// Clears the signature, as it no longer matches.
+    pub fn apply_patch(&mut self, patch: ManifestPatch) {
+        for path in &patch.removed {
+            self.assets.remove(path);
+        }
+        self.assets.extend(patch.added);
+        self.updated_at = Some(Utc::now());
+        self.signature = None;
+    }
+
+    /// Replaces `{{name}}` placeholders with the value of `name` in `vars`.
+    ///
+    /// Only applies to [`base_url`](Self::base_url), placeholders without a value are kept as is.
+    /// Values are inserted verbatim, placeholders within them are not expanded.
+    /// Clears the signature if anything changed, as it no longer matches.
+    ///
+    /// ```
+    /// # use aquila_core::manifest::AssetManifest;
+    /// # use std::collections::HashMap;
+    /// let mut manifest = AssetManifest {
+    ///     base_url: Some("https://{{cdn}}/{{unknown}}/{{region}}".into()),
+    ///     ..Default::default()
+    /// };
+    /// let vars = HashMap::from([
+    ///     ("cdn".to_string(), "cdn.example.com".to_string()),
+    ///     ("region".to_string(), "{{cdn}}".to_string()),
+    /// ]);
+    ///
+    /// manifest.substitute(&vars);
+    /// assert_eq!(
+    ///     manifest.base_url.unwrap(),
+    ///     "https://cdn.example.com/{{unknown}}/{{cdn}}"
+    /// );
+    /// ```
+    pub fn substitute(&mut self, vars: &HashMap<String, String>) {
+        let Some(base_url) = &self.base_url else {
+            return;
+        };
+
+        let mut substituted = String::with_capacity(base_url.len());
+        let mut rest = base_url.as_str();
+        while let Some(start) = rest.find("{{") {
+            substituted.push_str(&rest[..start]);
+            rest = &rest[start + 2..];
+            let value = rest
+                .find("}}")
+                .and_then(|end| Some((vars.get(&rest[..end])?, end)));
+            match value {
+                Some((value, end)) => {
+                    substituted.push_str(value);
+                    rest = &rest[end + 2..];
+                }
+                None => substituted.push_str("{{"),
+            }
+        }
+        substituted.push_str(rest);
+
+        if &substituted != base_url {
+            self.base_url = Some(substituted);
+            self.signature = None;
+        }
+    }
+
+    /// Serializes the manifest to its canonical JSON form: object keys sorted, no whitespace.
+    ///
+    /// The same logical manifest always produces the same bytes, which makes them suitable for
+    /// storage, signatures and ETags. Use [`to_pretty_string`](Self::to_pretty_string) for humans.
+    ///
+    /// ```
+    /// # use aquila_core::manifest::{AssetInfo, AssetManifest};
+    /// let info = |hash: &str| AssetInfo { hash: hash.into(), size: 1, mime_type: None };
+    ///
+    /// let mut a = AssetManifest { version: "v1".into(), ..Default::default() };
+    /// a.assets.insert("b.png".into(), info("b"));
+    /// a.assets.insert("a.png".into(), info("a"));
+    ///
+    /// let mut b = AssetManifest { version: "v1".into(), ..Default::default() };
+    /// b.assets.insert("a.png".into(), info("a"));
+    /// b.assets.insert("b.png".into(), info("b"));
+    ///
+    /// let bytes = a.to_canonical_vec().unwrap();
+    /// assert_eq!(bytes, b.to_canonical_vec().unwrap());
+    ///
+    /// // Round-trips to the same bytes.
+    /// let parsed: AssetManifest = serde_json::from_slice(&bytes).unwrap();
+    /// assert_eq!(parsed.to_canonical_vec().unwrap(), bytes);
+    /// ```
+    pub fn to_canonical_vec(&self) -> Result<Vec<u8>, ManifestError> {
+        Ok(serde_json::to_vec(&self.canonical_value()?)?)
+    }
+
+    /// Serializes the manifest to indented JSON with sorted keys, for human-facing output.
+    pub fn to_pretty_string(&self) -> Result<String, ManifestError> {
+        Ok(serde_json::to_string_pretty(&self.canonical_value()?)?)
+    }
+
+    pub(crate) fn canonical_value(&self) -> Result<serde_json::Value, ManifestError> {
+        Ok(sort_keys(serde_json::to_value(self)?))
+    }
+
+    /// The bytes covered by the signature of a manifest in JSON form: the canonical form of
+    /// every field but `signature`, including fields unknown to this version.
+    pub fn signing_payload(value: &serde_json::Value) -> Result<Vec<u8>, ManifestError> {
+        let mut value = value.clone();
+        if let Some(object) = value.as_object_mut() {
+            object.remove("signature");
+        }
+        Ok(serde_json::to_vec(&sort_keys(value))?)
+    }
+
+    /// Signs the manifest, replacing any previous signature.
+    pub fn sign(&mut self, key: &SigningKey) -> Result<(), ManifestError> {
+        let signature = key.sign(&Self::signing_payload(&self.canonical_value()?)?);
+        self.signature = Some(hex::encode(signature.to_bytes()));
+        Ok(())
+    }
+
+    /// Verifies the signature against the server's verifying key.
+    ///
+    /// Fields unknown to this version are lost when deserializing, so manifests of newer
+    /// servers should be verified with [`verify_json`](Self::verify_json) before.
+    pub fn verify(&self, key: &VerifyingKey) -> Result<(), ManifestError> {
+        Self::verify_json(&serde_json::to_value(self)?, key)
+    }
+
+    /// Verifies the signature of a manifest as received, before it is deserialized.
+    ///
+    /// ```
+    /// # use aquila_core::prelude::*;
+    /// # use aquila_core::signing::generate_signing_key;
+    /// # use ed25519_dalek::Signer;
+    /// let key = generate_signing_key();
+    /// let manifest = AssetManifest { version: "v1".into(), ..Default::default() };
+    ///
+    /// // Signed by a newer server, with a field this version doesn't know.
+    /// let mut value = serde_json::to_value(&manifest).unwrap();
+    /// value["channel"] = "beta".into();
+    /// let signature = key.sign(&AssetManifest::signing_payload(&value).unwrap());
+    /// value["signature"] = hex::encode(signature.to_bytes()).into();
+    ///
+    /// assert!(AssetManifest::verify_json(&value, &key.verifying_key()).is_ok());
+    /// let typed = AssetManifest::migrate(value).unwrap();
+    /// assert!(typed.verify(&key.verifying_key()).is_err());
+    /// ```
+    pub fn verify_json(value: &serde_json::Value, key: &VerifyingKey) -> Result<(), ManifestError> {
+        let signature = value
+            .get("signature")
+            .and_then(|signature| signature.as_str())
+            .ok_or(ManifestError::MissingSignature)?;
+
+        let bytes: [u8; 64] = hex::decode(signature)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or(ManifestError::InvalidSignature)?;
+
+        key.verify(
+            &Self::signing_payload(value)?,
+            &Signature::from_bytes(&bytes),
+        )
+        .map_err(|_| ManifestError::InvalidSignature)
+    }
+
+    /// Computes aggregated [`ManifestStats`] for this manifest.
+    pub fn stats(&self) -> ManifestStats {
+        let mut stats = ManifestStats {
+            asset_count: self.assets.len(),
+            ..Default::default()
+        };
+
+        for info in self.assets.values() {
+            stats.total_size += info.size;
+
+            let mime = info.mime_type.as_deref().unwrap_or("unknown");
+            let entry = stats.by_mime.entry(mime.to_string()).or_default();
+            entry.count += 1;
+            entry.total_size += info.size;
+        }
+
+        stats
+    }
+}
+
+/// Recursively sorts object keys, independent of the map type backing [`serde_json::Map`].
+fn sort_keys(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(object) => {
+            let mut entries: Vec<_> = object.into_iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            serde_json::Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, sort_keys(value)))
+                    .collect(),
+            )
+        }
+        serde_json::Value::Array(values) => {
+            serde_json::Value::Array(values.into_iter().map(sort_keys).collect())
+        }
+        value => value,
+    }
+}
+
+/// The outcome of a dry-run publish, describing what would be published.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct PublishReport {
+    pub version: String,
+
+    /// Number of assets in the manifest.
+    pub asset_count: usize,
+
+    /// Sum of all asset sizes in bytes.
+    pub total_size: u64,
+
+    /// Referenced hashes that are not present in the storage backend.
+    pub missing_hashes: Vec<String>,
+}
+
+/// The outcome of pruning old manifests.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct PruneReport {
+    /// The unpublished versions, oldest first. On a dry run, the ones that would be.
+    pub removed: Vec<String>,
+
+    /// Whether this was a dry run, i.e. nothing was deleted.
+    pub dry_run: bool,
+}
+
+/// Aggregated statistics of a manifest.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ManifestStats {
+    /// Number of assets in the manifest.
+    pub asset_count: usize,
+
+    /// Sum of all asset sizes in bytes.
+    pub total_size: u64,
+
+    /// Stats grouped by media type, `unknown` if an asset has none.
+    pub by_mime: BTreeMap<String, MimeStats>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct MimeStats {
+    pub count: usize,
+    pub total_size: u64,
+}
+
+/// Features of a server deployment, so clients can adapt instead of probing endpoints.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ServerCapabilities {
+    /// Optional features of the storage backend.
+    pub storage: StorageCapabilities,
+
+    /// The auth provider supports the browser login flow at `/auth/login`.
+    pub login: bool,
+
+    /// Published manifests are signed by the server.
+    pub signed_manifests: bool,
+
+    /// Assets and manifests can be downloaded without a token.
+    pub anonymous_read: bool,
+
+    /// Hash algorithm used to address blobs, e.g. `sha256`.
+    pub hash_algorithm: String,
+
+    /// Maximum upload size in bytes, `None` if unlimited.
+    pub max_upload_size: Option<u64>,
+
+    /// Scopes understood by the server.
+    pub scopes: Vec<String>,
+}
+
+/// Content store wide statistics, showing the savings of content-addressed storage.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct StorageStats {
+    /// Number of distinct blobs stored.
+    pub unique_blobs: usize,
+
+    /// Sum of all stored blob sizes in bytes.
+    pub total_blob_bytes: u64,
+
+    /// Number of published versions, excluding `latest`.
+    pub manifest_count: usize,
+
+    /// Number of assets summed across all manifests.
+    pub logical_asset_count: usize,
+
+    /// Sum of all asset sizes across all manifests in bytes.
+    pub logical_bytes: u64,
+
+    /// `logical_bytes / total_blob_bytes`, e.g. `3.0` means every stored byte is used three times.
+    pub dedup_ratio: f64,
+}
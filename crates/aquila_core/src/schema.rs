@@ -0,0 +1,29 @@
+//! JSON Schemas of the protocol types, for clients written in other languages.
+//!
+//! Requires the `schema` feature.
+
+use crate::manifest::*;
+use crate::traits::{BlobInfo, Page, StorageCapabilities, TransformParams};
+use schemars::{Schema, schema_for};
+
+/// Returns the schemas of all types sent over the wire, by type name.
+///
+/// Errors are not included, the server responds to failed requests with a plain text message
+/// and the HTTP status.
+pub fn schemas() -> Vec<(&'static str, Schema)> {
+    vec![
+        ("AssetManifest", schema_for!(AssetManifest)),
+        ("AssetInfo", schema_for!(AssetInfo)),
+        ("ManifestPatch", schema_for!(ManifestPatch)),
+        ("PublishReport", schema_for!(PublishReport)),
+        ("PruneReport", schema_for!(PruneReport)),
+        ("ManifestStats", schema_for!(ManifestStats)),
+        ("StorageStats", schema_for!(StorageStats)),
+        ("ServerCapabilities", schema_for!(ServerCapabilities)),
+        ("StorageCapabilities", schema_for!(StorageCapabilities)),
+        ("TransformParams", schema_for!(TransformParams)),
+        ("BlobInfo", schema_for!(BlobInfo)),
+        ("BlobPage", schema_for!(Page<BlobInfo>)),
+        ("ManifestPage", schema_for!(Page<String>)),
+    ]
+}
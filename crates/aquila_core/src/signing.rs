@@ -0,0 +1,31 @@
+//! Ed25519 keys for signing manifests, see [`AssetManifest::sign`](crate::manifest::AssetManifest::sign).
+//!
+//! Keys are exchanged as hex strings: the server is configured with the signing key,
+//! clients verify with the corresponding verifying (public) key.
+
+use crate::error::ManifestError;
+use rand_core::OsRng;
+
+pub use ed25519_dalek::{SigningKey, VerifyingKey};
+
+/// Generates a new random signing key.
+pub fn generate_signing_key() -> SigningKey {
+    SigningKey::generate(&mut OsRng)
+}
+
+/// Parses a hex encoded signing key.
+pub fn signing_key_from_hex(hex: &str) -> Result<SigningKey, ManifestError> {
+    Ok(SigningKey::from_bytes(&key_bytes(hex)?))
+}
+
+/// Parses a hex encoded verifying key.
+pub fn verifying_key_from_hex(hex: &str) -> Result<VerifyingKey, ManifestError> {
+    VerifyingKey::from_bytes(&key_bytes(hex)?).map_err(|e| ManifestError::InvalidKey(e.to_string()))
+}
+
+fn key_bytes(hex: &str) -> Result<[u8; 32], ManifestError> {
+    hex::decode(hex.trim())
+        .map_err(|e| ManifestError::InvalidKey(e.to_string()))?
+        .try_into()
+        .map_err(|_| ManifestError::InvalidKey("Expected 32 bytes".into()))
+}
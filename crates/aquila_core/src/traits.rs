@@ -1,8 +1,18 @@
 use crate::error::*;
+use std::collections::HashMap;
+use std::fmt::Debug;
 use std::pin::Pin;
+use std::str::FromStr;
+use std::time::SystemTime;
 
 use bytes::Bytes;
 use futures::Stream;
+use futures::future::try_join_all;
+use serde::{Deserialize, Serialize};
+
+/// Number of concurrent [`StorageBackend::exists`] calls made by the default
+/// [`StorageBackend::exists_many`].
+pub const EXISTS_MANY_CONCURRENCY: usize = 16;
 
 /// A trait for injecting storage logic into the server.
 pub trait StorageBackend: Send + Sync + 'static + Clone {
@@ -27,6 +37,24 @@ pub trait StorageBackend: Send + Sync + 'static + Clone {
         }
     }
 
+    /// Optional: Writes raw bytes at an explicit path, overwriting any existing object.
+    ///
+    /// Unlike [`write_blob`](Self::write_blob), the path is not a content hash, e.g. for sidecar
+    /// files that are not blobs or manifests. Used for manifest checksums, cached transforms and
+    /// pins. Backends that can't write objects return an error (default); their manifests are
+    /// published without checksums, transforms aren't cached and pins are unavailable.
+    fn put_object(
+        &self,
+        _path: &str,
+        _data: Bytes,
+    ) -> impl Future<Output = Result<(), StorageError>> + Send {
+        async {
+            Err(StorageError::Generic(
+                "Writing objects not implemented for this backend".into(),
+            ))
+        }
+    }
+
     /// Writes a manifest with the specified version to the storage backend.
     fn write_manifest(
         &self,
@@ -40,6 +68,42 @@ pub trait StorageBackend: Send + Sync + 'static + Clone {
     /// Checks if a file exists in the storage backend.
     fn exists(&self, path: &str) -> impl Future<Output = Result<bool, StorageError>> + Send;
 
+    /// Checks which of the given paths exist, in the same order.
+    ///
+    /// Defaults to calling [`exists`](Self::exists) concurrently, backends with a cheaper way
+    /// to check many files at once should override this.
+    fn exists_many(
+        &self,
+        paths: &[String],
+    ) -> impl Future<Output = Result<Vec<bool>, StorageError>> + Send {
+        async move {
+            let mut found = Vec::with_capacity(paths.len());
+            for chunk in paths.chunks(EXISTS_MANY_CONCURRENCY) {
+                found.extend(try_join_all(chunk.iter().map(|path| self.exists(path))).await?);
+            }
+            Ok(found)
+        }
+    }
+
+    /// Optional: Returns the size and modification time of a file.
+    ///
+    /// Used for `Last-Modified` headers, backends that can't stat files return an error (default).
+    fn stat(&self, _path: &str) -> impl Future<Output = Result<ObjectMeta, StorageError>> + Send {
+        async {
+            Err(StorageError::Generic(
+                "Stat not implemented for this backend".into(),
+            ))
+        }
+    }
+
+    /// Reports which optional features this backend supports, so callers don't have to
+    /// try them and handle the error.
+    ///
+    /// Defaults to none, backends must override this when implementing an optional method.
+    fn capabilities(&self) -> StorageCapabilities {
+        StorageCapabilities::default()
+    }
+
     /// Returns a Manifest path for a given version
     fn get_manifest_path(&self, version: &str) -> String {
         format!("manifests/{version}")
@@ -56,8 +120,369 @@ pub trait StorageBackend: Send + Sync + 'static + Clone {
         async { Ok(None) }
     }
 
+    /// Optional: Like [`get_download_url`](Self::get_download_url), but the URL should make
+    /// browsers save the file as `filename` (e.g., S3 `response-content-disposition`).
+    ///
+    /// `filename` is already sanitized. If `Ok(None)` (default), the server will proxy the file
+    /// and set the `Content-Disposition` header itself.
+    fn get_download_url_as(
+        &self,
+        _path: &str,
+        _filename: &str,
+    ) -> impl Future<Output = Result<Option<String>, StorageError>> + Send {
+        async { Ok(None) }
+    }
+
+    /// Optional: Like [`get_download_url`](Self::get_download_url), or
+    /// [`get_download_url_as`](Self::get_download_url_as) with a `filename`, for a path the
+    /// caller just found, e.g. with [`stat`](Self::stat), so backends can skip checking that it
+    /// exists again.
+    ///
+    /// Defaults to the checked variants.
+    fn get_download_url_unchecked(
+        &self,
+        path: &str,
+        filename: Option<&str>,
+    ) -> impl Future<Output = Result<Option<String>, StorageError>> + Send {
+        async move {
+            match filename {
+                Some(filename) => self.get_download_url_as(path, filename).await,
+                None => self.get_download_url(path).await,
+            }
+        }
+    }
+
+    /// Optional: Lists all blobs in the storage backend.
+    ///
+    /// Used for storage wide reports, backends that can't list return an error (default).
+    fn list_blobs(&self) -> impl Future<Output = Result<Vec<BlobInfo>, StorageError>> + Send {
+        async {
+            Err(StorageError::Generic(
+                "Listing blobs not implemented for this backend".into(),
+            ))
+        }
+    }
+
+    /// Optional: Lists the versions of all manifests in the storage backend, including `latest`.
+    fn list_manifests(&self) -> impl Future<Output = Result<Vec<String>, StorageError>> + Send {
+        async {
+            Err(StorageError::Generic(
+                "Listing manifests not implemented for this backend".into(),
+            ))
+        }
+    }
+
+    /// Optional: Lists one page of at most `limit` blobs, starting at `cursor`.
+    ///
+    /// Defaults to listing all blobs, sorting them by hash and using the offset as the cursor.
+    /// Backends with native pagination should override this and pass their continuation token
+    /// as the cursor instead.
+    fn list_blobs_page(
+        &self,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> impl Future<Output = Result<Page<BlobInfo>, StorageError>> + Send {
+        async move {
+            let mut blobs = self.list_blobs().await?;
+            blobs.sort_by(|a, b| a.hash.cmp(&b.hash));
+            Page::paginate(blobs, cursor, limit)
+        }
+    }
+
+    /// Optional: Lists one page of at most `limit` manifest versions, starting at `cursor`.
+    ///
+    /// See [`list_blobs_page`](Self::list_blobs_page).
+    fn list_manifests_page(
+        &self,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> impl Future<Output = Result<Page<String>, StorageError>> + Send {
+        async move {
+            let mut versions = self.list_manifests().await?;
+            versions.sort();
+            Page::paginate(versions, cursor, limit)
+        }
+    }
+
     /// Deletes a file from the storage backend.
     fn delete_file(&self, path: &str) -> impl Future<Output = Result<(), StorageError>> + Send;
+
+    /// Optional: Returns the backend to use for a single request made by `ctx`.
+    ///
+    /// The server calls this once per request and uses the result for all storage calls of
+    /// that request, so custom backends can isolate tenants, e.g. by prefixing paths with the
+    /// subject. Defaults to the backend itself, i.e. the context is ignored.
+    fn with_context(&self, _ctx: &RequestContext) -> Self {
+        self.clone()
+    }
+}
+
+/// The authenticated caller of a request, see [`StorageBackend::with_context`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RequestContext {
+    /// The id of the [`User`] making the request.
+    pub subject: String,
+
+    /// The scopes of the [`User`] making the request.
+    pub scopes: Vec<String>,
+}
+
+impl From<&User> for RequestContext {
+    fn from(user: &User) -> Self {
+        Self {
+            subject: user.id.clone(),
+            scopes: user.scopes.clone(),
+        }
+    }
+}
+
+/// Optional features supported by a [`StorageBackend`], see [`StorageBackend::capabilities`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct StorageCapabilities {
+    /// [`StorageBackend::write_stream`] is implemented.
+    pub streaming: bool,
+
+    /// [`StorageBackend::get_download_url`] returns direct URLs, so downloads are redirected.
+    pub download_urls: bool,
+
+    /// [`StorageBackend::list_blobs`] and [`StorageBackend::list_manifests`] are implemented.
+    pub listing: bool,
+
+    /// [`StorageBackend::put_object`] is implemented.
+    #[serde(default)]
+    pub objects: bool,
+
+    /// Data is kept apart per tenant, see [`StorageBackend::with_context`].
+    #[serde(default)]
+    pub tenants: bool,
+}
+
+/// A blob as reported by [`StorageBackend::list_blobs`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct BlobInfo {
+    /// The SHA256 hash, i.e. the path of the blob.
+    pub hash: String,
+
+    /// Size in bytes
+    pub size: u64,
+}
+
+/// Metadata of a stored file, see [`StorageBackend::stat`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ObjectMeta {
+    /// Size in bytes
+    pub size: u64,
+
+    /// Time of the last write, `None` if the backend doesn't track it.
+    pub last_modified: Option<SystemTime>,
+}
+
+/// One page of a listing, see [`StorageBackend::list_blobs_page`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Page<T> {
+    pub items: Vec<T>,
+
+    /// Opaque cursor of the next page, `None` if this is the last page.
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    /// Takes the page at the offset `cursor` out of the already sorted `items`.
+    ///
+    /// ```
+    /// # use aquila_core::traits::Page;
+    /// let page = Page::paginate(vec![1, 2, 3], None, 2).unwrap();
+    /// assert_eq!(page.items, [1, 2]);
+    /// assert_eq!(page.next_cursor.as_deref(), Some("2"));
+    ///
+    /// let page = Page::paginate(vec![1, 2, 3], Some("2"), 2).unwrap();
+    /// assert_eq!(page.items, [3]);
+    /// assert!(page.next_cursor.is_none());
+    ///
+    /// assert!(Page::paginate(vec![1, 2, 3], Some("x"), 2).is_err());
+    /// ```
+    pub fn paginate(
+        items: Vec<T>,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<Self, StorageError> {
+        let offset = match cursor {
+            Some(cursor) => cursor
+                .parse::<usize>()
+                .map_err(|_| StorageError::InvalidCursor(cursor.to_string()))?,
+            None => 0,
+        };
+        let limit = limit.max(1);
+        let end = offset.saturating_add(limit);
+        let next_cursor = (end < items.len()).then(|| end.to_string());
+
+        Ok(Self {
+            items: items.into_iter().skip(offset).take(limit).collect(),
+            next_cursor,
+        })
+    }
+}
+
+/// Returns `true` if `name` looks like a blob path, i.e. a hex encoded SHA256 hash.
+pub fn is_blob_hash(name: &str) -> bool {
+    name.len() == 64 && name.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Parameters of an on-the-fly asset transformation, e.g. `?w=128&format=webp`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TransformParams {
+    /// Target width in pixels.
+    pub w: Option<u32>,
+
+    /// Target height in pixels.
+    pub h: Option<u32>,
+
+    /// Target format as a file extension, e.g. `png` or `webp`. Keeps the input format if unset.
+    pub format: Option<String>,
+}
+
+impl TransformParams {
+    /// A stable, path safe key for caching the result, `None` if the format is not alphanumeric.
+    ///
+    /// ```
+    /// # use aquila_core::traits::TransformParams;
+    /// let params = TransformParams {
+    ///     w: Some(128),
+    ///     format: Some("webp".into()),
+    ///     ..Default::default()
+    /// };
+    /// assert_eq!(params.cache_key().unwrap(), "128x0.webp");
+    /// assert_eq!(TransformParams::default().cache_key().unwrap(), "0x0");
+    ///
+    /// let params = TransformParams {
+    ///     format: Some("../x".into()),
+    ///     ..Default::default()
+    /// };
+    /// assert!(params.cache_key().is_none());
+    /// ```
+    pub fn cache_key(&self) -> Option<String> {
+        let size = format!("{}x{}", self.w.unwrap_or(0), self.h.unwrap_or(0));
+        match &self.format {
+            None => Some(size),
+            Some(format)
+                if !format.is_empty() && format.chars().all(|c| c.is_ascii_alphanumeric()) =>
+            {
+                Some(format!("{size}.{}", format.to_ascii_lowercase()))
+            }
+            Some(_) => None,
+        }
+    }
+}
+
+/// A trait for transforming assets on the fly, e.g. resizing images.
+///
+/// Transformations are CPU bound, the server runs them on a blocking thread and caches
+/// the result, so the same transformation of a blob only runs once.
+pub trait AssetTransformer: Send + Sync + 'static + Debug {
+    /// Transforms the asset `data` according to `params`.
+    fn transform(&self, data: &[u8], params: &TransformParams) -> Result<Bytes, TransformError>;
+}
+
+/// Translates the claims of an external identity provider, e.g. roles or teams, into scopes.
+///
+/// See [`ScopeTable`] for a table based implementation.
+pub trait ScopeMapper: Send + Sync + 'static + Debug {
+    /// Returns the scopes granted for `claims`.
+    fn map(&self, claims: &[String]) -> Vec<String>;
+}
+
+/// Maps claims to scopes with a lookup table, e.g. `aquila-writer` to `read` and `write`.
+///
+/// Unknown claims grant nothing, [`default_scopes`](Self::with_default_scopes) are granted
+/// to everyone.
+///
+/// ```
+/// # use aquila_core::prelude::*;
+/// let table: ScopeTable = "aquila-writer=read write, aquila-reader=read".parse().unwrap();
+/// assert_eq!(table.map(&["aquila-writer".into()]), ["read", "write"]);
+/// assert_eq!(
+///     table.map(&["aquila-reader".into(), "aquila-writer".into()]),
+///     ["read", "write"]
+/// );
+/// assert!(table.map(&["other".into()]).is_empty());
+///
+/// let table = ScopeTable::default()
+///     .with_mapping("my-org/artists", ["write"])
+///     .with_default_scopes(["read"]);
+/// assert_eq!(table.map(&["my-org/artists".into()]), ["read", "write"]);
+///
+/// assert!("aquila-writer".parse::<ScopeTable>().is_err());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ScopeTable {
+    mappings: HashMap<String, Vec<String>>,
+    default_scopes: Vec<String>,
+}
+
+impl ScopeTable {
+    /// Grants `scopes` for `claim`, in addition to the scopes already mapped to it.
+    pub fn with_mapping(
+        mut self,
+        claim: impl Into<String>,
+        scopes: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.mappings
+            .entry(claim.into())
+            .or_default()
+            .extend(scopes.into_iter().map(Into::into));
+        self
+    }
+
+    /// Grants `scopes` regardless of the claims.
+    pub fn with_default_scopes(
+        mut self,
+        scopes: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.default_scopes = scopes.into_iter().map(Into::into).collect();
+        self
+    }
+}
+
+impl ScopeMapper for ScopeTable {
+    fn map(&self, claims: &[String]) -> Vec<String> {
+        let mapped = claims
+            .iter()
+            .filter_map(|claim| self.mappings.get(claim))
+            .flatten();
+
+        let mut scopes: Vec<String> = Vec::new();
+        for scope in self.default_scopes.iter().chain(mapped) {
+            if !scopes.contains(scope) {
+                scopes.push(scope.clone());
+            }
+        }
+        scopes
+    }
+}
+
+/// Parses a comma separated `claim=scopes` list, with space separated scopes,
+/// e.g. `aquila-writer=read write, aquila-reader=read`.
+impl FromStr for ScopeTable {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|mapping| !mapping.is_empty())
+            .try_fold(Self::default(), |table, mapping| {
+                match mapping.split_once('=') {
+                    Some((claim, scopes)) if !claim.trim().is_empty() => {
+                        Ok(table.with_mapping(claim.trim(), scopes.split_whitespace()))
+                    }
+                    _ => Err(format!("`{mapping}` is not a `claim=scopes` mapping")),
+                }
+            })
+    }
 }
 
 #[derive(Debug, Clone)]
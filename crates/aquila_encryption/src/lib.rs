@@ -0,0 +1,325 @@
+//! # Aquila Encryption
+//! [![Crates.io](https://img.shields.io/crates/v/aquila_encryption.svg)](https://crates.io/crates/aquila_encryption)
+//! [![Downloads](https://img.shields.io/crates/d/aquila_encryption.svg)](https://crates.io/crates/aquila_encryption)
+//! [![Docs](https://docs.rs/aquila_encryption/badge.svg)](https://docs.rs/aquila_encryption/)
+//!
+//! At-rest encryption for any [`StorageBackend`], e.g. for backends that don't encrypt natively.
+//!
+//! [`EncryptedStorage`] wraps another backend and encrypts blobs and manifests with
+//! XChaCha20-Poly1305 before they are written, and decrypts them when they are read.
+//!
+//! ## Design
+//!
+//! Blobs stay addressed by the SHA256 of their **plaintext**, the ciphertext is stored under
+//! that hash. This keeps deduplication and manifests unchanged, but means the stored bytes
+//! no longer hash to their path, so the content hash can only be verified after decryption.
+//!
+//! Integrity is provided by the AEAD instead: every object is encrypted in chunks using the
+//! STREAM construction with a random nonce prefix stored in front of the ciphertext,
+//! and its path is authenticated as associated data. Tampered, truncated or swapped objects
+//! fail to decrypt and are reported as errors.
+//!
+//! Since stored objects are ciphertext, direct download URLs are disabled and the server
+//! always proxies downloads. Sizes reported by [`StorageBackend::list_blobs`] are ciphertext sizes.
+//!
+//! ## Usage
+//!
+//! ```no_run
+//! # use aquila_encryption::EncryptedStorage;
+//! # use aquila_core::prelude::*;
+//! # fn run<S: StorageBackend>(inner: S) {
+//! // Load the 32 byte key from a secret store, losing it means losing all data!
+//! let key = [0u8; 32];
+//!
+//! let storage = EncryptedStorage::new(inner, &key);
+//! # }
+//! ```
+
+use aquila_core::prelude::*;
+use bytes::{Bytes, BytesMut};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::stream::{DecryptorBE32, EncryptorBE32};
+use chacha20poly1305::aead::{OsRng, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305};
+use futures::{Stream, StreamExt, stream};
+use std::pin::Pin;
+
+/// Size of an encrypted plaintext chunk.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Size of the Poly1305 tag appended to every chunk.
+const TAG_SIZE: usize = 16;
+
+/// Size of the random nonce prefix stored in front of the ciphertext.
+const NONCE_SIZE: usize = 19;
+
+#[derive(Clone)]
+pub struct EncryptedStorage<S: StorageBackend> {
+    inner: S,
+    key: Key,
+}
+
+impl<S: StorageBackend> EncryptedStorage<S> {
+    /// Wraps `inner`, encrypting everything with the given 256-bit key.
+    pub fn new(inner: S, key: &[u8; 32]) -> Self {
+        Self {
+            inner,
+            key: Key::from(*key),
+        }
+    }
+
+    /// Private helper to start a new encryption with a random nonce prefix.
+    fn encryptor(&self) -> (EncryptorBE32<XChaCha20Poly1305>, [u8; NONCE_SIZE]) {
+        let mut nonce = [0u8; NONCE_SIZE];
+        OsRng.fill_bytes(&mut nonce);
+
+        let encryptor = EncryptorBE32::new(&self.key, &nonce.into());
+        (encryptor, nonce)
+    }
+
+    /// Private helper to encrypt `data`, authenticating `path`.
+    fn encrypt(&self, path: &str, data: &[u8]) -> Result<Bytes, StorageError> {
+        let (mut encryptor, nonce) = self.encryptor();
+
+        let mut out = BytesMut::with_capacity(encrypted_len(data.len() as u64) as usize);
+        out.extend_from_slice(&nonce);
+
+        // All chunks but the last are full, the last one may be empty.
+        let last = data.len().saturating_sub(1) / CHUNK_SIZE * CHUNK_SIZE;
+        for chunk in data[..last].chunks(CHUNK_SIZE) {
+            out.extend(
+                encryptor
+                    .encrypt_next(payload(chunk, path))
+                    .map_err(crypto_err)?,
+            );
+        }
+        out.extend(
+            encryptor
+                .encrypt_last(payload(&data[last..], path))
+                .map_err(crypto_err)?,
+        );
+
+        Ok(out.freeze())
+    }
+
+    /// Private helper to decrypt `data` read from `path`.
+    fn decrypt(&self, path: &str, data: &[u8]) -> Result<Bytes, StorageError> {
+        if data.len() < NONCE_SIZE + TAG_SIZE {
+            return Err(StorageError::Generic(format!(
+                "Encrypted object '{path}' is truncated"
+            )));
+        }
+
+        let (nonce, mut data) = data.split_at(NONCE_SIZE);
+        let mut decryptor = DecryptorBE32::<XChaCha20Poly1305>::new(&self.key, nonce.into());
+
+        let mut out = BytesMut::with_capacity(data.len());
+        while data.len() > CHUNK_SIZE + TAG_SIZE {
+            let (chunk, rest) = data.split_at(CHUNK_SIZE + TAG_SIZE);
+            out.extend(
+                decryptor
+                    .decrypt_next(payload(chunk, path))
+                    .map_err(crypto_err)?,
+            );
+            data = rest;
+        }
+        out.extend(
+            decryptor
+                .decrypt_last(payload(data, path))
+                .map_err(crypto_err)?,
+        );
+
+        Ok(out.freeze())
+    }
+}
+
+fn payload<'a>(msg: &'a [u8], path: &'a str) -> Payload<'a, 'a> {
+    Payload {
+        msg,
+        aad: path.as_bytes(),
+    }
+}
+
+fn crypto_err(_: chacha20poly1305::aead::Error) -> StorageError {
+    StorageError::Generic("Encryption error: object is corrupted or the key is wrong".into())
+}
+
+/// Size of the stored object for a plaintext of `len` bytes.
+fn encrypted_len(len: u64) -> u64 {
+    let chunks = len.div_ceil(CHUNK_SIZE as u64).max(1);
+    NONCE_SIZE as u64 + len + chunks * TAG_SIZE as u64
+}
+
+/// Size of the plaintext of a stored object of `len` bytes, the inverse of [`encrypted_len`].
+fn decrypted_len(len: u64) -> u64 {
+    let sealed = len.saturating_sub(NONCE_SIZE as u64);
+    let chunks = sealed.div_ceil((CHUNK_SIZE + TAG_SIZE) as u64);
+    sealed.saturating_sub(chunks * TAG_SIZE as u64)
+}
+
+type ByteStream = Pin<Box<dyn Stream<Item = Result<Bytes, std::io::Error>> + Send>>;
+
+struct EncryptState {
+    input: ByteStream,
+    encryptor: Option<EncryptorBE32<XChaCha20Poly1305>>,
+    header: Option<Bytes>,
+    buffer: BytesMut,
+    path: String,
+}
+
+/// Encrypts a stream chunk by chunk, so only one chunk is buffered at a time.
+fn encrypt_stream(
+    input: ByteStream,
+    encryptor: EncryptorBE32<XChaCha20Poly1305>,
+    nonce: [u8; NONCE_SIZE],
+    path: &str,
+) -> ByteStream {
+    let state = EncryptState {
+        input,
+        encryptor: Some(encryptor),
+        header: Some(Bytes::copy_from_slice(&nonce)),
+        buffer: BytesMut::new(),
+        path: path.to_string(),
+    };
+
+    Box::pin(stream::unfold(state, |mut state| async move {
+        if let Some(header) = state.header.take() {
+            return Some((Ok(header), state));
+        }
+
+        loop {
+            let encryptor = state.encryptor.as_mut()?;
+
+            // Hold back a full chunk until more data arrives, the last one is encrypted differently.
+            if state.buffer.len() > CHUNK_SIZE {
+                let chunk = state.buffer.split_to(CHUNK_SIZE);
+                let res = encryptor
+                    .encrypt_next(payload(&chunk, &state.path))
+                    .map(Bytes::from)
+                    .map_err(|e| std::io::Error::other(crypto_err(e)));
+                return Some((res, state));
+            }
+
+            match state.input.next().await {
+                Some(Ok(data)) => state.buffer.extend_from_slice(&data),
+                Some(Err(e)) => {
+                    state.encryptor = None;
+                    return Some((Err(e), state));
+                }
+                None => {
+                    let encryptor = state.encryptor.take()?;
+                    let res = encryptor
+                        .encrypt_last(payload(&state.buffer, &state.path))
+                        .map(Bytes::from)
+                        .map_err(|e| std::io::Error::other(crypto_err(e)));
+                    return Some((res, state));
+                }
+            }
+        }
+    }))
+}
+
+impl<S: StorageBackend> StorageBackend for EncryptedStorage<S> {
+    async fn write_blob(&self, hash: &str, data: Bytes) -> Result<bool, StorageError> {
+        if self.inner.exists(hash).await? {
+            return Ok(false);
+        }
+
+        let data = self.encrypt(hash, &data)?;
+        self.inner.write_blob(hash, data).await
+    }
+
+    async fn write_stream(
+        &self,
+        hash: &str,
+        stream: ByteStream,
+        content_length: Option<u64>,
+    ) -> Result<bool, StorageError> {
+        if self.inner.exists(hash).await? {
+            return Ok(false);
+        }
+
+        let (encryptor, nonce) = self.encryptor();
+        let stream = encrypt_stream(stream, encryptor, nonce, hash);
+
+        self.inner
+            .write_stream(hash, stream, content_length.map(encrypted_len))
+            .await
+    }
+
+    async fn put_object(&self, path: &str, data: Bytes) -> Result<(), StorageError> {
+        let data = self.encrypt(path, &data)?;
+        self.inner.put_object(path, data).await
+    }
+
+    async fn write_manifest(&self, version: &str, data: Bytes) -> Result<(), StorageError> {
+        let path = self.inner.get_manifest_path(version);
+        let data = self.encrypt(&path, &data)?;
+        self.inner.write_manifest(version, data).await
+    }
+
+    async fn read_file(&self, path: &str) -> Result<Bytes, StorageError> {
+        let data = self.inner.read_file(path).await?;
+        self.decrypt(path, &data)
+    }
+
+    async fn exists(&self, path: &str) -> Result<bool, StorageError> {
+        self.inner.exists(path).await
+    }
+
+    /// Reports the plaintext size, the modification time is the one of the stored object.
+    async fn stat(&self, path: &str) -> Result<ObjectMeta, StorageError> {
+        let meta = self.inner.stat(path).await?;
+        Ok(ObjectMeta {
+            size: decrypted_len(meta.size),
+            ..meta
+        })
+    }
+
+    fn get_manifest_path(&self, version: &str) -> String {
+        self.inner.get_manifest_path(version)
+    }
+
+    fn capabilities(&self) -> StorageCapabilities {
+        // Stored objects are ciphertext, so they can't be downloaded directly.
+        StorageCapabilities {
+            download_urls: false,
+            ..self.inner.capabilities()
+        }
+    }
+
+    async fn list_blobs(&self) -> Result<Vec<BlobInfo>, StorageError> {
+        self.inner.list_blobs().await
+    }
+
+    async fn list_manifests(&self) -> Result<Vec<String>, StorageError> {
+        self.inner.list_manifests().await
+    }
+
+    async fn list_blobs_page(
+        &self,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<Page<BlobInfo>, StorageError> {
+        self.inner.list_blobs_page(cursor, limit).await
+    }
+
+    async fn list_manifests_page(
+        &self,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<Page<String>, StorageError> {
+        self.inner.list_manifests_page(cursor, limit).await
+    }
+
+    async fn delete_file(&self, path: &str) -> Result<(), StorageError> {
+        self.inner.delete_file(path).await
+    }
+
+    fn with_context(&self, ctx: &RequestContext) -> Self {
+        Self {
+            inner: self.inner.with_context(ctx),
+            key: self.key,
+        }
+    }
+}
@@ -6,49 +6,291 @@
 //! A storage backend powered by the local filesystem.
 //!
 //! Uses atomic writes to ensure assets are not read partially or lost during upload.
+//! Temp files of failed or cancelled writes are removed right away, those left behind by a crash
+//! can be removed with [`FileSystemStorage::cleanup_temp`].
 //!
 //! ## Usage
 //!
 //! ```no_run
 //! use aquila_fs::FileSystemStorage;
 //!
-//! let storage = FileSystemStorage::new("./aquila_data");
+//! let storage = FileSystemStorage::new("./aquila_data")
+//!     // Optional: Store blob `abcdef...` at `ab/cd/abcdef...` to keep directories small
+//!     .with_sharding(2);
 //! ```
+//!
+//! ## Sharding
+//!
+//! By default all blobs are stored flat in the root directory, which gets slow with
+//! hundreds of thousands of files. [`FileSystemStorage::with_sharding`] fans them out into
+//! nested directories instead. Existing flat blobs can be moved with
+//! [`FileSystemStorage::migrate_to_sharded`].
+//!
+//! ## Durability
+//!
+//! Manifests are always `fsync`ed before and after the rename, so a crash can't lose a
+//! published version. Blobs skip this by default, as syncing every upload is expensive and
+//! a lost blob can simply be uploaded again, see [`FileSystemStorage::with_durability`].
 
+use aquila_core::compression::{compress_manifest, decompress_manifest};
 use aquila_core::prelude::*;
 use bytes::Bytes;
 use futures::{Stream, StreamExt};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::time::{Duration, SystemTime};
 use tokio::fs;
+use tokio::io::AsyncWriteExt;
 
-async fn atomic_write(path: &std::path::Path, data: Bytes) -> Result<(), StorageError> {
+async fn atomic_write(path: &Path, data: Bytes, durable: bool) -> Result<(), StorageError> {
     if let Some(parent) = path.parent() {
         fs::create_dir_all(parent).await.map_err(StorageError::Io)?;
     }
 
-    let tmp_path = path.with_extension("tmp");
+    let tmp = TempFile::new(path);
+    let tmp_path = &tmp.path;
 
-    fs::write(&tmp_path, data).await.map_err(StorageError::Io)?;
-    fs::rename(&tmp_path, path)
-        .await
-        .map_err(StorageError::Io)?;
+    let mut file = fs::File::create(tmp_path).await.map_err(StorageError::Io)?;
+    file.write_all(&data).await.map_err(StorageError::Io)?;
 
+    commit(file, tmp_path, path, durable).await?;
+    tmp.keep();
     Ok(())
 }
 
+/// Returns a unique temp path next to `path`, so concurrent writes of the same file don't collide.
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}.tmp", uuid::Uuid::new_v4()));
+    path.with_file_name(name)
+}
+
+/// Removes the temp file when dropped, unless it was moved into place, so a failed write or
+/// a cancelled upload doesn't leave a partial file behind.
+struct TempFile {
+    path: PathBuf,
+    committed: bool,
+}
+
+impl TempFile {
+    fn new(path: &Path) -> Self {
+        Self {
+            path: tmp_path(path),
+            committed: false,
+        }
+    }
+
+    /// Call once the file was renamed.
+    fn keep(mut self) {
+        self.committed = true;
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Moves a fully written temp file into place.
+///
+/// If `durable`, the file is synced before the rename and the directory after it,
+/// otherwise a crash can leave a missing or empty file even though the rename succeeded.
+async fn commit(
+    mut file: fs::File,
+    tmp_path: &Path,
+    path: &Path,
+    durable: bool,
+) -> Result<(), StorageError> {
+    file.flush().await.map_err(StorageError::Io)?;
+    if durable {
+        file.sync_all().await.map_err(StorageError::Io)?;
+    }
+    drop(file);
+
+    fs::rename(tmp_path, path).await.map_err(StorageError::Io)?;
+
+    // Directories can't be opened for syncing on Windows, renames are durable there.
+    #[cfg(unix)]
+    if durable && let Some(parent) = path.parent() {
+        fs::File::open(parent)
+            .await
+            .map_err(StorageError::Io)?
+            .sync_all()
+            .await
+            .map_err(StorageError::Io)?;
+    }
+
+    Ok(())
+}
+
+/// Lists the entries directly inside `dir`, a missing directory is empty.
+async fn list_entries(dir: &Path) -> Result<Vec<(String, std::fs::Metadata)>, StorageError> {
+    let mut entries = match fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(StorageError::Io(e)),
+    };
+
+    let mut list = Vec::new();
+    while let Some(entry) = entries.next_entry().await.map_err(StorageError::Io)? {
+        let metadata = entry.metadata().await.map_err(StorageError::Io)?;
+        if let Some(name) = entry.file_name().to_str() {
+            list.push((name.to_string(), metadata));
+        }
+    }
+    Ok(list)
+}
+
+/// Returns `true` if `name` is a shard directory, i.e. two hex characters.
+fn is_shard(name: &str) -> bool {
+    name.len() == 2 && name.chars().all(|c| c.is_ascii_hexdigit())
+}
+
 #[derive(Clone)]
 pub struct FileSystemStorage {
     root: PathBuf,
+    /// Number of nested shard directories for blobs, `0` is flat.
+    shard_depth: usize,
+    /// If set, blobs are synced to disk before a write returns.
+    durable_blobs: bool,
+    /// If set, manifests are stored gzipped.
+    compress_manifests: bool,
 }
 
 impl FileSystemStorage {
     pub fn new(path: impl Into<PathBuf>) -> Self {
-        Self { root: path.into() }
+        Self {
+            root: path.into(),
+            shard_depth: 0,
+            durable_blobs: false,
+            compress_manifests: false,
+        }
+    }
+
+    /// Sync blobs to disk (`fsync` of the file and its directory) before a write returns.
+    ///
+    /// Off by default: syncing makes every upload noticeably slower, and a blob lost in a crash
+    /// can be uploaded again. Manifests are always synced.
+    pub fn with_durability(mut self, durable: bool) -> Self {
+        self.durable_blobs = durable;
+        self
+    }
+
+    /// Store manifests gzipped, they are decompressed transparently when read.
+    ///
+    /// Blobs are never compressed. Manifests written before enabling this are still read, but
+    /// compressed manifests are only decompressed while this is enabled.
+    pub fn with_compressed_manifests(mut self, compress: bool) -> Self {
+        self.compress_manifests = compress;
+        self
+    }
+
+    /// Store blobs in `depth` nested directories named by pairs of hash characters,
+    /// e.g. with a depth of 2 blob `abcdef...` is stored at `ab/cd/abcdef...`.
+    ///
+    /// Flat blobs written before enabling this must be moved with
+    /// [`migrate_to_sharded`](Self::migrate_to_sharded), or they won't be found.
+    ///
+    /// The depth is capped at 8.
+    pub fn with_sharding(mut self, depth: usize) -> Self {
+        self.shard_depth = depth.min(8);
+        self
     }
 
     fn get_path(&self, path: &str) -> PathBuf {
-        self.root.join(path)
+        if self.shard_depth == 0 || !is_blob_hash(path) {
+            return self.root.join(path);
+        }
+
+        let mut full = self.root.clone();
+        for level in 0..self.shard_depth {
+            full.push(&path[level * 2..level * 2 + 2]);
+        }
+        full.push(path);
+        full
+    }
+
+    /// Removes temp files older than `max_age`, left behind by interrupted writes.
+    ///
+    /// Returns the number of removed files. Useful on startup, `max_age` should be longer than
+    /// the slowest upload, as temp files of writes still in progress are removed as well.
+    ///
+    /// ```
+    /// # use aquila_fs::FileSystemStorage;
+    /// # use std::time::Duration;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let root = std::env::temp_dir().join("aquila_fs_cleanup_doctest");
+    /// let storage = FileSystemStorage::new(&root);
+    ///
+    /// // Simulate a write that was interrupted before the rename.
+    /// std::fs::create_dir_all(root.join("manifests")).unwrap();
+    /// let orphan = root.join("manifests/v1.0.3f2a.tmp");
+    /// std::fs::write(&orphan, b"partial").unwrap();
+    ///
+    /// assert_eq!(storage.cleanup_temp(Duration::ZERO).await.unwrap(), 1);
+    /// assert!(!orphan.exists());
+    /// # std::fs::remove_dir_all(&root).unwrap();
+    /// # }
+    /// ```
+    pub async fn cleanup_temp(&self, max_age: Duration) -> Result<usize, StorageError> {
+        let now = SystemTime::now();
+        let mut removed = 0;
+
+        let mut dirs = vec![self.root.clone()];
+        while let Some(dir) = dirs.pop() {
+            for (name, metadata) in list_entries(&dir).await? {
+                if metadata.is_dir() {
+                    dirs.push(dir.join(name));
+                    continue;
+                }
+
+                let age = metadata
+                    .modified()
+                    .ok()
+                    .and_then(|modified| now.duration_since(modified).ok())
+                    .unwrap_or_default();
+
+                if name.ends_with(".tmp") && age >= max_age {
+                    fs::remove_file(dir.join(name))
+                        .await
+                        .map_err(StorageError::Io)?;
+                    removed += 1;
+                }
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Moves blobs stored in the flat layout into their shard directories.
+    ///
+    /// Returns the number of moved blobs, does nothing if sharding is disabled.
+    pub async fn migrate_to_sharded(&self) -> Result<usize, StorageError> {
+        if self.shard_depth == 0 {
+            return Ok(0);
+        }
+
+        let mut moved = 0;
+        for (name, metadata) in list_entries(&self.root).await? {
+            if !metadata.is_file() || !is_blob_hash(&name) {
+                continue;
+            }
+
+            let target = self.get_path(&name);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent).await.map_err(StorageError::Io)?;
+            }
+            fs::rename(self.root.join(&name), &target)
+                .await
+                .map_err(StorageError::Io)?;
+            moved += 1;
+        }
+
+        Ok(moved)
     }
 }
 
@@ -58,7 +300,7 @@ impl StorageBackend for FileSystemStorage {
         if path.exists() {
             return Ok(false);
         }
-        atomic_write(&path, data).await?;
+        atomic_write(&path, data, self.durable_blobs).await?;
         Ok(true)
     }
 
@@ -76,47 +318,125 @@ impl StorageBackend for FileSystemStorage {
         if let Some(parent) = path.parent() {
             fs::create_dir_all(parent).await.map_err(StorageError::Io)?;
         }
-        let tmp_path = path.with_extension("tmp");
+        let tmp = TempFile::new(&path);
+        let tmp_path = &tmp.path;
 
-        let mut file = fs::File::create(&tmp_path)
-            .await
-            .map_err(StorageError::Io)?;
+        let mut file = fs::File::create(tmp_path).await.map_err(StorageError::Io)?;
 
-        use tokio::io::AsyncWriteExt;
         while let Some(res) = stream.next().await {
             let chunk = res.map_err(StorageError::Io)?;
             file.write_all(&chunk).await.map_err(StorageError::Io)?;
         }
-        file.flush().await.map_err(StorageError::Io)?;
-
-        fs::rename(&tmp_path, path)
-            .await
-            .map_err(StorageError::Io)?;
 
+        commit(file, tmp_path, &path, self.durable_blobs).await?;
+        tmp.keep();
         Ok(true)
     }
 
+    async fn put_object(&self, path: &str, data: Bytes) -> Result<(), StorageError> {
+        let path = self.root.join(path);
+        atomic_write(&path, data, true).await
+    }
+
     async fn write_manifest(&self, version: &str, data: Bytes) -> Result<(), StorageError> {
         let path = self.get_path(&self.get_manifest_path(version));
-        atomic_write(&path, data).await?;
+        let data = if self.compress_manifests {
+            compress_manifest(&data)?
+        } else {
+            data
+        };
+        atomic_write(&path, data, true).await?;
         Ok(())
     }
 
     async fn read_file(&self, path: &str) -> Result<Bytes, StorageError> {
-        let path = self.get_path(path);
-        match fs::read(&path).await {
-            Ok(data) => Ok(Bytes::from(data)),
+        let full_path = self.get_path(path);
+        let data = match fs::read(&full_path).await {
+            Ok(data) => Bytes::from(data),
             Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-                Err(StorageError::NotFound(path.to_string_lossy().to_string()))
+                return Err(StorageError::NotFound(
+                    full_path.to_string_lossy().to_string(),
+                ));
             }
-            Err(e) => Err(StorageError::Io(e)),
+            Err(e) => return Err(StorageError::Io(e)),
+        };
+
+        if self.compress_manifests && path.starts_with(&self.get_manifest_path("")) {
+            return decompress_manifest(data);
         }
+        Ok(data)
     }
 
     async fn exists(&self, path: &str) -> Result<bool, StorageError> {
         Ok(self.get_path(path).exists())
     }
 
+    async fn stat(&self, path: &str) -> Result<ObjectMeta, StorageError> {
+        let full_path = self.get_path(path);
+        let metadata = match fs::metadata(&full_path).await {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(StorageError::NotFound(
+                    full_path.to_string_lossy().to_string(),
+                ));
+            }
+            Err(e) => return Err(StorageError::Io(e)),
+        };
+
+        Ok(ObjectMeta {
+            size: metadata.len(),
+            last_modified: metadata.modified().ok(),
+        })
+    }
+
+    fn capabilities(&self) -> StorageCapabilities {
+        StorageCapabilities {
+            streaming: true,
+            download_urls: false,
+            listing: true,
+            objects: true,
+            tenants: false,
+        }
+    }
+
+    async fn list_blobs(&self) -> Result<Vec<BlobInfo>, StorageError> {
+        let mut dirs = vec![self.root.clone()];
+        for _ in 0..self.shard_depth {
+            let mut shards = Vec::new();
+            for dir in dirs {
+                for (name, metadata) in list_entries(&dir).await? {
+                    if metadata.is_dir() && is_shard(&name) {
+                        shards.push(dir.join(name));
+                    }
+                }
+            }
+            dirs = shards;
+        }
+
+        let mut blobs = Vec::new();
+        for dir in dirs {
+            for (name, metadata) in list_entries(&dir).await? {
+                if metadata.is_file() && is_blob_hash(&name) {
+                    blobs.push(BlobInfo {
+                        hash: name,
+                        size: metadata.len(),
+                    });
+                }
+            }
+        }
+        Ok(blobs)
+    }
+
+    async fn list_manifests(&self) -> Result<Vec<String>, StorageError> {
+        let dir = self.get_path(&self.get_manifest_path(""));
+        Ok(list_entries(&dir)
+            .await?
+            .into_iter()
+            .filter(|(name, metadata)| metadata.is_file() && !name.ends_with(".tmp"))
+            .map(|(name, _)| name)
+            .collect())
+    }
+
     async fn delete_file(&self, path: &str) -> Result<(), StorageError> {
         let path = self.get_path(path);
         if path.exists() {
@@ -0,0 +1,41 @@
+//! Asserts that manifests round trip with and without `with_compressed_manifests`.
+
+use aquila_core::compression::MARKER;
+use aquila_core::prelude::*;
+use aquila_fs::FileSystemStorage;
+use bytes::Bytes;
+
+/// Manifest data starting with the gzip magic, e.g. the nonce of an encrypted manifest.
+const GZIP_LIKE: &[u8] = &[0x1f, 0x8b, 0x08, 0x00, 0x42];
+
+#[tokio::test]
+async fn compressed_manifests() {
+    let root = std::env::temp_dir().join(format!("aquila_fs_compression_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    let json = Bytes::from_static(br#"{"version":"v1"}"#);
+
+    for compress in [false, true] {
+        let storage = FileSystemStorage::new(&root).with_compressed_manifests(compress);
+        let path = storage.get_manifest_path("v1");
+
+        storage.write_manifest("v1", json.clone()).await.unwrap();
+        assert_eq!(storage.read_file(&path).await.unwrap(), json);
+        let stored = std::fs::read(root.join(&path)).unwrap();
+        assert_eq!(stored.starts_with(MARKER), compress);
+
+        storage
+            .write_manifest("v1", Bytes::from_static(GZIP_LIKE))
+            .await
+            .unwrap();
+        assert_eq!(storage.read_file(&path).await.unwrap(), GZIP_LIKE);
+    }
+
+    // Manifests written before compression was enabled are still read.
+    let plain = FileSystemStorage::new(&root);
+    plain.write_manifest("v2", json.clone()).await.unwrap();
+    let compressed = FileSystemStorage::new(&root).with_compressed_manifests(true);
+    let path = compressed.get_manifest_path("v2");
+    assert_eq!(compressed.read_file(&path).await.unwrap(), json);
+
+    let _ = std::fs::remove_dir_all(&root);
+}
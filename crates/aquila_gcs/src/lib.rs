@@ -0,0 +1,554 @@
+//! # Aquila GCS
+//! [![Crates.io](https://img.shields.io/crates/v/aquila_gcs.svg)](https://crates.io/crates/aquila_gcs)
+//! [![Downloads](https://img.shields.io/crates/d/aquila_gcs.svg)](https://crates.io/crates/aquila_gcs)
+//! [![Docs](https://docs.rs/aquila_gcs/badge.svg)](https://docs.rs/aquila_gcs/)
+//!
+//! A storage backend powered by [Google Cloud Storage](https://cloud.google.com/storage).
+//!
+//! A lean alternative to the OpenDAL GCS service. Streams are uploaded using
+//! **resumable uploads** and downloads can be served via **Signed URLs**.
+//!
+//! ## Configuration
+//!
+//! Uses the standard Google credentials lookup (e.g., `GOOGLE_APPLICATION_CREDENTIALS`).
+//! Signed URLs require service account credentials.
+//!
+//! ## Usage
+//!
+//! ```no_run
+//! # use aquila_gcs::GcsStorage;
+//! # use google_cloud_storage::client::{Client, ClientConfig};
+//! # use std::time::Duration;
+//! # async fn run() {
+//! let config = ClientConfig::default().with_auth().await.unwrap();
+//! let client = Client::new(config);
+//!
+//! let storage = GcsStorage::new(client, "my-game-assets".to_string())
+//!     // Optional: Prefix for organizing data in a shared bucket
+//!     .with_prefix("production/")
+//!     // Optional: Enable Signed URLs (Direct GCS Download)
+//!     .with_presigning(Duration::from_secs(300));
+//! # }
+//! ```
+
+use aquila_core::compression::{compress_manifest, decompress_manifest};
+use aquila_core::prelude::*;
+use bytes::{Bytes, BytesMut};
+use futures::{Stream, StreamExt};
+use google_cloud_storage::client::{Client, ClientConfig};
+use google_cloud_storage::http::Error as GcsError;
+use google_cloud_storage::http::objects::delete::DeleteObjectRequest;
+use google_cloud_storage::http::objects::download::Range;
+use google_cloud_storage::http::objects::get::GetObjectRequest;
+use google_cloud_storage::http::objects::list::ListObjectsRequest;
+use google_cloud_storage::http::objects::upload::{Media, UploadObjectRequest, UploadType};
+use google_cloud_storage::http::resumable_upload_client::{ChunkSize, UploadStatus};
+use google_cloud_storage::sign::SignedURLOptions;
+use std::pin::Pin;
+use std::time::{Duration, SystemTime};
+use tracing::{debug, error, instrument};
+
+/// Size of a resumable upload chunk, must be a multiple of 256 KiB.
+const CHUNK_SIZE: usize = 32 * 256 * 1024;
+
+#[derive(Clone)]
+pub struct GcsStorage {
+    client: Client,
+    bucket: String,
+    prefix: String,
+    /// If set, generate signed URLs for this duration.
+    presign_duration: Option<Duration>,
+    /// If set, manifests are stored gzipped.
+    compress_manifests: bool,
+}
+
+impl GcsStorage {
+    pub fn new(client: Client, bucket: String) -> Self {
+        Self {
+            client,
+            bucket,
+            prefix: Default::default(),
+            presign_duration: None,
+            compress_manifests: false,
+        }
+    }
+
+    /// Creates a client using the standard Google credentials lookup.
+    pub async fn from_env(bucket: String) -> Result<Self, StorageError> {
+        let config = ClientConfig::default()
+            .with_auth()
+            .await
+            .map_err(|e| StorageError::Generic(format!("Google credentials: {e}")))?;
+        Ok(Self::new(Client::new(config), bucket))
+    }
+
+    /// Store manifests gzipped, they are decompressed transparently when read.
+    ///
+    /// Blobs are never compressed. Manifests written before enabling this are still read, but
+    /// compressed manifests are only decompressed while this is enabled.
+    pub fn with_compressed_manifests(mut self, compress: bool) -> Self {
+        self.compress_manifests = compress;
+        self
+    }
+
+    /// Enable signed URLs (e.g. 5 minutes)
+    pub fn with_presigning(mut self, duration: Duration) -> Self {
+        self.presign_duration = Some(duration);
+        self
+    }
+
+    /// Set a prefix for organizing data in a shared bucket.
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Private helper to create a key from a path. Adds the prefix if set.
+    fn key(&self, path: &str) -> String {
+        format!("{}{path}", self.prefix)
+    }
+
+    fn upload_request(&self) -> UploadObjectRequest {
+        UploadObjectRequest {
+            bucket: self.bucket.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Private helper to list the objects directly below a path, with paths and sizes.
+    async fn list(&self, path: &str) -> Result<Vec<(String, u64)>, StorageError> {
+        let mut objects = Vec::new();
+        let mut token = None;
+        loop {
+            let (page, next_token) = self.list_page(path, token, None).await?;
+            objects.extend(page);
+
+            match next_token {
+                Some(next_token) => token = Some(next_token),
+                None => break,
+            }
+        }
+
+        Ok(objects)
+    }
+
+    /// Private helper to list one page of objects directly below a path, with the page token
+    /// of the next page.
+    async fn list_page(
+        &self,
+        path: &str,
+        page_token: Option<String>,
+        max_results: Option<i32>,
+    ) -> Result<(Vec<(String, u64)>, Option<String>), StorageError> {
+        let prefix = self.key(path);
+        let req = ListObjectsRequest {
+            bucket: self.bucket.clone(),
+            prefix: Some(prefix.clone()),
+            delimiter: Some("/".to_string()),
+            page_token,
+            max_results,
+            ..Default::default()
+        };
+
+        let page = self.client.list_objects(&req).await.map_err(|e| {
+            error!("Failed to list objects: {e:?}");
+            StorageError::Generic(format!("GCS List Error: {e}"))
+        })?;
+
+        let objects = page
+            .items
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|object| {
+                let name = object.name.strip_prefix(&prefix)?;
+                Some((name.to_string(), object.size.max(0) as u64))
+            })
+            .collect();
+
+        Ok((objects, page.next_page_token))
+    }
+
+    /// Private helper to check existence.
+    async fn exists(&self, key: &str) -> Result<bool, StorageError> {
+        let req = GetObjectRequest {
+            bucket: self.bucket.clone(),
+            object: key.to_string(),
+            ..Default::default()
+        };
+
+        match self.client.get_object(&req).await {
+            Ok(_) => {
+                debug!("Blob already exists in GCS");
+                Ok(true)
+            }
+            Err(GcsError::Response(err)) if err.code == 404 => Ok(false),
+            Err(err) => Err(StorageError::Generic(format!(
+                "GCS Get Object Error: {err}"
+            ))),
+        }
+    }
+
+    /// Private helper to sign a download URL, optionally as an attachment with a filename.
+    async fn sign(
+        &self,
+        key: &str,
+        filename: Option<&str>,
+    ) -> Result<Option<String>, StorageError> {
+        let Some(expires) = self.presign_duration else {
+            return Ok(None);
+        };
+
+        let mut opts = SignedURLOptions {
+            expires,
+            ..Default::default()
+        };
+
+        if let Some(name) = filename {
+            opts.query_parameters.insert(
+                "response-content-disposition".to_string(),
+                vec![format!("attachment; filename=\"{name}\"")],
+            );
+        }
+
+        let url = self
+            .client
+            .signed_url(&self.bucket, key, None, None, opts)
+            .await
+            .map_err(|e| {
+                error!("Failed to sign URL: {e:?}");
+                StorageError::Generic(format!("GCS Sign Error: {e}"))
+            })?;
+
+        Ok(Some(url))
+    }
+
+    async fn put(&self, key: String, data: Bytes) -> Result<(), GcsError> {
+        self.client
+            .upload_object(
+                &self.upload_request(),
+                data,
+                &UploadType::Simple(Media::new(key)),
+            )
+            .await
+            .map(|_| ())
+    }
+
+    /// Uploads the stream in chunks using a resumable upload session.
+    ///
+    /// The last chunk is always held back, so the total size is known when the upload is finalized.
+    async fn upload_resumable(
+        &self,
+        key: String,
+        mut stream: Pin<Box<dyn Stream<Item = Result<Bytes, std::io::Error>> + Send>>,
+        content_length: Option<u64>,
+    ) -> Result<(), StorageError> {
+        let uploader = self
+            .client
+            .prepare_resumable_upload(&self.upload_request(), &UploadType::Simple(Media::new(key)))
+            .await
+            .map_err(|e| StorageError::Generic(format!("GCS Resumable Upload Error: {e}")))?;
+
+        let upload = async {
+            let mut buffer = BytesMut::new();
+            let mut offset = 0u64;
+
+            while let Some(res) = stream.next().await {
+                buffer.extend_from_slice(&res?);
+
+                while buffer.len() > CHUNK_SIZE {
+                    let chunk = buffer.split_to(CHUNK_SIZE).freeze();
+                    let last_byte = offset + chunk.len() as u64 - 1;
+                    uploader
+                        .upload_multiple_chunk(
+                            chunk,
+                            &ChunkSize::new(offset, last_byte, content_length),
+                        )
+                        .await
+                        .map_err(|e| {
+                            StorageError::Generic(format!("GCS Chunk Upload Error: {e}"))
+                        })?;
+                    offset = last_byte + 1;
+                }
+            }
+
+            let total = offset + buffer.len() as u64;
+            if total == 0 {
+                return uploader
+                    .upload_single_chunk(Vec::<u8>::new(), 0)
+                    .await
+                    .map_err(|e| StorageError::Generic(format!("GCS Upload Error: {e}")));
+            }
+
+            let status = uploader
+                .upload_multiple_chunk(
+                    buffer.freeze(),
+                    &ChunkSize::new(offset, total - 1, Some(total)),
+                )
+                .await
+                .map_err(|e| StorageError::Generic(format!("GCS Chunk Upload Error: {e}")))?;
+
+            match status {
+                UploadStatus::Ok(_) => Ok(()),
+                status => Err(StorageError::Generic(format!(
+                    "GCS resumable upload incomplete: {status:?}"
+                ))),
+            }
+        };
+
+        let res = upload.await;
+        if res.is_err()
+            && let Err(e) = uploader.cancel().await
+        {
+            error!("Failed to cancel resumable upload: {e}");
+        }
+
+        res
+    }
+}
+
+impl StorageBackend for GcsStorage {
+    #[instrument(skip(self, data), fields(bucket = %self.bucket, key))]
+    async fn write_blob(&self, hash: &str, data: Bytes) -> Result<bool, StorageError> {
+        let key = self.key(hash);
+        tracing::Span::current().record("key", &key);
+
+        if self.exists(&key).await? {
+            return Ok(false);
+        }
+
+        debug!("Uploading to GCS...");
+        self.put(key, data).await.map_err(|e| {
+            error!("Failed to upload blob: {e:?}");
+            StorageError::Generic(format!("GCS Upload Error: {e}"))
+        })?;
+
+        debug!("Upload successful");
+        Ok(true)
+    }
+
+    #[instrument(skip(self, stream), fields(bucket = %self.bucket, key))]
+    async fn write_stream(
+        &self,
+        hash: &str,
+        stream: Pin<Box<dyn Stream<Item = Result<Bytes, std::io::Error>> + Send>>,
+        content_length: Option<u64>,
+    ) -> Result<bool, StorageError> {
+        let key = self.key(hash);
+        tracing::Span::current().record("key", &key);
+
+        if self.exists(&key).await? {
+            return Ok(false);
+        }
+
+        debug!("Streaming to GCS...");
+        self.upload_resumable(key, stream, content_length)
+            .await
+            .inspect_err(|e| error!("Failed to upload stream: {e:?}"))?;
+
+        Ok(true)
+    }
+
+    #[instrument(skip(self, data), fields(bucket = %self.bucket, key))]
+    async fn put_object(&self, path: &str, data: Bytes) -> Result<(), StorageError> {
+        let key = self.key(path);
+        tracing::Span::current().record("key", &key);
+
+        self.put(key, data).await.map_err(|e| {
+            error!("Failed to put object: {e:?}");
+            StorageError::Generic(format!("GCS Put Object Error: {e}"))
+        })
+    }
+
+    #[instrument(skip(self, data), fields(bucket = %self.bucket, key))]
+    async fn write_manifest(&self, version: &str, data: Bytes) -> Result<(), StorageError> {
+        let key = self.key(&self.get_manifest_path(version));
+        tracing::Span::current().record("key", &key);
+
+        let data = if self.compress_manifests {
+            compress_manifest(&data)?
+        } else {
+            data
+        };
+
+        debug!("Uploading manifest...");
+        self.put(key, data).await.map_err(|e| {
+            error!("Failed to upload manifest: {e:?}");
+            StorageError::Generic(format!("GCS Manifest Upload Error: {e}"))
+        })
+    }
+
+    #[instrument(skip(self), fields(bucket = %self.bucket, key))]
+    async fn read_file(&self, path: &str) -> Result<Bytes, StorageError> {
+        let key = self.key(path);
+        tracing::Span::current().record("key", &key);
+
+        debug!("Reading file from GCS...");
+        let req = GetObjectRequest {
+            bucket: self.bucket.clone(),
+            object: key,
+            ..Default::default()
+        };
+
+        match self.client.download_object(&req, &Range::default()).await {
+            Ok(data)
+                if self.compress_manifests && path.starts_with(&self.get_manifest_path("")) =>
+            {
+                decompress_manifest(Bytes::from(data))
+            }
+            Ok(data) => Ok(Bytes::from(data)),
+            Err(GcsError::Response(err)) if err.code == 404 => {
+                debug!("File not found in GCS");
+                Err(StorageError::NotFound(path.to_string()))
+            }
+            Err(e) => {
+                error!("GCS Error during read: {e:?}");
+                Err(StorageError::Generic(format!("GCS Error: {e}")))
+            }
+        }
+    }
+
+    #[instrument(skip(self), fields(bucket = %self.bucket, key))]
+    async fn exists(&self, path: &str) -> Result<bool, StorageError> {
+        let key = self.key(path);
+        tracing::Span::current().record("key", &key);
+        self.exists(&key).await
+    }
+
+    #[instrument(skip(self), fields(bucket = %self.bucket, key))]
+    async fn stat(&self, path: &str) -> Result<ObjectMeta, StorageError> {
+        let key = self.key(path);
+        tracing::Span::current().record("key", &key);
+
+        let req = GetObjectRequest {
+            bucket: self.bucket.clone(),
+            object: key,
+            ..Default::default()
+        };
+
+        match self.client.get_object(&req).await {
+            Ok(object) => Ok(ObjectMeta {
+                size: object.size.max(0) as u64,
+                last_modified: object.updated.map(SystemTime::from),
+            }),
+            Err(GcsError::Response(err)) if err.code == 404 => {
+                Err(StorageError::NotFound(path.to_string()))
+            }
+            Err(err) => Err(StorageError::Generic(format!(
+                "GCS Get Object Error: {err}"
+            ))),
+        }
+    }
+
+    #[instrument(skip(self), fields(bucket = %self.bucket, key))]
+    async fn get_download_url(&self, path: &str) -> Result<Option<String>, StorageError> {
+        let key = self.key(path);
+        tracing::Span::current().record("key", &key);
+        self.sign(&key, None).await
+    }
+
+    #[instrument(skip(self), fields(bucket = %self.bucket, key))]
+    async fn get_download_url_as(
+        &self,
+        path: &str,
+        filename: &str,
+    ) -> Result<Option<String>, StorageError> {
+        let key = self.key(path);
+        tracing::Span::current().record("key", &key);
+        self.sign(&key, Some(filename)).await
+    }
+
+    fn capabilities(&self) -> StorageCapabilities {
+        StorageCapabilities {
+            streaming: true,
+            download_urls: self.presign_duration.is_some(),
+            listing: true,
+            objects: true,
+            tenants: false,
+        }
+    }
+
+    #[instrument(skip(self), fields(bucket = %self.bucket))]
+    async fn list_blobs(&self) -> Result<Vec<BlobInfo>, StorageError> {
+        Ok(self
+            .list("")
+            .await?
+            .into_iter()
+            .filter(|(name, _)| is_blob_hash(name))
+            .map(|(hash, size)| BlobInfo { hash, size })
+            .collect())
+    }
+
+    #[instrument(skip(self), fields(bucket = %self.bucket))]
+    async fn list_manifests(&self) -> Result<Vec<String>, StorageError> {
+        let path = self.get_manifest_path("");
+        Ok(self
+            .list(&path)
+            .await?
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect())
+    }
+
+    /// Uses the GCS page token as the cursor. Other objects at the root are skipped,
+    /// so a page can have fewer than `limit` blobs.
+    #[instrument(skip(self), fields(bucket = %self.bucket))]
+    async fn list_blobs_page(
+        &self,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<Page<BlobInfo>, StorageError> {
+        let max_results = i32::try_from(limit).unwrap_or(i32::MAX);
+        let (objects, next_cursor) = self
+            .list_page("", cursor.map(String::from), Some(max_results))
+            .await?;
+
+        Ok(Page {
+            items: objects
+                .into_iter()
+                .filter(|(name, _)| is_blob_hash(name))
+                .map(|(hash, size)| BlobInfo { hash, size })
+                .collect(),
+            next_cursor,
+        })
+    }
+
+    /// Uses the GCS page token as the cursor.
+    #[instrument(skip(self), fields(bucket = %self.bucket))]
+    async fn list_manifests_page(
+        &self,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<Page<String>, StorageError> {
+        let path = self.get_manifest_path("");
+        let max_results = i32::try_from(limit).unwrap_or(i32::MAX);
+        let (objects, next_cursor) = self
+            .list_page(&path, cursor.map(String::from), Some(max_results))
+            .await?;
+
+        Ok(Page {
+            items: objects.into_iter().map(|(name, _)| name).collect(),
+            next_cursor,
+        })
+    }
+
+    #[instrument(skip(self), fields(bucket = %self.bucket, key))]
+    async fn delete_file(&self, path: &str) -> Result<(), StorageError> {
+        let key = self.key(path);
+        tracing::Span::current().record("key", &key);
+
+        let req = DeleteObjectRequest {
+            bucket: self.bucket.clone(),
+            object: key,
+            ..Default::default()
+        };
+
+        match self.client.delete_object(&req).await {
+            Ok(()) => Ok(()),
+            Err(GcsError::Response(err)) if err.code == 404 => Ok(()),
+            Err(e) => {
+                error!("Failed to delete file: {e:?}");
+                Err(StorageError::Generic(format!("GCS Delete Error: {e}")))
+            }
+        }
+    }
+}
@@ -0,0 +1,70 @@
+//! # Aquila Image
+//! [![Crates.io](https://img.shields.io/crates/v/aquila_image.svg)](https://crates.io/crates/aquila_image)
+//! [![Downloads](https://img.shields.io/crates/d/aquila_image.svg)](https://crates.io/crates/aquila_image)
+//! [![Docs](https://docs.rs/aquila_image/badge.svg)](https://docs.rs/aquila_image/)
+//!
+//! An [`AssetTransformer`] that resizes and converts images, powered by the
+//! [`image`](https://crates.io/crates/image) crate.
+//!
+//! Serves thumbnails and other variants of an image asset without storing every size,
+//! e.g. `GET /assets/{hash}/transform?w=128&format=webp`.
+//!
+//! Images are scaled down to fit within the requested width and height, keeping their
+//! aspect ratio, and never scaled up. If only one is given, the other follows from the
+//! aspect ratio.
+//! Supports PNG, JPEG, WebP and GIF.
+//!
+//! ## Usage
+//!
+//! ```no_run
+//! # use aquila_image::ImageResizer;
+//! # use aquila_server::prelude::*;
+//! # use std::sync::Arc;
+//! let config = AquilaServerConfig {
+//!     transformer: Some(Arc::new(ImageResizer)),
+//!     ..Default::default()
+//! };
+//! ```
+
+use aquila_core::prelude::*;
+use bytes::Bytes;
+use image::imageops::FilterType;
+use image::{ImageFormat, ImageReader};
+use std::io::Cursor;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ImageResizer;
+
+impl AssetTransformer for ImageResizer {
+    fn transform(&self, data: &[u8], params: &TransformParams) -> Result<Bytes, TransformError> {
+        let reader = ImageReader::new(Cursor::new(data))
+            .with_guessed_format()
+            .map_err(|e| TransformError::Generic(e.to_string()))?;
+
+        let format = match params.format.as_deref() {
+            Some(extension) => ImageFormat::from_extension(extension).ok_or_else(|| {
+                TransformError::InvalidParams(format!("Unknown image format '{extension}'"))
+            })?,
+            None => reader
+                .format()
+                .ok_or_else(|| TransformError::Unsupported("Not an image".into()))?,
+        };
+
+        let mut image = reader
+            .decode()
+            .map_err(|e| TransformError::Unsupported(e.to_string()))?;
+
+        let width = params.w.unwrap_or(u32::MAX);
+        let height = params.h.unwrap_or(u32::MAX);
+        if width < image.width() || height < image.height() {
+            image = image.resize(width, height, FilterType::Lanczos3);
+        }
+
+        let mut out = Cursor::new(Vec::new());
+        image
+            .write_to(&mut out, format)
+            .map_err(|e| TransformError::InvalidParams(e.to_string()))?;
+
+        Ok(Bytes::from(out.into_inner()))
+    }
+}
@@ -22,22 +22,54 @@
 //! # }
 //! ```
 
+use aquila_core::compression::{compress_manifest, decompress_manifest};
 use aquila_core::prelude::*;
 use bytes::Bytes;
 use futures::{Stream, StreamExt};
-use opendal::Operator;
+use opendal::{Operator, Writer};
 use std::pin::Pin;
+use std::time::SystemTime;
+use tracing::error;
 
 #[derive(Clone)]
 pub struct OpendalStorage {
     op: Operator,
+    /// If set, manifests are stored gzipped.
+    compress_manifests: bool,
 }
 
 impl OpendalStorage {
     /// Create a new storage from an OpenDAL Operator.
     /// The Operator can be configured for any supported backend e.g., s3, fs, gcs, etc.
     pub fn new(op: Operator) -> Self {
-        Self { op }
+        Self {
+            op,
+            compress_manifests: false,
+        }
+    }
+
+    /// Store manifests gzipped, they are decompressed transparently when read.
+    ///
+    /// Blobs are never compressed. Manifests written before enabling this are still read, but
+    /// compressed manifests are only decompressed while this is enabled.
+    pub fn with_compressed_manifests(mut self, compress: bool) -> Self {
+        self.compress_manifests = compress;
+        self
+    }
+
+    /// Private helper to list the files directly below a directory, with names and sizes.
+    async fn list(&self, dir: &str) -> Result<Vec<(String, u64)>, StorageError> {
+        let entries = match self.op.list(dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == opendal::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(StorageError::Generic(format!("OpenDAL List Error: {e}"))),
+        };
+
+        Ok(entries
+            .into_iter()
+            .filter(|entry| entry.metadata().is_file())
+            .map(|entry| (entry.name().to_string(), entry.metadata().content_length()))
+            .collect())
     }
 
     /// Private helper to check existence.
@@ -56,6 +88,50 @@ impl OpendalStorage {
     }
 }
 
+/// Aborts the writer unless it was closed, so a failed or cancelled upload doesn't leave
+/// a partial object or a pending multipart upload behind.
+struct AbortOnDrop(Option<Writer>);
+
+impl AbortOnDrop {
+    async fn write(&mut self, chunk: Bytes) -> opendal::Result<()> {
+        match &mut self.0 {
+            Some(writer) => writer.write(chunk).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn close(mut self) -> opendal::Result<()> {
+        if let Some(writer) = &mut self.0 {
+            writer.close().await?;
+            self.0 = None;
+        }
+        Ok(())
+    }
+
+    async fn abort(mut self) {
+        if let Some(mut writer) = self.0.take()
+            && let Err(e) = writer.abort().await
+        {
+            error!("Failed to abort write: {e}");
+        }
+    }
+}
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        // Can't await here, the abort of a cancelled upload runs in the background.
+        if let Some(mut writer) = self.0.take()
+            && let Ok(runtime) = tokio::runtime::Handle::try_current()
+        {
+            runtime.spawn(async move {
+                if let Err(e) = writer.abort().await {
+                    error!("Failed to abort cancelled write: {e}");
+                }
+            });
+        }
+    }
+}
+
 impl StorageBackend for OpendalStorage {
     async fn write_blob(&self, hash: &str, data: Bytes) -> Result<bool, StorageError> {
         let path = hash.to_string();
@@ -84,18 +160,25 @@ impl StorageBackend for OpendalStorage {
             return Ok(false);
         }
 
-        let mut writer = self
+        let writer = self
             .op
             .writer(&path)
             .await
             .map_err(|e| StorageError::Generic(format!("OpenDAL init error: {e}")))?;
+        let mut writer = AbortOnDrop(Some(writer));
 
         while let Some(res) = stream.next().await {
-            let chunk = res.map_err(StorageError::Io)?;
-            writer
-                .write(chunk)
-                .await
-                .map_err(|e| StorageError::Generic(format!("OpenDAL write error: {e}")))?;
+            let written = match res {
+                Ok(chunk) => writer
+                    .write(chunk)
+                    .await
+                    .map_err(|e| StorageError::Generic(format!("OpenDAL write error: {e}"))),
+                Err(e) => Err(StorageError::Io(e)),
+            };
+            if let Err(e) = written {
+                writer.abort().await;
+                return Err(e);
+            }
         }
 
         writer
@@ -106,9 +189,22 @@ impl StorageBackend for OpendalStorage {
         Ok(true)
     }
 
+    async fn put_object(&self, path: &str, data: Bytes) -> Result<(), StorageError> {
+        self.op
+            .write(path, data)
+            .await
+            .map_err(|e| StorageError::Generic(format!("OpenDAL Put Object Error: {e}")))?;
+
+        Ok(())
+    }
+
     async fn write_manifest(&self, version: &str, data: Bytes) -> Result<(), StorageError> {
         let path = self.get_manifest_path(version);
-        let data = data.clone();
+        let data = if self.compress_manifests {
+            compress_manifest(&data)?
+        } else {
+            data
+        };
 
         self.op
             .write(&path, data)
@@ -122,6 +218,11 @@ impl StorageBackend for OpendalStorage {
         let path = path.to_string();
 
         match self.op.read(&path).await {
+            Ok(buffer)
+                if self.compress_manifests && path.starts_with(&self.get_manifest_path("")) =>
+            {
+                decompress_manifest(buffer.to_bytes())
+            }
             Ok(buffer) => Ok(buffer.to_bytes()),
             Err(e) if e.kind() == opendal::ErrorKind::NotFound => Err(StorageError::NotFound(path)),
             Err(e) => Err(StorageError::Generic(e.to_string())),
@@ -132,6 +233,49 @@ impl StorageBackend for OpendalStorage {
         self.exists(path).await
     }
 
+    async fn stat(&self, path: &str) -> Result<ObjectMeta, StorageError> {
+        match self.op.stat(path).await {
+            Ok(metadata) => Ok(ObjectMeta {
+                size: metadata.content_length(),
+                last_modified: metadata.last_modified().map(SystemTime::from),
+            }),
+            Err(e) if e.kind() == opendal::ErrorKind::NotFound => {
+                Err(StorageError::NotFound(path.to_string()))
+            }
+            Err(e) => Err(StorageError::Generic(format!("OpenDAL Stat Error: {e}"))),
+        }
+    }
+
+    fn capabilities(&self) -> StorageCapabilities {
+        StorageCapabilities {
+            streaming: true,
+            download_urls: false,
+            listing: true,
+            objects: true,
+            tenants: false,
+        }
+    }
+
+    async fn list_blobs(&self) -> Result<Vec<BlobInfo>, StorageError> {
+        Ok(self
+            .list("")
+            .await?
+            .into_iter()
+            .filter(|(name, _)| is_blob_hash(name))
+            .map(|(hash, size)| BlobInfo { hash, size })
+            .collect())
+    }
+
+    async fn list_manifests(&self) -> Result<Vec<String>, StorageError> {
+        let path = self.get_manifest_path("");
+        Ok(self
+            .list(&path)
+            .await?
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect())
+    }
+
     async fn delete_file(&self, path: &str) -> Result<(), StorageError> {
         let path = path.to_string();
 
@@ -24,30 +24,36 @@
 //! let config = aws_config::load_from_env().await;
 //! let client = Client::new(&config);
 //!
-//! let storage = S3Storage::new(
-//!     client,
-//!     "my-game-assets".to_string(), // Bucket
-//!     Some("production/".to_string()) // Optional Prefix
-//! )
-//! // Optional: Enable Presigned URLs (Direct S3 Download)
-//! .with_presigning(Duration::from_secs(300));
+//! let storage = S3Storage::new(client, "my-game-assets".to_string())
+//!     // Optional: Prefix for organizing data in a shared bucket
+//!     .with_prefix("production/")
+//!     // Optional: Enable Presigned URLs (Direct S3 Download)
+//!     .with_presigning(Duration::from_secs(300))
+//!     // Optional: Clamp presigned URLs to the lifetime of temporary credentials
+//!     .with_credentials_provider(config.credentials_provider().unwrap());
 //! # }
 //! ```
 
+use aquila_core::compression::{compress_manifest, decompress_manifest};
 use aquila_core::prelude::*;
 use aws_sdk_s3::Client;
+use aws_sdk_s3::config::{ProvideCredentials, SharedCredentialsProvider};
 use aws_sdk_s3::error::SdkError;
 use aws_sdk_s3::presigning::PresigningConfig;
 use aws_sdk_s3::primitives::{ByteStream, SdkBody};
 use bytes::Bytes;
+use futures::future::try_join_all;
 use futures::{Stream, StreamExt, TryStreamExt};
 use http_body_util::StreamBody;
 use hyper::body::Frame;
 use std::pin::Pin;
 use std::task::{Context, Poll};
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 use tokio::sync::mpsc;
-use tracing::{debug, error, instrument};
+use tracing::{debug, error, instrument, warn};
+
+/// Number of concurrent HEAD requests made by [`StorageBackend::exists_many`].
+const EXISTS_MANY_CONCURRENCY: usize = 64;
 
 #[derive(Clone)]
 pub struct S3Storage {
@@ -56,6 +62,10 @@ pub struct S3Storage {
     prefix: String,
     /// If set, generate presigned URLs for this duration.
     presign_duration: Option<Duration>,
+    /// If set, presigned URLs never outlive the credentials.
+    credentials_provider: Option<SharedCredentialsProvider>,
+    /// If set, manifests are stored gzipped.
+    compress_manifests: bool,
 }
 
 struct ChannelStream(mpsc::Receiver<Result<Bytes, std::io::Error>>);
@@ -75,15 +85,46 @@ impl S3Storage {
             bucket,
             prefix: Default::default(),
             presign_duration: None,
+            credentials_provider: None,
+            compress_manifests: false,
         }
     }
 
+    /// Creates a client from the standard AWS environment variables and config files, and
+    /// clamps presigned URLs to the lifetime of its credentials.
+    pub async fn from_env(bucket: String) -> Self {
+        let config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+        let storage = Self::new(Client::new(&config), bucket);
+        match config.credentials_provider() {
+            Some(provider) => storage.with_credentials_provider(provider),
+            None => storage,
+        }
+    }
+
+    /// Store manifests gzipped, they are decompressed transparently when read.
+    ///
+    /// Blobs are never compressed. Manifests written before enabling this are still read, but
+    /// compressed manifests are only decompressed while this is enabled.
+    pub fn with_compressed_manifests(mut self, compress: bool) -> Self {
+        self.compress_manifests = compress;
+        self
+    }
+
     /// Enable presigned URLs (e.g. 5 minutes)
     pub fn with_presigning(mut self, duration: Duration) -> Self {
         self.presign_duration = Some(duration);
         self
     }
 
+    /// Set the credentials used by the client, so presigned URLs can be clamped to their expiry.
+    ///
+    /// Useful with temporary credentials (e.g., an assumed role), as a presigned URL stops
+    /// working once the credentials that signed it expire.
+    pub fn with_credentials_provider(mut self, provider: SharedCredentialsProvider) -> Self {
+        self.credentials_provider = Some(provider);
+        self
+    }
+
     /// Set a prefix for organizing data in a shared bucket.
     pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
         self.prefix = prefix.into();
@@ -98,6 +139,128 @@ impl S3Storage {
             .unwrap_or(format!("{}{path}", self.prefix))
     }
 
+    /// Private helper to resolve the presign duration, clamped to the remaining credential validity.
+    ///
+    /// Returns `None` if presigning is disabled or the credentials are already expired.
+    async fn presign_duration(&self) -> Result<Option<Duration>, StorageError> {
+        let Some(duration) = self.presign_duration else {
+            return Ok(None);
+        };
+
+        let Some(provider) = &self.credentials_provider else {
+            return Ok(Some(duration));
+        };
+
+        let credentials = provider
+            .provide_credentials()
+            .await
+            .map_err(|e| StorageError::Generic(format!("Failed to load credentials: {e}")))?;
+
+        let Some(expiry) = credentials.expiry() else {
+            return Ok(Some(duration));
+        };
+
+        match expiry.duration_since(SystemTime::now()) {
+            Ok(remaining) if !remaining.is_zero() => Ok(Some(duration.min(remaining))),
+            _ => {
+                warn!("Credentials expired, skipping presigned URL");
+                Ok(None)
+            }
+        }
+    }
+
+    /// Private helper to presign a download URL, optionally as an attachment with a filename.
+    ///
+    /// With `check`, no URL is returned for missing objects.
+    async fn presign(
+        &self,
+        key: &str,
+        filename: Option<&str>,
+        check: bool,
+    ) -> Result<Option<String>, StorageError> {
+        let Some(duration) = self.presign_duration().await? else {
+            return Ok(None);
+        };
+
+        // Don't redirect to a URL that would 404, let the server respond instead.
+        if check && !self.exists(key).await? {
+            return Ok(None);
+        }
+
+        let cfg = PresigningConfig::expires_in(duration)
+            .map_err(|e| StorageError::Generic(format!("Invalid presign config: {}", e)))?;
+
+        let req = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .set_response_content_disposition(
+                filename.map(|name| format!("attachment; filename=\"{name}\"")),
+            )
+            .presigned(cfg)
+            .await
+            .map_err(|e| {
+                error!("Failed to presign URL: {:?}", e);
+                StorageError::Generic(format!("S3 Presign Error: {}", e))
+            })?;
+
+        Ok(Some(req.uri().to_string()))
+    }
+
+    /// Private helper to list the objects directly below a path, with paths and sizes.
+    async fn list(&self, path: &str) -> Result<Vec<(String, u64)>, StorageError> {
+        let mut objects = Vec::new();
+        let mut token = None;
+        loop {
+            let (page, next_token) = self.list_page(path, token.as_deref(), None).await?;
+            objects.extend(page);
+
+            match next_token {
+                Some(next_token) => token = Some(next_token),
+                None => break,
+            }
+        }
+
+        Ok(objects)
+    }
+
+    /// Private helper to list one page of objects directly below a path, with the continuation
+    /// token of the next page.
+    async fn list_page(
+        &self,
+        path: &str,
+        token: Option<&str>,
+        max_keys: Option<i32>,
+    ) -> Result<(Vec<(String, u64)>, Option<String>), StorageError> {
+        let prefix = self.key(path);
+        let page = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(&prefix)
+            .delimiter("/")
+            .set_continuation_token(token.map(String::from))
+            .set_max_keys(max_keys)
+            .send()
+            .await
+            .map_err(|e| {
+                error!("Failed to list objects: {e:?}");
+                StorageError::Generic(format!("S3 List Error: {e:?}"))
+            })?;
+
+        let objects = page
+            .contents()
+            .iter()
+            .filter_map(|object| {
+                let name = object.key()?.strip_prefix(&prefix)?;
+                Some((name.to_string(), object.size().unwrap_or(0) as u64))
+            })
+            .collect();
+
+        Ok((objects, page.next_continuation_token().map(String::from)))
+    }
+
     /// Private helper to check existence.
     async fn exists(&self, key: &str) -> Result<bool, StorageError> {
         let res = self
@@ -196,12 +359,38 @@ impl StorageBackend for S3Storage {
         Ok(true)
     }
 
+    #[instrument(skip(self, data), fields(bucket = %self.bucket, key))]
+    async fn put_object(&self, path: &str, data: Bytes) -> Result<(), StorageError> {
+        let key = self.key(path);
+        tracing::Span::current().record("key", &key);
+
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .body(ByteStream::from(data))
+            .send()
+            .await
+            .map_err(|e| {
+                error!("Failed to put object: {e:?}");
+                StorageError::Generic(format!("S3 Put Object Error: {e:?}"))
+            })?;
+
+        Ok(())
+    }
+
     #[instrument(skip(self, data), fields(bucket = %self.bucket, key))]
     async fn write_manifest(&self, version: &str, data: Bytes) -> Result<(), StorageError> {
         let path = self.get_manifest_path(version);
         let key = self.key(&path);
         tracing::Span::current().record("key", &key);
 
+        let data = if self.compress_manifests {
+            compress_manifest(&data)?
+        } else {
+            data
+        };
+
         debug!("Uploading manifest...");
         self.client
             .put_object()
@@ -238,6 +427,10 @@ impl StorageBackend for S3Storage {
                     error!("Failed to stream body: {:?}", e);
                     StorageError::Generic(format!("Failed to stream S3 body: {}", e))
                 })?;
+
+                if self.compress_manifests && path.starts_with(&self.get_manifest_path("")) {
+                    return decompress_manifest(data.into_bytes());
+                }
                 Ok(data.into_bytes())
             }
             Err(SdkError::ServiceError(err)) => {
@@ -268,30 +461,142 @@ impl StorageBackend for S3Storage {
     }
 
     #[instrument(skip(self), fields(bucket = %self.bucket, key))]
-    async fn get_download_url(&self, path: &str) -> Result<Option<String>, StorageError> {
+    async fn stat(&self, path: &str) -> Result<ObjectMeta, StorageError> {
         let key = self.key(path);
         tracing::Span::current().record("key", &key);
 
-        let Some(duration) = self.presign_duration else {
-            return Ok(None);
-        };
-
-        let cfg = PresigningConfig::expires_in(duration)
-            .map_err(|e| StorageError::Generic(format!("Invalid presign config: {}", e)))?;
-
-        let req = self
+        let res = self
             .client
-            .get_object()
+            .head_object()
             .bucket(&self.bucket)
             .key(&key)
-            .presigned(cfg)
-            .await
-            .map_err(|e| {
-                error!("Failed to presign URL: {:?}", e);
-                StorageError::Generic(format!("S3 Presign Error: {}", e))
-            })?;
+            .send()
+            .await;
 
-        Ok(Some(req.uri().to_string()))
+        match res {
+            Ok(output) => Ok(ObjectMeta {
+                size: output.content_length().unwrap_or_default().max(0) as u64,
+                last_modified: output
+                    .last_modified()
+                    .and_then(|time| SystemTime::try_from(*time).ok()),
+            }),
+            Err(SdkError::ServiceError(err)) if err.err().is_not_found() => {
+                Err(StorageError::NotFound(path.to_string()))
+            }
+            Err(err) => Err(StorageError::Generic(format!(
+                "S3 Head Object Error: {err:?}"
+            ))),
+        }
+    }
+
+    /// S3 has no batch HEAD, but handles many concurrent requests well.
+    #[instrument(skip(self, paths), fields(bucket = %self.bucket, count = paths.len()))]
+    async fn exists_many(&self, paths: &[String]) -> Result<Vec<bool>, StorageError> {
+        let mut found = Vec::with_capacity(paths.len());
+        for chunk in paths.chunks(EXISTS_MANY_CONCURRENCY) {
+            let keys: Vec<String> = chunk.iter().map(|path| self.key(path)).collect();
+            found.extend(try_join_all(keys.iter().map(|key| self.exists(key))).await?);
+        }
+        Ok(found)
+    }
+
+    #[instrument(skip(self), fields(bucket = %self.bucket, key))]
+    async fn get_download_url(&self, path: &str) -> Result<Option<String>, StorageError> {
+        let key = self.key(path);
+        tracing::Span::current().record("key", &key);
+        self.presign(&key, None, true).await
+    }
+
+    #[instrument(skip(self), fields(bucket = %self.bucket, key))]
+    async fn get_download_url_as(
+        &self,
+        path: &str,
+        filename: &str,
+    ) -> Result<Option<String>, StorageError> {
+        let key = self.key(path);
+        tracing::Span::current().record("key", &key);
+        self.presign(&key, Some(filename), true).await
+    }
+
+    #[instrument(skip(self), fields(bucket = %self.bucket, key))]
+    async fn get_download_url_unchecked(
+        &self,
+        path: &str,
+        filename: Option<&str>,
+    ) -> Result<Option<String>, StorageError> {
+        let key = self.key(path);
+        tracing::Span::current().record("key", &key);
+        self.presign(&key, filename, false).await
+    }
+
+    fn capabilities(&self) -> StorageCapabilities {
+        StorageCapabilities {
+            streaming: true,
+            download_urls: self.presign_duration.is_some(),
+            listing: true,
+            objects: true,
+            tenants: false,
+        }
+    }
+
+    #[instrument(skip(self), fields(bucket = %self.bucket))]
+    async fn list_blobs(&self) -> Result<Vec<BlobInfo>, StorageError> {
+        Ok(self
+            .list("")
+            .await?
+            .into_iter()
+            .filter(|(name, _)| is_blob_hash(name))
+            .map(|(hash, size)| BlobInfo { hash, size })
+            .collect())
+    }
+
+    #[instrument(skip(self), fields(bucket = %self.bucket))]
+    async fn list_manifests(&self) -> Result<Vec<String>, StorageError> {
+        let path = self.get_manifest_path("");
+        Ok(self
+            .list(&path)
+            .await?
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect())
+    }
+
+    /// Uses the S3 continuation token as the cursor. Other objects at the root are skipped,
+    /// so a page can have fewer than `limit` blobs.
+    #[instrument(skip(self), fields(bucket = %self.bucket))]
+    async fn list_blobs_page(
+        &self,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<Page<BlobInfo>, StorageError> {
+        let max_keys = i32::try_from(limit).unwrap_or(i32::MAX);
+        let (objects, next_cursor) = self.list_page("", cursor, Some(max_keys)).await?;
+
+        Ok(Page {
+            items: objects
+                .into_iter()
+                .filter(|(name, _)| is_blob_hash(name))
+                .map(|(hash, size)| BlobInfo { hash, size })
+                .collect(),
+            next_cursor,
+        })
+    }
+
+    /// Uses the S3 continuation token as the cursor.
+    #[instrument(skip(self), fields(bucket = %self.bucket))]
+    async fn list_manifests_page(
+        &self,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<Page<String>, StorageError> {
+        let path = self.get_manifest_path("");
+        let max_keys = i32::try_from(limit).unwrap_or(i32::MAX);
+        let (objects, next_cursor) = self.list_page(&path, cursor, Some(max_keys)).await?;
+
+        Ok(Page {
+            items: objects.into_iter().map(|(name, _)| name).collect(),
+            next_cursor,
+        })
     }
 
     #[instrument(skip(self), fields(bucket = %self.bucket, key))]
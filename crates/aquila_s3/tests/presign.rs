@@ -0,0 +1,65 @@
+//! Asserts that presigned URLs are only generated for objects that exist, against a stub S3.
+
+use aquila_core::prelude::*;
+use aquila_s3::S3Storage;
+use aws_sdk_s3::Client;
+use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// Serves an empty response with `status` to every request, returns its endpoint.
+async fn stub_s3(status: &'static str) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                // Every request is a `HEAD` without a body, read up to the end of its headers.
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    match socket.read(&mut buf).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let response =
+                    format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+                let _ = socket.write_all(response.as_bytes()).await;
+            });
+        }
+    });
+    format!("http://{addr}")
+}
+
+fn storage(endpoint: String) -> S3Storage {
+    let config = aws_sdk_s3::Config::builder()
+        .behavior_version(BehaviorVersion::latest())
+        .region(Region::new("us-east-1"))
+        .credentials_provider(Credentials::new("key", "secret", None, None, "test"))
+        .endpoint_url(endpoint)
+        .force_path_style(true)
+        .build();
+    S3Storage::new(Client::from_conf(config), "bucket".into())
+        .with_presigning(Duration::from_secs(300))
+}
+
+#[tokio::test]
+async fn presign_missing_object() {
+    let missing = storage(stub_s3("404 Not Found").await);
+    assert_eq!(missing.get_download_url("missing").await.unwrap(), None);
+    let url = missing
+        .get_download_url_as("missing", "a.png")
+        .await
+        .unwrap();
+    assert_eq!(url, None);
+    // Without the check, the object is assumed to exist.
+    let url = missing.get_download_url_unchecked("missing", None).await;
+    assert!(url.unwrap().is_some());
+
+    let found = storage(stub_s3("200 OK").await);
+    let url = found.get_download_url("found").await.unwrap().unwrap();
+    assert!(url.contains("/bucket/found?"));
+    assert!(url.contains("X-Amz-Signature="));
+}
@@ -1,22 +1,42 @@
-use crate::auth::AuthenticatedUser;
-use crate::state::AppState;
+use crate::auth::{AuthenticatedUser, DownloadUser, ReadUser, RequiredScopes};
+use crate::retention::{PINS_PATH, read_pins};
+use crate::server::DownloadMode;
+use crate::state::{AppState, CachedManifest};
+use crate::tenant::TENANT_SCOPE_PREFIX;
 
 use aquila_core::prelude::*;
 use axum::response::Redirect;
 use axum::{
     Json,
-    extract::{Path, Query, Request, State},
-    http::StatusCode,
-    response::{IntoResponse, Response},
+    extract::{FromRequest, FromRequestParts, Path, Query, Request, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header, request::Parts},
+    response::{AppendHeaders, IntoResponse, Response},
 };
-use bytes::Bytes;
-use futures::TryStreamExt;
+use bytes::{Bytes, BytesMut};
+use futures::{StreamExt, TryStreamExt, stream};
 use sha2::{Digest, Sha256};
-use std::sync::{Arc, Mutex};
-use tracing::error;
+use std::collections::BTreeSet;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::{OwnedSemaphorePermit, oneshot};
+use tracing::{error, warn};
 
 pub struct ApiError(anyhow::Error);
 
+/// An error that maps to a specific HTTP status, e.g. for rejected requests.
+#[derive(Debug)]
+pub struct StatusError(pub StatusCode, pub String);
+
+impl std::fmt::Display for StatusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.0, self.1)
+    }
+}
+
+impl std::error::Error for StatusError {}
+
 impl<E> From<E> for ApiError
 where
     E: Into<anyhow::Error>,
@@ -28,10 +48,37 @@ where
 
 impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
+        if let Some(StatusError(status, message)) = self.0.downcast_ref::<StatusError>() {
+            return (*status, message.clone()).into_response();
+        }
+
+        if let Some(transform_err) = self.0.downcast_ref::<TransformError>() {
+            let status = match transform_err {
+                TransformError::InvalidParams(_) => StatusCode::BAD_REQUEST,
+                TransformError::Unsupported(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                TransformError::Generic(_) => {
+                    error!("Transform Error: {:?}", self.0);
+                    return (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "Transform failed".to_string(),
+                    )
+                        .into_response();
+                }
+            };
+            return (status, transform_err.to_string()).into_response();
+        }
+
+        if let Some(err @ ManifestError::UnsupportedSchema(_)) = self.0.downcast_ref() {
+            return (StatusCode::UNPROCESSABLE_ENTITY, err.to_string()).into_response();
+        }
+
         self.0
             .downcast_ref::<StorageError>()
             .map(|storage_err| match storage_err {
                 StorageError::NotFound(_) => (StatusCode::NOT_FOUND, "Asset not found".to_string()),
+                StorageError::InvalidCursor(_) => {
+                    (StatusCode::BAD_REQUEST, storage_err.to_string())
+                }
                 _ => {
                     error!("Internal Server Storage Error: {:?}", self.0);
                     (
@@ -43,7 +90,17 @@ impl IntoResponse for ApiError {
             .unwrap_or_else(|| {
                 self.0
                     .downcast_ref::<AuthError>()
-                    .map(|_| (StatusCode::UNAUTHORIZED, "Unauthorized".to_string()))
+                    .map(|auth_err| match auth_err {
+                        AuthError::System(_) => {
+                            error!("Authentication System Error: {:?}", self.0);
+                            (
+                                StatusCode::SERVICE_UNAVAILABLE,
+                                "Authentication unavailable".to_string(),
+                            )
+                        }
+                        AuthError::Forbidden(message) => (StatusCode::FORBIDDEN, message.clone()),
+                        _ => (StatusCode::UNAUTHORIZED, "Unauthorized".to_string()),
+                    })
                     .unwrap_or_else(|| {
                         error!("Internal Server Error: {:?}", self.0);
                         (
@@ -56,47 +113,656 @@ impl IntoResponse for ApiError {
     }
 }
 
-fn check_scope(user: &User, required: &str) -> Result<(), ApiError> {
-    if user.scopes.iter().any(|s| s == "admin" || s == required) {
+pub(crate) fn check_scope(user: &User, required: &str) -> Result<(), ApiError> {
+    check_scopes(user, RequiredScopes::All(&[required]))
+}
+
+fn check_scopes(user: &User, required: RequiredScopes) -> Result<(), ApiError> {
+    if required.is_satisfied_by(user) {
+        return Ok(());
+    }
+
+    let message = match required {
+        RequiredScopes::All([scope]) | RequiredScopes::Any([scope]) => {
+            format!("Missing permission: '{scope}' scope required.")
+        }
+        RequiredScopes::All(scopes) => {
+            format!("Missing permission: all of {scopes:?} scopes required.")
+        }
+        RequiredScopes::Any(scopes) => {
+            format!("Missing permission: one of {scopes:?} scopes required.")
+        }
+    };
+    Err(ApiError::from(AuthError::Forbidden(message)))
+}
+
+fn validate_manifest(manifest: &AssetManifest) -> Result<(), ApiError> {
+    let invalid = |msg: String| ApiError::from(StatusError(StatusCode::BAD_REQUEST, msg));
+
+    if manifest.schema_version != MANIFEST_SCHEMA_VERSION {
+        return Err(ManifestError::UnsupportedSchema(manifest.schema_version.into()).into());
+    }
+    if manifest.version.is_empty() {
+        return Err(invalid("Manifest version must not be empty".into()));
+    }
+    if manifest.version == "latest" {
+        return Err(invalid(
+            "`latest` is reserved for the newest version".into(),
+        ));
+    }
+    // The version is used as a path segment in storage.
+    if manifest.version.contains(['/', '\\']) || matches!(manifest.version.as_str(), "." | "..") {
+        return Err(invalid(format!(
+            "Invalid manifest version: '{}'",
+            manifest.version
+        )));
+    }
+
+    for (path, info) in &manifest.assets {
+        if path.is_empty()
+            || path.starts_with('/')
+            || path.contains('\\')
+            || path
+                .split('/')
+                .any(|s| s.is_empty() || s == "." || s == "..")
+        {
+            return Err(invalid(format!("Invalid asset path: '{path}'")));
+        }
+
+        if !is_blob_hash(&info.hash) {
+            return Err(invalid(format!(
+                "Invalid hash for '{path}': '{}'",
+                info.hash
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads a stored manifest, migrating it to the current schema version. `latest` is
+/// dereferenced, see [`latest_pointer`].
+async fn read_manifest<S: StorageBackend>(
+    storage: &S,
+    version: &str,
+) -> Result<AssetManifest, ApiError> {
+    let path = storage.get_manifest_path(version);
+    let mut data = storage.read_file(&path).await?;
+    if let Some(target) = pointer_target(&data) {
+        data = storage
+            .read_file(&storage.get_manifest_path(&target))
+            .await?;
+    }
+
+    Ok(AssetManifest::migrate(serde_json::from_slice(&data)?)?)
+}
+
+/// The stored `latest`, the version it points to as a JSON string, e.g. `"v1.3"`.
+///
+/// Publishing only writes this pointer instead of a second copy of the manifest. `latest`
+/// written as a full copy by older versions is still read.
+fn latest_pointer(version: &str) -> Bytes {
+    Bytes::from(serde_json::Value::from(version).to_string())
+}
+
+/// The version a stored pointer like `latest` points to, `None` for manifests.
+///
+/// Pointers are detected by their body, as a JSON string can never be a manifest. This way
+/// they are also recognized under other names, e.g. `acme@latest` of a
+/// [`TenantStorage`](crate::tenant::TenantStorage).
+fn pointer_target(data: &[u8]) -> Option<String> {
+    if data.trim_ascii_start().first() != Some(&b'"') {
+        return None;
+    }
+    serde_json::from_slice::<String>(data)
+        .ok()
+        .filter(|target| target != "latest")
+}
+
+/// The version `latest` points to, or `None` if nothing was published as `latest` yet.
+async fn latest_version<S: StorageBackend>(storage: &S) -> Result<Option<String>, ApiError> {
+    let data = match storage
+        .read_file(&storage.get_manifest_path("latest"))
+        .await
+    {
+        Ok(data) => data,
+        Err(StorageError::NotFound(_)) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    match pointer_target(&data) {
+        Some(target) => Ok(Some(target)),
+        None => Ok(Some(
+            AssetManifest::migrate(serde_json::from_slice(&data)?)?.version,
+        )),
+    }
+}
+
+/// Directory of the manifest checksums, e.g. `checksums/manifests/v1.0`.
+const CHECKSUMS_DIR: &str = "checksums";
+
+/// Path of the SHA256 sidecar of a manifest, see [`read_checked`].
+///
+/// Holds one checksum per line, of the last write first and of the manifest it replaced second.
+fn manifest_checksum_path<S: StorageBackend>(storage: &S, version: &str) -> String {
+    format!("{CHECKSUMS_DIR}/{}", storage.get_manifest_path(version))
+}
+
+/// Writes a manifest and its checksum and clears the manifest cache, also if the write failed
+/// halfway. The reference index is only updated if the write succeeded.
+///
+/// The checksum is written first and keeps the checksum of the replaced manifest, so readers
+/// racing the write, or a manifest write that fails afterwards, still match one of them.
+async fn write_manifest<S: StorageBackend, A: AuthProvider>(
+    state: &AppState<S, A>,
+    storage: &S,
+    version: &str,
+    data: Bytes,
+) -> Result<(), StorageError> {
+    let manifest = serde_json::from_slice(&data).map(AssetManifest::migrate);
+    let written = async {
+        let mut checksums = hex::encode(Sha256::digest(&data));
+        match storage.read_file(&storage.get_manifest_path(version)).await {
+            Ok(previous) => {
+                checksums.push('\n');
+                checksums.push_str(&hex::encode(Sha256::digest(&previous)));
+            }
+            Err(StorageError::NotFound(_)) => {}
+            Err(e) => return Err(e),
+        }
+        // Without objects, manifests are stored without checksums and read unverified.
+        if storage.capabilities().objects {
+            let path = manifest_checksum_path(storage, version);
+            storage.put_object(&path, checksums.into()).await?;
+        }
+        storage.write_manifest(version, data).await
+    }
+    .await;
+    state.manifest_cache.invalidate();
+    written?;
+
+    let path = storage.get_manifest_path(version);
+    match manifest {
+        Ok(Ok(manifest)) => state.references.insert(path, &manifest).await,
+        // E.g. `latest` replacing a full copy with a pointer.
+        _ => state.references.remove(&path).await,
+    }
+    Ok(())
+}
+
+/// Deletes a manifest and its checksum and clears the manifest cache. The reference index is
+/// only updated if the manifest was deleted.
+async fn delete_manifest_file<S: StorageBackend, A: AuthProvider>(
+    state: &AppState<S, A>,
+    storage: &S,
+    version: &str,
+) -> Result<(), StorageError> {
+    let path = storage.get_manifest_path(version);
+    let deleted = storage.delete_file(&path).await;
+    state.manifest_cache.invalidate();
+    deleted?;
+
+    state.references.remove(&path).await;
+    storage
+        .delete_file(&manifest_checksum_path(storage, version))
+        .await
+}
+
+/// Reads a manifest, failing if it doesn't match the checksum written with it, e.g. after
+/// bit rot, see [`read_checked`].
+///
+/// For `latest`, both the pointer and the manifest it points to are verified.
+async fn read_verified_manifest<S: StorageBackend>(
+    storage: &S,
+    version: &str,
+) -> Result<AssetManifest, ApiError> {
+    let mut data = read_checked(storage, version).await?;
+    if let Some(target) = pointer_target(&data) {
+        data = read_checked(storage, &target).await?;
+    }
+
+    Ok(AssetManifest::migrate(serde_json::from_slice(&data)?)?)
+}
+
+/// Reads the stored `version`, failing if it matches none of its checksums.
+///
+/// Manifests without checksums, e.g. written before they were added, are returned unverified.
+/// A mismatch is read again once, as concurrent publishes may have replaced both in between.
+async fn read_checked<S: StorageBackend>(storage: &S, version: &str) -> Result<Bytes, ApiError> {
+    let path = storage.get_manifest_path(version);
+    let checksum_path = manifest_checksum_path(storage, version);
+    for _ in 0..2 {
+        let data = storage.read_file(&path).await?;
+        let checksums = match storage.read_file(&checksum_path).await {
+            Ok(checksums) => checksums,
+            Err(StorageError::NotFound(_)) => return Ok(data),
+            Err(e) => return Err(e.into()),
+        };
+
+        let checksum = hex::encode(Sha256::digest(&data));
+        if checksums
+            .split(|b| *b == b'\n')
+            .any(|line| line.trim_ascii() == checksum.as_bytes())
+        {
+            return Ok(data);
+        }
+    }
+
+    error!("Manifest checksum mismatch for version {version}");
+    Err(ApiError::from(StatusError(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        format!("Manifest checksum mismatch for version '{version}'"),
+    )))
+}
+
+/// Header clients can use to declare the SHA256 of the uploaded body.
+pub const CONTENT_SHA256_HEADER: &str = "X-Content-SHA256";
+
+/// Header naming the version a manifest request resolved to, see `GET /manifest/{version}`.
+pub const RESOLVED_VERSION_HEADER: &str = "X-Aquila-Resolved-Version";
+
+fn check_declared_hash(headers: &HeaderMap, hash: &str) -> Result<(), ApiError> {
+    let Some(declared) = headers
+        .get(CONTENT_SHA256_HEADER)
+        .and_then(|val| val.to_str().ok())
+    else {
+        return Ok(());
+    };
+
+    if declared.trim().eq_ignore_ascii_case(hash) {
         Ok(())
     } else {
-        Err(ApiError::from(AuthError::Forbidden(format!(
-            "Missing permission: '{}' scope required.",
-            required
-        ))))
+        Err(ApiError::from(StatusError(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("Declared {CONTENT_SHA256_HEADER} '{declared}' does not match '{hash}'"),
+        )))
     }
 }
 
 /// GET /assets/{hash}
+///
+/// With `?download=filename.png` the response makes browsers save the file under that name.
+/// Supports conditional requests with `If-None-Match` and `If-Modified-Since`.
+///
+/// Instead of a bearer token, a `?token=` of a signed link is accepted, see `sign_asset`.
 pub async fn download_asset<S: StorageBackend, A: AuthProvider>(
     State(state): State<AppState<S, A>>,
-    AuthenticatedUser(user): AuthenticatedUser,
+    DownloadUser(user): DownloadUser,
     Path(hash): Path<String>,
+    Query(params): Query<DownloadParams>,
+    headers: HeaderMap,
 ) -> Result<impl IntoResponse, ApiError> {
     check_scope(&user, "read")?;
-    let data = state.storage.read_file(&hash).await?;
-    if let Some(url) = state.storage.get_download_url(&hash).await? {
+    let storage = state.storage_for(&user);
+    let filename = params.download.as_deref().and_then(sanitize_filename);
+
+    // Blobs are immutable, so the hash is a strong ETag and the mtime never goes back.
+    let last_modified = match storage.stat(&hash).await {
+        Ok(meta) => meta.last_modified,
+        Err(StorageError::NotFound(path)) => return Err(StorageError::NotFound(path).into()),
+        // Backends without `stat` only get ETags, but must not redirect to missing blobs.
+        Err(_) if storage.exists(&hash).await? => None,
+        Err(_) => return Err(StorageError::NotFound(hash).into()),
+    };
+    let mut validators = vec![(header::ETAG, format!("\"{hash}\""))];
+    if let Some(time) = last_modified {
+        validators.push((header::LAST_MODIFIED, httpdate::fmt_http_date(time)));
+    }
+    if not_modified(&headers, &validators[0].1, last_modified) {
+        return Ok((StatusCode::NOT_MODIFIED, AppendHeaders(validators)).into_response());
+    }
+
+    // The blob was found above, the backend doesn't need to check again.
+    let url = match state.download_mode {
+        DownloadMode::Proxy => None,
+        _ => {
+            storage
+                .get_download_url_unchecked(&hash, filename.as_deref())
+                .await?
+        }
+    };
+    if let Some(url) = url {
         return Ok(Redirect::temporary(&url).into_response());
     }
+    if state.download_mode == DownloadMode::Redirect {
+        return Err(ApiError::from(StatusError(
+            StatusCode::NOT_IMPLEMENTED,
+            "Storage backend returned no download URL".into(),
+        )));
+    }
 
+    let data = storage.read_file(&hash).await?;
+    if state.verify_on_read && is_blob_hash(&hash) {
+        verify_blob(&hash, data.clone()).await?;
+    }
     // TODO set Content-Type based on manifest info
-    Ok(data.into_response())
+    let mut res = (AppendHeaders(validators), data).into_response();
+    if let Some(filename) = filename
+        && let Ok(value) = HeaderValue::from_str(&format!("attachment; filename=\"{filename}\""))
+    {
+        res.headers_mut().insert(header::CONTENT_DISPOSITION, value);
+    }
+
+    Ok(res)
+}
+
+/// GET /assets/{hash}/refs
+///
+/// Lists the manifest versions referencing the asset, e.g. to check if it is still used.
+/// See [`ReferenceIndex`](crate::state::ReferenceIndex).
+pub async fn get_asset_refs<S: StorageBackend, A: AuthProvider>(
+    State(state): State<AppState<S, A>>,
+    ReadUser(user): ReadUser,
+    Path(hash): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    check_scope(&user, "read")?;
+    let storage = state.storage_for(&user);
+
+    if !is_blob_hash(&hash) {
+        return Err(ApiError::from(StatusError(
+            StatusCode::BAD_REQUEST,
+            format!("Invalid hash: '{hash}'"),
+        )));
+    }
+
+    // The index is storage wide, only the manifests of the current context are reported.
+    let prefix = storage.get_manifest_path("");
+    let versions: Vec<String> = state
+        .references
+        .get(&state.storage, &hash)
+        .await?
+        .iter()
+        .filter_map(|path| path.strip_prefix(&prefix).map(String::from))
+        .collect();
+
+    Ok(Json(versions))
+}
+
+/// Hashes a blob read from storage on a blocking thread, failing if it doesn't match `hash`.
+async fn verify_blob(hash: &str, data: Bytes) -> Result<(), ApiError> {
+    let actual = tokio::task::spawn_blocking(move || hex::encode(Sha256::digest(&data))).await?;
+    if actual != hash {
+        error!("Stored blob {hash} is corrupted, its content hashes to {actual}");
+        return Err(ApiError::from(StatusError(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Stored blob {hash} is corrupted"),
+        )));
+    }
+    Ok(())
+}
+
+/// Lifetime of signed download links without a `?ttl=`, in seconds.
+const DEFAULT_SIGNED_LINK_TTL: u64 = 60 * 60;
+
+/// Maximum lifetime of signed download links, in seconds. Longer TTLs are clamped.
+const MAX_SIGNED_LINK_TTL: u64 = 7 * 24 * 60 * 60;
+
+#[derive(serde::Deserialize)]
+pub struct SignParams {
+    /// Lifetime of the link in seconds.
+    ttl: Option<u64>,
+}
+
+/// GET /assets/{hash}/sign
+///
+/// Mints a link to download the asset without a token, valid for `?ttl=` seconds,
+/// e.g. to share it. Works with every backend, as the download is served by this server.
+/// The TTL is capped by the `read` cap of `token_ttl_caps`.
+pub async fn sign_asset<S: StorageBackend, A: AuthProvider>(
+    State(state): State<AppState<S, A>>,
+    ReadUser(user): ReadUser,
+    Path(hash): Path<String>,
+    Query(params): Query<SignParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    check_scope(&user, "read")?;
+    let storage = state.storage_for(&user);
+
+    if !is_blob_hash(&hash) {
+        return Err(ApiError::from(StatusError(
+            StatusCode::BAD_REQUEST,
+            format!("Invalid hash: '{hash}'"),
+        )));
+    }
+    if !storage.exists(&hash).await? {
+        return Err(StorageError::NotFound(hash).into());
+    }
+
+    let ttl = params
+        .ttl
+        .unwrap_or(DEFAULT_SIGNED_LINK_TTL)
+        .min(MAX_SIGNED_LINK_TTL);
+    let ttl = state.token_ttl_caps.clamp(&["read".to_string()], ttl);
+    let token = state.jwt_service.mint_download(&user, &hash, ttl)?;
+
+    Ok(Json(serde_json::json!({
+        "url": format!("/assets/{hash}?token={token}"),
+        "expires_in": ttl
+    })))
+}
+
+/// Largest width or height accepted by `GET /assets/{hash}/transform`.
+const MAX_TRANSFORM_SIZE: u32 = 4096;
+
+/// GET /assets/{hash}/transform
+///
+/// Runs the configured [`AssetTransformer`], e.g. `?w=128&format=webp` for a thumbnail.
+/// Results are stored as derived objects at `derived/{hash}/{key}` and reused on later requests.
+pub async fn transform_asset<S: StorageBackend, A: AuthProvider>(
+    State(state): State<AppState<S, A>>,
+    ReadUser(user): ReadUser,
+    Path(hash): Path<String>,
+    Query(params): Query<TransformParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    check_scope(&user, "read")?;
+    let storage = state.storage_for(&user);
+
+    let Some(transformer) = state.transformer.clone() else {
+        return Err(ApiError::from(StatusError(
+            StatusCode::NOT_IMPLEMENTED,
+            "No asset transformer configured".into(),
+        )));
+    };
+
+    let invalid = |msg: String| ApiError::from(StatusError(StatusCode::BAD_REQUEST, msg));
+    if !is_blob_hash(&hash) {
+        return Err(invalid(format!("Invalid hash: '{hash}'")));
+    }
+    if params
+        .w
+        .max(params.h)
+        .is_some_and(|size| size > MAX_TRANSFORM_SIZE)
+    {
+        return Err(invalid(format!(
+            "Width and height must be at most {MAX_TRANSFORM_SIZE}"
+        )));
+    }
+    let Some(key) = params.cache_key() else {
+        return Err(invalid("Format must be alphanumeric".into()));
+    };
+
+    let content_type = match params.format.as_deref().map(str::to_ascii_lowercase) {
+        Some(format) => mime_for_format(&format),
+        None => "application/octet-stream",
+    };
+    let derived_path = format!("derived/{hash}/{key}");
+
+    let data = match storage.read_file(&derived_path).await {
+        Ok(data) => data,
+        Err(StorageError::NotFound(_)) => {
+            let data = storage.read_file(&hash).await?;
+            let data = tokio::task::spawn_blocking(move || transformer.transform(&data, &params))
+                .await??;
+
+            if storage.capabilities().objects {
+                storage.put_object(&derived_path, data.clone()).await?;
+            }
+            data
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    Ok(([(header::CONTENT_TYPE, content_type)], data))
+}
+
+/// MIME type of a transform output format, by file extension.
+fn mime_for_format(format: &str) -> &'static str {
+    match format {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "webp" => "image/webp",
+        "gif" => "image/gif",
+        "avif" => "image/avif",
+        _ => "application/octet-stream",
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct DownloadParams {
+    /// Filename for `Content-Disposition: attachment`.
+    download: Option<String>,
+}
+
+/// Strips directories, quotes, semicolons and control characters from a download filename,
+/// replaces non-ASCII characters with `_`.
+fn sanitize_filename(name: &str) -> Option<String> {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let name: String = name
+        .chars()
+        .filter(|c| !c.is_control() && !matches!(c, '"' | ';'))
+        .map(|c| if c.is_ascii() { c } else { '_' })
+        .collect();
+    let name = name.trim();
+
+    (!name.is_empty() && name != "." && name != "..").then(|| name.to_string())
+}
+
+/// Maximum number of hashes per `POST /assets/exists` request.
+const MAX_EXISTS_BATCH: usize = 1000;
+
+/// POST /assets/exists
+///
+/// Accepts a JSON array of hashes and returns the subset that is already stored,
+/// so clients can skip uploading them.
+pub async fn assets_exist<S: StorageBackend, A: AuthProvider>(
+    State(state): State<AppState<S, A>>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    Json(hashes): Json<Vec<String>>,
+) -> Result<impl IntoResponse, ApiError> {
+    check_scope(&user, "write")?;
+    let storage = state.storage_for(&user);
+
+    let invalid = |msg: String| ApiError::from(StatusError(StatusCode::BAD_REQUEST, msg));
+    if hashes.len() > MAX_EXISTS_BATCH {
+        return Err(invalid(format!(
+            "At most {MAX_EXISTS_BATCH} hashes per request"
+        )));
+    }
+    if let Some(hash) = hashes.iter().find(|hash| !is_blob_hash(hash)) {
+        return Err(invalid(format!("Invalid hash: '{hash}'")));
+    }
+
+    let found = storage.exists_many(&hashes).await?;
+    let existing: Vec<String> = hashes
+        .into_iter()
+        .zip(found)
+        .filter_map(|(hash, exists)| exists.then_some(hash))
+        .collect();
+
+    Ok(Json(existing))
+}
+
+/// How long clients are asked to wait when too many uploads are in progress.
+const UPLOAD_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+/// A slot of `max_concurrent_uploads`, held until the upload handler returns.
+///
+/// Extracted before the body, so a rejected upload isn't received at all.
+pub struct UploadPermit {
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl<S, A> FromRequestParts<AppState<S, A>> for UploadPermit
+where
+    S: StorageBackend,
+    A: AuthProvider,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        _parts: &mut Parts,
+        state: &AppState<S, A>,
+    ) -> Result<Self, Self::Rejection> {
+        let Some(permits) = &state.upload_permits else {
+            return Ok(UploadPermit { _permit: None });
+        };
+
+        match permits.clone().try_acquire_owned() {
+            Ok(permit) => Ok(UploadPermit {
+                _permit: Some(permit),
+            }),
+            Err(_) => Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(
+                    header::RETRY_AFTER,
+                    UPLOAD_RETRY_AFTER.as_secs().to_string(),
+                )],
+                "Too many uploads in progress, try again later",
+            )
+                .into_response()),
+        }
+    }
 }
 
 /// POST /assets
 /// Accepts raw body, calculates SHA256, stores it. Returns the Hash.
+///
+/// If the `X-Content-SHA256` header is set, it must match the calculated hash.
+/// Bodies of at least `stream_upload_threshold` bytes are streamed, see [`read_upload`]. Without
+/// a declared hash, they are spooled to a temp file while hashing, as the path isn't known yet.
 pub async fn upload_asset<S: StorageBackend, A: AuthProvider>(
     State(state): State<AppState<S, A>>,
     AuthenticatedUser(user): AuthenticatedUser,
-    body: Bytes,
+    _permit: UploadPermit,
+    request: Request,
 ) -> Result<impl IntoResponse, ApiError> {
     check_scope(&user, "write")?;
+    let storage = state.storage_for(&user);
 
-    let mut hasher = Sha256::new();
-    hasher.update(&body);
-    let hash = hex::encode(hasher.finalize());
+    let declared = request
+        .headers()
+        .get(CONTENT_SHA256_HEADER)
+        .and_then(|val| val.to_str().ok())
+        .map(|val| val.trim().to_ascii_lowercase());
 
-    let status = if state.storage.write_blob(&hash, body).await? {
+    let (hash, created) = match read_upload(&state, &storage, request).await? {
+        UploadBody::Buffered(body) => {
+            let hash = hex::encode(Sha256::digest(&body));
+            if let Some(declared) = declared {
+                check_hash(&declared, &hash)?;
+            }
+            let created = storage.write_blob(&hash, body).await?;
+            (hash, created)
+        }
+        UploadBody::Streamed(body, content_length) => match declared {
+            Some(hash) if is_blob_hash(&hash) => {
+                let created =
+                    store_stream(&storage, &hash, body, content_length, state.verify_uploads)
+                        .await?;
+                (hash, created)
+            }
+            _ => {
+                let spooled = SpooledUpload::write(body, content_length).await?;
+                if let Some(declared) = declared {
+                    check_hash(&declared, &spooled.hash)?;
+                }
+                let created = storage
+                    .write_stream(&spooled.hash, spooled.read().await?, Some(spooled.size))
+                    .await?;
+                (spooled.hash.clone(), created)
+            }
+        },
+    };
+
+    let status = if created {
         StatusCode::CREATED
     } else {
         StatusCode::OK
@@ -105,56 +771,120 @@ pub async fn upload_asset<S: StorageBackend, A: AuthProvider>(
     Ok((status, hash))
 }
 
-// PUT /assets/stream/{hash}
-pub async fn upload_asset_stream<S: StorageBackend, A: AuthProvider>(
-    State(state): State<AppState<S, A>>,
-    AuthenticatedUser(user): AuthenticatedUser,
-    Path(hash): Path<String>,
-    request: Request,
-) -> Result<impl IntoResponse, ApiError> {
-    check_scope(&user, "write")?;
+/// A request body as passed to [`StorageBackend::write_stream`].
+type BodyStream = Pin<Box<dyn futures::Stream<Item = Result<Bytes, std::io::Error>> + Send>>;
+
+/// An upload body, see [`read_upload`].
+enum UploadBody {
+    Buffered(Bytes),
+    /// The body and its `Content-Length`, if declared.
+    Streamed(BodyStream, Option<u64>),
+}
 
+/// Buffers upload bodies with a `Content-Length` below `stream_upload_threshold`, and streams
+/// the rest, so clients don't have to pick the endpoint by size.
+///
+/// Bodies are always buffered if the backend doesn't support streaming.
+async fn read_upload<S: StorageBackend, A: AuthProvider>(
+    state: &AppState<S, A>,
+    storage: &S,
+    request: Request,
+) -> Result<UploadBody, ApiError> {
     let content_length = request
         .headers()
-        .get(axum::http::header::CONTENT_LENGTH)
+        .get(header::CONTENT_LENGTH)
         .and_then(|val| val.to_str().ok())
         .and_then(|val| val.parse::<u64>().ok());
 
-    let hasher = Arc::new(Mutex::new(Sha256::new()));
-    let hasher_writer = hasher.clone();
-    let stream = request
-        .into_body()
-        .into_data_stream()
-        .map_err(std::io::Error::other)
-        .map_ok(move |chunk| {
-            if let Ok(mut h) = hasher_writer.lock() {
-                h.update(&chunk);
+    let body = request.into_body();
+    if content_length.is_some_and(|len| len < state.stream_upload_threshold)
+        || !storage.capabilities().streaming
+    {
+        let body = axum::body::to_bytes(body, usize::MAX)
+            .await
+            .map_err(|e| StatusError(StatusCode::BAD_REQUEST, e.to_string()))?;
+        return Ok(UploadBody::Buffered(body));
+    }
+
+    let body = body.into_data_stream().map_err(std::io::Error::other);
+    Ok(UploadBody::Streamed(Box::pin(body), content_length))
+}
+
+/// Rejects a body whose `hash` differs from the `expected` one.
+fn check_hash(expected: &str, hash: &str) -> Result<(), ApiError> {
+    if expected.eq_ignore_ascii_case(hash) {
+        return Ok(());
+    }
+    Err(ApiError::from(StatusError(
+        StatusCode::UNPROCESSABLE_ENTITY,
+        format!("Hash of the body '{hash}' does not match '{expected}'"),
+    )))
+}
+
+/// Streams `body` to `hash`. Its length is counted while received and checked against the
+/// declared `content_length`, and if `verify`, it is hashed too. The blob is deleted on mismatch.
+async fn store_stream<S: StorageBackend>(
+    storage: &S,
+    hash: &str,
+    body: BodyStream,
+    content_length: Option<u64>,
+    verify: bool,
+) -> Result<bool, ApiError> {
+    // The length and hash state are folded through the stream, the result is sent once it is
+    // exhausted.
+    let (received_tx, received_rx) = oneshot::channel();
+    let stream = stream::unfold(
+        (body, 0u64, verify.then(Sha256::new), received_tx),
+        |(mut body, mut len, mut hasher, received_tx)| async move {
+            match body.next().await {
+                Some(chunk) => {
+                    if let Ok(chunk) = &chunk {
+                        len += chunk.len() as u64;
+                        if let Some(hasher) = &mut hasher {
+                            hasher.update(chunk);
+                        }
+                    }
+                    Some((chunk, (body, len, hasher, received_tx)))
+                }
+                None => {
+                    let hash = hasher.map(|hasher| hex::encode(hasher.finalize()));
+                    let _ = received_tx.send((len, hash));
+                    None
+                }
             }
-            chunk
-        });
+        },
+    );
 
-    let pinned_stream = Box::pin(stream);
-    let created = state
-        .storage
-        .write_stream(&hash, pinned_stream, content_length)
+    let created = storage
+        .write_stream(hash, Box::pin(stream), content_length)
         .await?;
+    if !created {
+        return Ok(false);
+    }
 
-    if created {
-        let calculated_hash = {
-            let hasher_guard = hasher.lock().map_err(|_| {
-                ApiError::from(anyhow::anyhow!("Internal Error: Hasher mutex poisoned"))
-            })?;
-            hex::encode(hasher_guard.clone().finalize())
-        };
+    // Not sent if the backend stopped reading early, so the data can't be trusted either.
+    let received = received_rx.await.ok();
+
+    if let Some(expected) = content_length
+        && received.as_ref().map(|(len, _)| *len) != Some(expected)
+    {
+        error!("Length mismatch for upload {hash}. Expected: {expected}. Deleting file.");
+        discard_blob(storage, hash).await;
+
+        return Err(ApiError::from(StatusError(
+            StatusCode::BAD_REQUEST,
+            format!("Body length does not match Content-Length {expected}"),
+        )));
+    }
+
+    if verify {
+        let calculated_hash = received.and_then(|(_, hash)| hash).unwrap_or_default();
 
         if calculated_hash != hash {
             error!(
                 "Hash mismatch for upload {hash}. Calculated: {calculated_hash}. Deleting file."
             );
-
-            if let Err(e) = state.storage.delete_file(&hash).await {
-                error!("Failed to delete corrupted file {hash}: {e}");
-            }
+            discard_blob(storage, hash).await;
 
             return Err(ApiError::from(StorageError::Generic(format!(
                 "Integrity check failed. Expected {hash}, got {calculated_hash}"
@@ -162,6 +892,116 @@ pub async fn upload_asset_stream<S: StorageBackend, A: AuthProvider>(
         };
     }
 
+    Ok(true)
+}
+
+/// Deletes a blob that failed a check after it was written.
+async fn discard_blob<S: StorageBackend>(storage: &S, hash: &str) {
+    if let Err(e) = storage.delete_file(hash).await {
+        error!("Failed to delete corrupted file {hash}: {e}");
+    }
+}
+
+/// A streamed upload of unknown hash, spooled to a temp file. The file is removed when dropped.
+struct SpooledUpload {
+    path: std::path::PathBuf,
+    hash: String,
+    size: u64,
+}
+
+/// Size of the chunks read back from a [`SpooledUpload`].
+const SPOOL_CHUNK_SIZE: usize = 64 * 1024;
+
+impl SpooledUpload {
+    /// Spools `body`, rejecting it if its length differs from the declared `content_length`.
+    async fn write(mut body: BodyStream, content_length: Option<u64>) -> Result<Self, ApiError> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let name = format!(
+            "aquila-upload-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+        let mut spooled = Self {
+            path: std::env::temp_dir().join(name),
+            hash: String::new(),
+            size: 0,
+        };
+
+        let mut file = tokio::fs::File::create(&spooled.path).await?;
+        let mut hasher = Sha256::new();
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk?;
+            hasher.update(&chunk);
+            spooled.size += chunk.len() as u64;
+            file.write_all(&chunk).await?;
+        }
+        file.flush().await?;
+
+        if let Some(expected) = content_length
+            && spooled.size != expected
+        {
+            return Err(ApiError::from(StatusError(
+                StatusCode::BAD_REQUEST,
+                format!("Body length does not match Content-Length {expected}"),
+            )));
+        }
+
+        spooled.hash = hex::encode(hasher.finalize());
+        Ok(spooled)
+    }
+
+    /// Streams the spooled body back.
+    async fn read(&self) -> Result<BodyStream, ApiError> {
+        let file = tokio::fs::File::open(&self.path).await?;
+        let stream = stream::try_unfold(file, |mut file| async move {
+            let mut chunk = BytesMut::with_capacity(SPOOL_CHUNK_SIZE);
+            match file.read_buf(&mut chunk).await? {
+                0 => Ok(None),
+                _ => Ok(Some((chunk.freeze(), file))),
+            }
+        });
+        Ok(Box::pin(stream))
+    }
+}
+
+impl Drop for SpooledUpload {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+// PUT /assets/stream/{hash}
+//
+// A declared `X-Content-SHA256` that disagrees with the path hash is rejected before streaming.
+// The received data is hashed and deleted on mismatch, unless `verify_uploads` is disabled.
+// Small bodies are buffered and always verified before storing, see [`read_upload`].
+pub async fn upload_asset_stream<S: StorageBackend, A: AuthProvider>(
+    State(state): State<AppState<S, A>>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    _permit: UploadPermit,
+    Path(hash): Path<String>,
+    request: Request,
+) -> Result<impl IntoResponse, ApiError> {
+    check_scope(&user, "write")?;
+    let storage = state.storage_for(&user);
+    if !is_blob_hash(&hash) {
+        return Err(ApiError::from(StatusError(
+            StatusCode::BAD_REQUEST,
+            format!("Invalid hash: '{hash}'"),
+        )));
+    }
+    check_declared_hash(request.headers(), &hash)?;
+
+    let created = match read_upload(&state, &storage, request).await? {
+        UploadBody::Buffered(body) => {
+            check_hash(&hash, &hex::encode(Sha256::digest(&body)))?;
+            storage.write_blob(&hash, body).await?
+        }
+        UploadBody::Streamed(body, content_length) => {
+            store_stream(&storage, &hash, body, content_length, state.verify_uploads).await?
+        }
+    };
+
     let status = if created {
         StatusCode::CREATED
     } else {
@@ -172,53 +1012,715 @@ pub async fn upload_asset_stream<S: StorageBackend, A: AuthProvider>(
 }
 
 /// GET /manifest/{version}
+///
+/// Responds with the canonical form, see [`AssetManifest::to_canonical_vec`].
+/// Placeholders are substituted if `manifest_vars` is configured, and the result re-signed.
+///
+/// Responses carry an `ETag`, a matching `If-None-Match` is answered with `304 Not Modified`.
+/// The `X-Aquila-Resolved-Version` header names the version served, e.g. the one `latest`
+/// points to. If `manifest_cache_ttl` is set, responses are cached until the next manifest write.
+///
+/// Manifests are checked against the checksum written with them, a corrupted manifest is
+/// rejected with `500 Internal Server Error` instead of being served.
 pub async fn get_manifest<S: StorageBackend, A: AuthProvider>(
     State(state): State<AppState<S, A>>,
-    AuthenticatedUser(user): AuthenticatedUser,
+    ReadUser(user): ReadUser,
     Path(version): Path<String>,
+    headers: HeaderMap,
 ) -> Result<impl IntoResponse, ApiError> {
     check_scope(&user, "read")?;
+    let storage = state.storage_for(&user);
+
+    // The path of the context bound storage, so tenants don't share entries.
+    let path = storage.get_manifest_path(&version);
+    let cached = state
+        .manifest_cache_ttl
+        .and_then(|ttl| state.manifest_cache.get(&path, ttl));
 
-    let path = state.storage.get_manifest_path(version.as_str());
-    let data = state.storage.read_file(&path).await?;
+    let manifest = match cached {
+        Some(manifest) => manifest,
+        None => {
+            let generation = state.manifest_cache.generation();
+            let manifest = render_manifest(&state, &storage, &version).await?;
+            if state.manifest_cache_ttl.is_some() {
+                state.manifest_cache.set(path, manifest.clone(), generation);
+            }
+            manifest
+        }
+    };
 
-    // Validate
-    let _manifest: AssetManifest = serde_json::from_slice(&data)?;
+    let etag = (header::ETAG, manifest.etag.clone());
+    let resolved = AppendHeaders(
+        manifest
+            .version
+            .clone()
+            .map(|version| (RESOLVED_VERSION_HEADER, version)),
+    );
+    if etag_matches(&headers, &manifest.etag) {
+        return Ok((StatusCode::NOT_MODIFIED, [etag], resolved).into_response());
+    }
 
-    Ok(Json(serde_json::from_slice::<serde_json::Value>(&data)?))
+    Ok((
+        [(header::CONTENT_TYPE, "application/json".to_string()), etag],
+        resolved,
+        manifest.body,
+    )
+        .into_response())
+}
+
+/// Reads a manifest as served by `GET /manifest/{version}`.
+async fn render_manifest<S: StorageBackend, A: AuthProvider>(
+    state: &AppState<S, A>,
+    storage: &S,
+    version: &str,
+) -> Result<CachedManifest, ApiError> {
+    let mut manifest = read_verified_manifest(storage, version).await?;
+
+    if let Some(vars) = &state.manifest_vars {
+        let signed = manifest.signature.is_some();
+        manifest.substitute(vars);
+        if signed
+            && manifest.signature.is_none()
+            && let Some(key) = &state.signing_key
+        {
+            manifest.sign(key)?;
+        }
+    }
+
+    let body = Bytes::from(manifest.to_canonical_vec()?);
+    let etag = format!("\"{}\"", hex::encode(Sha256::digest(&body)));
+    let version = HeaderValue::from_str(&manifest.version).ok();
+
+    Ok(CachedManifest {
+        body,
+        etag,
+        version,
+    })
+}
+
+/// Returns `true` if the `If-None-Match` header contains `etag` or is `*`.
+fn etag_matches(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|val| val.to_str().ok())
+        .flat_map(|val| val.split(','))
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == etag || tag == "*")
+}
+
+/// Returns `true` if the cached copy of the client is still current.
+///
+/// `If-None-Match` takes precedence, `If-Modified-Since` is only checked without it.
+fn not_modified(headers: &HeaderMap, etag: &str, last_modified: Option<SystemTime>) -> bool {
+    if headers.contains_key(header::IF_NONE_MATCH) {
+        return etag_matches(headers, etag);
+    }
+
+    let since = headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|val| val.to_str().ok())
+        .and_then(|val| httpdate::parse_http_date(val).ok());
+    match (since, last_modified) {
+        // HTTP dates have no fractional seconds.
+        (Some(since), Some(modified)) => modified < since + Duration::from_secs(1),
+        _ => false,
+    }
+}
+
+/// GET /manifest/{version}/stats
+pub async fn get_manifest_stats<S: StorageBackend, A: AuthProvider>(
+    State(state): State<AppState<S, A>>,
+    ReadUser(user): ReadUser,
+    Path(version): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    check_scope(&user, "read")?;
+    let storage = state.storage_for(&user);
+
+    let manifest = read_manifest(&storage, &version).await?;
+
+    Ok(Json(manifest.stats()))
+}
+
+#[derive(serde::Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchiveFormat {
+    #[default]
+    Tar,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ArchiveParams {
+    #[serde(default)]
+    format: ArchiveFormat,
+}
+
+/// Tar archives are made of 512 byte blocks.
+const TAR_BLOCK_SIZE: usize = 512;
+
+/// GET /manifest/{version}/archive
+///
+/// Streams all assets of a version as a single archive (`?format=tar`, the default),
+/// with entries named by their manifest paths.
+///
+/// The archive is built lazily: up to `archive_prefetch` blobs are read ahead concurrently while
+/// earlier entries are sent, so memory stays bounded by that many of the largest assets.
+/// This trades the request count for a single long-lived connection: an interrupted
+/// download has to start over, and a blob missing from storage aborts the stream midway.
+pub async fn get_manifest_archive<S: StorageBackend, A: AuthProvider>(
+    State(state): State<AppState<S, A>>,
+    ReadUser(user): ReadUser,
+    Path(version): Path<String>,
+    Query(params): Query<ArchiveParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    check_scope(&user, "read")?;
+    let storage = state.storage_for(&user);
+
+    let manifest = read_manifest(&storage, &version).await?;
+    let ArchiveFormat::Tar = params.format;
+
+    let mtime = manifest.published_at.timestamp().max(0) as u64;
+    let mut entries: Vec<(String, AssetInfo)> = manifest.assets.into_iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+    // `buffered` keeps the entry order and only reads ahead while the client keeps up.
+    let body = stream::iter(entries)
+        .map(move |(path, info)| {
+            let storage = storage.clone();
+            async move {
+                let data = storage.read_file(&info.hash).await?;
+                let header = tar_header(&path, data.len() as u64, mtime)?;
+                let padding = (TAR_BLOCK_SIZE - data.len() % TAR_BLOCK_SIZE) % TAR_BLOCK_SIZE;
+
+                Ok::<_, StorageError>(stream::iter(
+                    [header, data, Bytes::from(vec![0; padding])].map(Ok),
+                ))
+            }
+        })
+        .buffered(state.archive_prefetch)
+        .try_flatten()
+        // End of archive: two empty blocks.
+        .chain(stream::once(async {
+            Ok(Bytes::from(vec![0; TAR_BLOCK_SIZE * 2]))
+        }))
+        .inspect_err(move |e: &StorageError| {
+            error!("Failed to stream archive for version {version}: {e}")
+        });
+
+    let mut res = axum::body::Body::from_stream(body).into_response();
+    let headers = res.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/x-tar"),
+    );
+    if let Some(filename) = sanitize_filename(&format!("{}.tar", manifest.version))
+        && let Ok(value) = HeaderValue::from_str(&format!("attachment; filename=\"{filename}\""))
+    {
+        headers.insert(header::CONTENT_DISPOSITION, value);
+    }
+
+    Ok(res)
+}
+
+/// Encodes the tar header for a regular file, including GNU long name entries if needed.
+fn tar_header(path: &str, size: u64, mtime: u64) -> std::io::Result<Bytes> {
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Regular);
+    header.set_size(size);
+    header.set_mode(0o644);
+    header.set_mtime(mtime);
+
+    // The builder only writes the header here, the data is streamed separately.
+    let mut builder = tar::Builder::new(Vec::new());
+    builder.append_data(&mut header, path, std::io::empty())?;
+
+    Ok(Bytes::from(std::mem::take(builder.get_mut())))
 }
 
 #[derive(serde::Deserialize)]
 pub struct PublishParams {
     #[serde(default = "default_true")]
     latest: bool,
+    /// Validate only, don't write anything.
+    #[serde(default)]
+    dry_run: bool,
+    /// Fail with `409 Conflict` if a referenced blob is missing, nothing is written then.
+    #[serde(default)]
+    require_blobs: bool,
 }
 
 fn default_true() -> bool {
     true
 }
 
+/// Returns the hashes referenced by `manifest` that aren't stored yet, sorted.
+async fn missing_hashes<S: StorageBackend>(
+    storage: &S,
+    manifest: &AssetManifest,
+) -> Result<Vec<String>, StorageError> {
+    let hashes: BTreeSet<&str> = manifest.assets.values().map(|a| a.hash.as_str()).collect();
+    let mut missing = Vec::new();
+    for hash in hashes {
+        if !storage.exists(hash).await? {
+            missing.push(hash.to_string());
+        }
+    }
+    Ok(missing)
+}
+
+/// A manifest body in the [`ManifestFormat`] of its `Content-Type`, JSON by default.
+///
+/// YAML and TOML require the `yaml` and `toml` features, other types are rejected with 415.
+pub struct ManifestBody(pub AssetManifest);
+
+impl<S: Send + Sync> FromRequest<S> for ManifestBody {
+    type Rejection = Response;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let format = request
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|val| val.to_str().ok())
+            .and_then(ManifestFormat::from_content_type)
+            .filter(|format| *format != ManifestFormat::Json);
+
+        // Unknown types are left to `Json`, which rejects them as usual.
+        let Some(format) = format else {
+            return Json::from_request(request, state)
+                .await
+                .map(|Json(manifest)| ManifestBody(manifest))
+                .map_err(IntoResponse::into_response);
+        };
+
+        let body = Bytes::from_request(request, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        AssetManifest::from_slice(format, &body)
+            .map(ManifestBody)
+            .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e.to_string()).into_response())
+    }
+}
+
 /// POST /manifest
+///
+/// The manifest may be sent as YAML or TOML, see [`ManifestBody`]. It is stored as JSON.
+///
+/// With `?dry_run=true` the manifest is only validated and a [`PublishReport`] is returned.
+///
+/// With `?require_blobs=true` the publish is all or nothing: missing blobs fail it before
+/// anything is written, and if moving `latest` fails, a newly written version is removed
+/// again. Blob uploads happen before and aren't rolled back, which is harmless as they are
+/// content addressed and invisible until referenced.
 pub async fn publish_manifest<S: StorageBackend, A: AuthProvider>(
     State(state): State<AppState<S, A>>,
     AuthenticatedUser(user): AuthenticatedUser,
     Query(params): Query<PublishParams>,
-    Json(manifest): Json<AssetManifest>,
+    ManifestBody(mut manifest): ManifestBody,
+) -> Result<impl IntoResponse, ApiError> {
+    check_scope(&user, "write")?;
+    let storage = state.storage_for(&user);
+    validate_manifest(&manifest)?;
+
+    if params.dry_run {
+        let missing_hashes = missing_hashes(&storage, &manifest).await?;
+        return Ok((
+            StatusCode::OK,
+            Json(PublishReport {
+                version: manifest.version.clone(),
+                asset_count: manifest.assets.len(),
+                total_size: manifest.assets.values().map(|a| a.size).sum(),
+                missing_hashes,
+            }),
+        )
+            .into_response());
+    }
+
+    // Never trust a signature sent by the client.
+    manifest.signature = None;
+    if let Some(key) = &state.signing_key {
+        manifest.sign(key)?;
+    }
+
+    if params.require_blobs {
+        let missing = missing_hashes(&storage, &manifest).await?;
+        if !missing.is_empty() {
+            return Err(ApiError::from(StatusError(
+                StatusCode::CONFLICT,
+                format!("Missing blobs: {}", missing.join(", ")),
+            )));
+        }
+    }
+
+    let data = Bytes::from(manifest.to_canonical_vec()?);
+
+    let path = storage.get_manifest_path(&manifest.version);
+    let existed = params.require_blobs && storage.exists(&path).await?;
+    write_manifest(&state, &storage, &manifest.version, data).await?;
+
+    if params.latest
+        && let Err(e) = write_manifest(
+            &state,
+            &storage,
+            "latest",
+            latest_pointer(&manifest.version),
+        )
+        .await
+    {
+        if params.require_blobs && !existed {
+            warn!(
+                "Moving `latest` failed, removing version '{}'",
+                manifest.version
+            );
+            if let Err(e) = delete_manifest_file(&state, &storage, &manifest.version).await {
+                error!("Failed to remove version '{}': {e}", manifest.version);
+            }
+        }
+        return Err(e.into());
+    }
+
+    Ok(StatusCode::CREATED.into_response())
+}
+
+/// PATCH /manifest/{version}
+/// Applies a [`ManifestPatch`] to an existing manifest and stores it again.
+pub async fn patch_manifest<S: StorageBackend, A: AuthProvider>(
+    State(state): State<AppState<S, A>>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    Path(version): Path<String>,
+    Json(patch): Json<ManifestPatch>,
 ) -> Result<impl IntoResponse, ApiError> {
     check_scope(&user, "write")?;
+    let storage = state.storage_for(&user);
+
+    let mut manifest = read_manifest(&storage, &version).await?;
+
+    manifest.apply_patch(patch);
+    validate_manifest(&manifest)?;
+    if let Some(key) = &state.signing_key {
+        manifest.sign(key)?;
+    }
 
-    let data = Bytes::from(serde_json::to_vec_pretty(&manifest)?);
+    let data = Bytes::from(manifest.to_canonical_vec()?);
+    write_manifest(&state, &storage, &manifest.version, data).await?;
+
+    // Rewritten as a pointer, in case `latest` is still a full copy of the old manifest.
+    if latest_version(&storage).await?.as_ref() == Some(&manifest.version) {
+        let pointer = latest_pointer(&manifest.version);
+        write_manifest(&state, &storage, "latest", pointer).await?;
+    }
+
+    Ok(Json(manifest))
+}
 
-    state
+/// DELETE /manifest/{version}
+///
+/// Unpublishes a manifest, the referenced blobs are kept. If `latest` points to the version,
+/// it is repointed to the newest remaining version by `published_at`, or removed if none is left.
+pub async fn delete_manifest<S: StorageBackend, A: AuthProvider>(
+    State(state): State<AppState<S, A>>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    Path(version): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    check_scope(&user, "write")?;
+    let storage = state.storage_for(&user);
+
+    if version == "latest" {
+        return Err(ApiError::from(StatusError(
+            StatusCode::BAD_REQUEST,
+            "`latest` is an alias, unpublish the version it points to instead".into(),
+        )));
+    }
+
+    let manifest = read_manifest(&storage, &version).await?;
+
+    let is_latest = latest_version(&storage).await?.as_ref() == Some(&manifest.version);
+
+    // Find the replacement first, so nothing is deleted if listing fails.
+    let mut replacement: Option<AssetManifest> = None;
+    if is_latest {
+        for other in storage.list_manifests().await? {
+            if other == "latest" || other == version {
+                continue;
+            }
+
+            let other = read_manifest(&storage, &other).await?;
+            if replacement
+                .as_ref()
+                .is_none_or(|newest| other.published_at > newest.published_at)
+            {
+                replacement = Some(other);
+            }
+        }
+    }
+
+    delete_manifest_file(&state, &storage, &version).await?;
+
+    if is_latest {
+        match replacement {
+            Some(newest) => {
+                let pointer = latest_pointer(&newest.version);
+                write_manifest(&state, &storage, "latest", pointer).await?;
+            }
+            None => delete_manifest_file(&state, &storage, "latest").await?,
+        }
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// GET /capabilities
+///
+/// Reports the features of this deployment, so clients can adapt up front.
+pub async fn get_capabilities<S: StorageBackend, A: AuthProvider>(
+    State(state): State<AppState<S, A>>,
+) -> impl IntoResponse {
+    Json(ServerCapabilities {
+        storage: state.storage.capabilities(),
+        login: state.auth.get_login_url().is_some(),
+        signed_manifests: state.signing_key.is_some(),
+        anonymous_read: state.allow_anonymous_read,
+        hash_algorithm: "sha256".into(),
+        max_upload_size: state.max_body_size.map(|size| size as u64),
+        scopes: ["read", "write", "admin"].map(String::from).to_vec(),
+    })
+}
+
+/// How long the result of `GET /admin/stats` is reused, as it scans the whole storage.
+const STATS_CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// GET /admin/stats
+///
+/// Reports how much content-addressing saves, by listing all blobs and scanning all manifests.
+/// Requires a backend that supports listing, the result is cached for [`STATS_CACHE_TTL`].
+pub async fn get_storage_stats<S: StorageBackend, A: AuthProvider>(
+    State(state): State<AppState<S, A>>,
+    AuthenticatedUser(user): AuthenticatedUser,
+) -> Result<impl IntoResponse, ApiError> {
+    check_scope(&user, "admin")?;
+
+    if let Some(stats) = state.stats_cache.get(STATS_CACHE_TTL) {
+        return Ok(Json(stats));
+    }
+
+    let blobs = state.storage.list_blobs().await?;
+    let mut stats = StorageStats {
+        unique_blobs: blobs.len(),
+        total_blob_bytes: blobs.iter().map(|b| b.size).sum(),
+        ..Default::default()
+    };
+
+    for version in state.storage.list_manifests().await? {
+        let path = state.storage.get_manifest_path(&version);
+        let data = state.storage.read_file(&path).await?;
+        // Pointers, e.g. per tenant, refer to another version, as does a full copy of `latest`
+        // written by older versions.
+        if pointer_target(&data).is_some() || version == "latest" {
+            continue;
+        }
+
+        let manifest = AssetManifest::migrate(serde_json::from_slice(&data)?)?;
+        stats.manifest_count += 1;
+        stats.logical_asset_count += manifest.assets.len();
+        stats.logical_bytes += manifest.assets.values().map(|a| a.size).sum::<u64>();
+    }
+
+    if stats.total_blob_bytes > 0 {
+        stats.dedup_ratio = stats.logical_bytes as f64 / stats.total_blob_bytes as f64;
+    }
+
+    state.stats_cache.set(stats.clone());
+
+    Ok(Json(stats))
+}
+
+/// Default number of items per page of a listing.
+const DEFAULT_PAGE_SIZE: usize = 100;
+
+/// Maximum number of items per page of a listing, larger limits are clamped.
+const MAX_PAGE_SIZE: usize = 1000;
+
+#[derive(serde::Deserialize)]
+pub struct PageParams {
+    limit: Option<usize>,
+    /// Cursor of the previous page, see [`Page::next_cursor`].
+    cursor: Option<String>,
+}
+
+impl PageParams {
+    fn limit(&self) -> usize {
+        self.limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE)
+    }
+}
+
+/// GET /manifests
+///
+/// Lists the manifest versions page by page, with `?limit=&cursor=`.
+pub async fn list_manifests<S: StorageBackend, A: AuthProvider>(
+    State(state): State<AppState<S, A>>,
+    ReadUser(user): ReadUser,
+    Query(params): Query<PageParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    check_scope(&user, "read")?;
+    let storage = state.storage_for(&user);
+
+    let page = storage
+        .list_manifests_page(params.cursor.as_deref(), params.limit())
+        .await?;
+
+    Ok(Json(page))
+}
+
+/// GET /admin/blobs
+///
+/// Lists the stored blobs page by page, with `?limit=&cursor=`.
+pub async fn list_blobs<S: StorageBackend, A: AuthProvider>(
+    State(state): State<AppState<S, A>>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    Query(params): Query<PageParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    check_scope(&user, "admin")?;
+
+    let page = state
         .storage
-        .write_manifest(&manifest.version, data.clone())
+        .list_blobs_page(params.cursor.as_deref(), params.limit())
         .await?;
 
-    if params.latest {
-        state.storage.write_manifest("latest", data).await?;
+    Ok(Json(page))
+}
+
+/// Private helper to apply `update` to the pinned blobs, serialized with other updates.
+async fn update_pins<S: StorageBackend, A: AuthProvider>(
+    state: &AppState<S, A>,
+    update: impl FnOnce(&mut BTreeSet<String>) -> bool,
+) -> Result<(), ApiError> {
+    let _lock = state.pins_lock.lock().await;
+
+    let mut pins = read_pins(&state.storage).await?;
+    if update(&mut pins) {
+        let data = serde_json::to_vec(&pins)?;
+        state.storage.put_object(PINS_PATH, data.into()).await?;
+    }
+    Ok(())
+}
+
+#[derive(serde::Deserialize)]
+pub struct PruneParams {
+    /// Number of versions to keep.
+    keep: usize,
+    /// Report only, don't delete anything.
+    #[serde(default)]
+    dry_run: bool,
+    /// Comma-separated versions that are never removed, e.g. tagged releases.
+    #[serde(default)]
+    keep_versions: String,
+}
+
+/// POST /admin/prune-manifests?keep=50&keep_versions=1.0,2.0
+///
+/// Unpublishes all but the `keep` newest versions by `published_at`, never the one `latest`
+/// points to or one of `keep_versions`. Those are kept in addition to `keep`, except `latest`
+/// which counts towards it. The referenced blobs are kept. Returns a [`PruneReport`].
+pub async fn prune_manifests<S: StorageBackend, A: AuthProvider>(
+    State(state): State<AppState<S, A>>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    Query(params): Query<PruneParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    check_scope(&user, "admin")?;
+    let storage = state.storage_for(&user);
+
+    let latest = latest_version(&storage).await?;
+    let protected: BTreeSet<&str> = params
+        .keep_versions
+        .split(',')
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .collect();
+
+    let mut versions = Vec::new();
+    for version in storage.list_manifests().await? {
+        if version == "latest"
+            || latest.as_ref() == Some(&version)
+            || protected.contains(version.as_str())
+        {
+            continue;
+        }
+        let manifest = read_manifest(&storage, &version).await?;
+        versions.push((manifest.published_at, version));
+    }
+
+    // Newest first, the versions after the first `keep` are removed. The one `latest` points
+    // to counts towards `keep`.
+    versions.sort_by(|a, b| b.cmp(a));
+    let keep = params.keep.saturating_sub(latest.is_some() as usize);
+    let removed: Vec<String> = versions
+        .into_iter()
+        .skip(keep)
+        .rev()
+        .map(|(_, version)| version)
+        .collect();
+
+    if !params.dry_run {
+        for version in &removed {
+            delete_manifest_file(&state, &storage, version).await?;
+        }
+    }
+
+    Ok(Json(PruneReport {
+        removed,
+        dry_run: params.dry_run,
+    }))
+}
+
+/// GET /admin/pins
+///
+/// Lists the pinned blobs, sorted by hash.
+pub async fn list_pins<S: StorageBackend, A: AuthProvider>(
+    State(state): State<AppState<S, A>>,
+    AuthenticatedUser(user): AuthenticatedUser,
+) -> Result<impl IntoResponse, ApiError> {
+    check_scope(&user, "admin")?;
+
+    Ok(Json(read_pins(&state.storage).await?))
+}
+
+/// POST /admin/pins/{hash}
+///
+/// Pins a blob, so it is kept even if no manifest references it anymore, e.g. for rollbacks.
+pub async fn pin_blob<S: StorageBackend, A: AuthProvider>(
+    State(state): State<AppState<S, A>>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    Path(hash): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    check_scope(&user, "admin")?;
+
+    if !is_blob_hash(&hash) {
+        return Err(ApiError::from(StatusError(
+            StatusCode::BAD_REQUEST,
+            format!("Invalid hash: '{hash}'"),
+        )));
+    }
+    if !state.storage.exists(&hash).await? {
+        return Err(StorageError::NotFound(hash).into());
     }
 
-    Ok(StatusCode::CREATED)
+    update_pins(&state, |pins| pins.insert(hash)).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// DELETE /admin/pins/{hash}
+pub async fn unpin_blob<S: StorageBackend, A: AuthProvider>(
+    State(state): State<AppState<S, A>>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    Path(hash): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    check_scope(&user, "admin")?;
+
+    update_pins(&state, |pins| pins.remove(&hash)).await?;
+
+    Ok(StatusCode::NO_CONTENT)
 }
 
 #[derive(serde::Deserialize)]
@@ -243,17 +1745,63 @@ pub async fn auth_login<S: StorageBackend, A: AuthProvider>(
 #[derive(serde::Deserialize)]
 pub struct CreateTokenRequest {
     /// Who is this token for? (e.g., "game_v1", "build_server")
+    ///
+    /// On multi-tenant storage, must be the id of the caller, unless the caller is an admin.
     pub subject: String,
     /// How long should it last?
     ///
     /// Default: 1 year
     pub duration_seconds: Option<u64>,
-    /// Optional scopes
+    /// Optional scopes, see `AquilaServerConfig::mintable_scopes`.
     ///
     /// Default: `read`
     pub scopes: Option<Vec<String>>,
 }
 
+/// Keeps tokens minted on multi-tenant storage in the tenant of the caller, see
+/// [`TenantStorage`](crate::tenant::TenantStorage).
+///
+/// Non-admins can only mint for themselves, as the tenant defaults to the user id. Returns the
+/// requested scopes without tenants, and the tenants of the caller to add.
+fn tenant_scopes(
+    user: &User,
+    is_admin: bool,
+    subject: &str,
+    scopes: Vec<String>,
+) -> Result<(Vec<String>, Vec<String>), ApiError> {
+    if !is_admin && subject != user.id {
+        return Err(ApiError::from(StatusError(
+            StatusCode::FORBIDDEN,
+            "Tokens can only be minted for yourself.".into(),
+        )));
+    }
+
+    let mut tenants: Vec<String> = user
+        .scopes
+        .iter()
+        .filter(|s| s.starts_with(TENANT_SCOPE_PREFIX))
+        .cloned()
+        .collect();
+    if tenants.is_empty() {
+        tenants.push(format!("{TENANT_SCOPE_PREFIX}{}", user.id));
+    }
+    if let Some(scope) = scopes
+        .iter()
+        .find(|s| s.starts_with(TENANT_SCOPE_PREFIX) && !tenants.contains(s))
+    {
+        return Err(ApiError::from(StatusError(
+            StatusCode::FORBIDDEN,
+            format!("Scope '{scope}' cannot be minted."),
+        )));
+    }
+    let scopes = scopes
+        .into_iter()
+        .filter(|s| !s.starts_with(TENANT_SCOPE_PREFIX))
+        .collect();
+
+    Ok((scopes, tenants))
+}
+
 /// POST /auth/token
 pub async fn issue_token<S: StorageBackend, A: AuthProvider>(
     State(state): State<AppState<S, A>>,
@@ -263,6 +1811,7 @@ pub async fn issue_token<S: StorageBackend, A: AuthProvider>(
     check_scope(&user, "write")?;
 
     let scopes = req.scopes.unwrap_or_else(|| vec!["read".to_string()]);
+    // Not even admins, see `AquilaServerConfig::mintable_scopes`.
     if scopes
         .iter()
         .any(|s| matches!(s.as_str(), "admin" | "write"))
@@ -272,7 +1821,26 @@ pub async fn issue_token<S: StorageBackend, A: AuthProvider>(
         )));
     }
 
-    let duration = req.duration_seconds.unwrap_or(31_536_000); // 1 year
+    let is_admin = user.scopes.iter().any(|s| s == "admin");
+    let (mut scopes, tenants) = if state.storage.capabilities().tenants {
+        tenant_scopes(&user, is_admin, &req.subject, scopes)?
+    } else {
+        (scopes, Vec::new())
+    };
+
+    if !is_admin && let Some(scope) = scopes.iter().find(|s| !state.mintable_scopes.contains(s)) {
+        return Err(ApiError::from(StatusError(
+            StatusCode::FORBIDDEN,
+            format!("Scope '{scope}' cannot be minted."),
+        )));
+    }
+
+    let mut duration = req.duration_seconds.unwrap_or(31_536_000); // 1 year
+    if !is_admin {
+        duration = state.token_ttl_caps.clamp(&scopes, duration);
+    }
+    scopes.extend(tenants);
+
     let token = state.jwt_service.mint(req.subject, scopes, duration)?;
 
     Ok(Json(serde_json::json!({
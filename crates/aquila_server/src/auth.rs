@@ -2,9 +2,13 @@ use crate::jwt::JwtService;
 use crate::state::AppState;
 use aquila_core::prelude::*;
 use axum::{
-    extract::FromRequestParts,
+    extract::{FromRequestParts, Path, Query},
     http::{StatusCode, request::Parts},
 };
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
 
 /// A wrapper struct indicating a request has been authenticated.
 #[derive(Clone, Debug)]
@@ -21,25 +25,152 @@ where
         parts: &mut Parts,
         state: &AppState<S, A>,
     ) -> Result<Self, Self::Rejection> {
-        let token = parts
-            .headers
-            .get("Authorization")
-            .and_then(|auth_header| {
-                auth_header
-                    .to_str()
-                    .map(|header_str| {
-                        header_str
-                            .strip_prefix("Bearer ")
-                            .unwrap_or(header_str)
-                            .trim()
-                    })
-                    .ok()
-            })
-            .unwrap_or("");
-
-        match state.auth.verify(token).await {
-            Ok(user) => Ok(AuthenticatedUser(user)),
-            Err(_) => Err((StatusCode::UNAUTHORIZED, "Unauthorized".to_string())),
+        verify(bearer_token(parts), state)
+            .await
+            .map(AuthenticatedUser)
+    }
+}
+
+/// Like [`AuthenticatedUser`], but for read-only routes.
+///
+/// If `allow_anonymous_read` is enabled, requests without a token are let through as an
+/// anonymous user with only the `read` scope. A token that is sent must still be valid.
+#[derive(Clone, Debug)]
+pub struct ReadUser(pub User);
+
+impl<S, A> FromRequestParts<AppState<S, A>> for ReadUser
+where
+    S: StorageBackend,
+    A: AuthProvider,
+{
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState<S, A>,
+    ) -> Result<Self, Self::Rejection> {
+        let token = bearer_token(parts);
+        if token.is_empty() && state.allow_anonymous_read {
+            return Ok(ReadUser(User {
+                id: "anonymous".to_string(),
+                scopes: vec!["read".to_string()],
+            }));
+        }
+
+        verify(token, state).await.map(ReadUser)
+    }
+}
+
+/// Like [`ReadUser`], but also accepts a signed download link with a `?token=` minted by
+/// `GET /assets/{hash}/sign`, see [`JwtService::mint_download`].
+#[derive(Clone, Debug)]
+pub struct DownloadUser(pub User);
+
+impl<S, A> FromRequestParts<AppState<S, A>> for DownloadUser
+where
+    S: StorageBackend,
+    A: AuthProvider,
+{
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState<S, A>,
+    ) -> Result<Self, Self::Rejection> {
+        #[derive(serde::Deserialize)]
+        struct SignedParams {
+            token: Option<String>,
+        }
+
+        let unauthorized = || (StatusCode::UNAUTHORIZED, "Unauthorized".to_string());
+        let Ok(Query(SignedParams { token: Some(token) })) = Query::try_from_uri(&parts.uri) else {
+            return ReadUser::from_request_parts(parts, state)
+                .await
+                .map(|ReadUser(user)| DownloadUser(user));
+        };
+        let Path(hash) = Path::<String>::from_request_parts(parts, state)
+            .await
+            .map_err(|_| unauthorized())?;
+
+        state
+            .jwt_service
+            .verify_download(&token, &hash)
+            .map(DownloadUser)
+            .map_err(|_| unauthorized())
+    }
+}
+
+/// Extracts the token from the `Authorization` header, empty if missing.
+fn bearer_token(parts: &Parts) -> &str {
+    parts
+        .headers
+        .get("Authorization")
+        .and_then(|auth_header| {
+            auth_header
+                .to_str()
+                .map(|header_str| {
+                    header_str
+                        .strip_prefix("Bearer ")
+                        .unwrap_or(header_str)
+                        .trim()
+                })
+                .ok()
+        })
+        .unwrap_or("")
+}
+
+async fn verify<S: StorageBackend, A: AuthProvider>(
+    token: &str,
+    state: &AppState<S, A>,
+) -> Result<User, (StatusCode, String)> {
+    match state.auth.verify(token).await {
+        Ok(user) => Ok(user),
+        Err(AuthError::System(_)) => Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Authentication unavailable".to_string(),
+        )),
+        Err(_) => Err((StatusCode::UNAUTHORIZED, "Unauthorized".to_string())),
+    }
+}
+
+/// Scopes a route requires. The `admin` scope satisfies any requirement.
+///
+/// ```
+/// # use aquila_server::auth::RequiredScopes;
+/// # use aquila_core::prelude::User;
+/// let user = |scopes: &[&str]| User {
+///     id: "test".into(),
+///     scopes: scopes.iter().map(|s| s.to_string()).collect(),
+/// };
+///
+/// let all = RequiredScopes::All(&["read", "write"]);
+/// assert!(all.is_satisfied_by(&user(&["read", "write"])));
+/// assert!(!all.is_satisfied_by(&user(&["read"])));
+/// assert!(all.is_satisfied_by(&user(&["admin"])));
+///
+/// let any = RequiredScopes::Any(&["read", "write"]);
+/// assert!(any.is_satisfied_by(&user(&["write"])));
+/// assert!(!any.is_satisfied_by(&user(&["other"])));
+/// assert!(any.is_satisfied_by(&user(&["admin"])));
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub enum RequiredScopes<'a> {
+    /// Every scope is required.
+    All(&'a [&'a str]),
+    /// One of the scopes is sufficient.
+    Any(&'a [&'a str]),
+}
+
+impl RequiredScopes<'_> {
+    pub fn is_satisfied_by(&self, user: &User) -> bool {
+        let has = |scope: &str| user.scopes.iter().any(|s| s == scope);
+        if has("admin") {
+            return true;
+        }
+
+        match self {
+            Self::All(scopes) => scopes.iter().all(|scope| has(scope)),
+            Self::Any(scopes) => scopes.iter().any(|scope| has(scope)),
         }
     }
 }
@@ -76,3 +207,294 @@ impl<P: AuthProvider> AuthProvider for JWTServiceAuthProvider<P> {
         self.provider.exchange_code(code).await
     }
 }
+
+/// Combines two providers, accepting tokens of either, e.g. static API keys and GitHub tokens.
+///
+/// Tokens are verified by `first`, then by `second` if `first` fails for any reason, so e.g. an
+/// outage of `first` doesn't lock out the users of `second`. If both fail, the error of `second`
+/// is returned. A missing token is rejected without asking either provider. Nest to chain more
+/// providers, e.g. `OrAuth::new(a, OrAuth::new(b, c))`.
+///
+/// The login flow is delegated to the first provider with a login URL.
+///
+/// ```
+/// # use aquila_server::prelude::*;
+/// # use aquila_core::prelude::*;
+/// # use aquila_auth_mock::MockAuth;
+/// #[derive(Clone)]
+/// struct Unreachable;
+///
+/// impl AuthProvider for Unreachable {
+///     async fn verify(&self, _token: &str) -> Result<User, AuthError> {
+///         Err(AuthError::System("unreachable".into()))
+///     }
+/// }
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let keys = MockAuth::default().with_token("key", ["read"]);
+/// let auth = OrAuth::new(keys.clone(), Unreachable);
+/// assert!(auth.verify("key").await.is_ok());
+/// assert!(matches!(auth.verify("other").await, Err(AuthError::System(_))));
+///
+/// let auth = OrAuth::new(Unreachable, keys);
+/// assert!(auth.verify("key").await.is_ok());
+/// assert!(matches!(auth.verify("other").await, Err(AuthError::InvalidToken)));
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct OrAuth<A: AuthProvider, B: AuthProvider> {
+    first: A,
+    second: B,
+}
+
+impl<A: AuthProvider, B: AuthProvider> OrAuth<A, B> {
+    pub fn new(first: A, second: B) -> Self {
+        Self { first, second }
+    }
+}
+
+impl<A: AuthProvider, B: AuthProvider> AuthProvider for OrAuth<A, B> {
+    async fn verify(&self, token: &str) -> Result<User, AuthError> {
+        if token.is_empty() {
+            return Err(AuthError::InvalidToken);
+        }
+
+        match self.first.verify(token).await {
+            Ok(user) => Ok(user),
+            Err(_) => self.second.verify(token).await,
+        }
+    }
+
+    fn get_login_url(&self) -> Option<String> {
+        self.first
+            .get_login_url()
+            .or_else(|| self.second.get_login_url())
+    }
+
+    async fn exchange_code(&self, code: &str) -> Result<User, AuthError> {
+        if self.first.get_login_url().is_some() {
+            self.first.exchange_code(code).await
+        } else {
+            self.second.exchange_code(code).await
+        }
+    }
+}
+
+/// Bounds how long a provider may take, so a slow identity provider can't stall every request.
+///
+/// If `verify` or `exchange_code` don't finish within the deadline, they fail with
+/// [`AuthError::System`], which is answered with `503 Service Unavailable`.
+#[derive(Clone)]
+pub struct TimeoutAuth<P: AuthProvider> {
+    provider: P,
+    deadline: Duration,
+}
+
+impl<P: AuthProvider> TimeoutAuth<P> {
+    pub fn new(provider: P, deadline: Duration) -> Self {
+        Self { provider, deadline }
+    }
+}
+
+impl<P: AuthProvider> AuthProvider for TimeoutAuth<P> {
+    async fn verify(&self, token: &str) -> Result<User, AuthError> {
+        tokio::time::timeout(self.deadline, self.provider.verify(token))
+            .await
+            .map_err(|_| AuthError::System("auth timeout".into()))?
+    }
+
+    fn get_login_url(&self) -> Option<String> {
+        self.provider.get_login_url()
+    }
+
+    async fn exchange_code(&self, code: &str) -> Result<User, AuthError> {
+        tokio::time::timeout(self.deadline, self.provider.exchange_code(code))
+            .await
+            .map_err(|_| AuthError::System("auth timeout".into()))?
+    }
+}
+
+/// Translates the scopes of a provider with a [`ScopeMapper`], e.g. roles of an identity
+/// provider like `aquila-writer` into `read` and `write`.
+///
+/// The scopes of the user returned by `provider` are passed as claims, the user gets the mapped
+/// scopes instead. Wrap the identity provider itself, not a [`JWTServiceAuthProvider`], as
+/// tokens minted by the server already carry Aquila scopes.
+///
+/// ```
+/// # use aquila_server::prelude::*;
+/// # use aquila_core::prelude::*;
+/// # use aquila_auth_mock::MockAuth;
+/// # #[tokio::main]
+/// # async fn main() {
+/// # let config = AquilaServerConfig::default();
+/// let idp = MockAuth::default().with_token("token", ["aquila-writer"]);
+/// let table: ScopeTable = "aquila-writer=read write, aquila-reader=read".parse().unwrap();
+/// let auth = JWTServiceAuthProvider::new(config.jwt_service(), ScopeMappingAuth::new(idp, table));
+///
+/// assert_eq!(auth.verify("token").await.unwrap().scopes, ["read", "write"]);
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct ScopeMappingAuth<P: AuthProvider> {
+    provider: P,
+    mapper: Arc<dyn ScopeMapper>,
+}
+
+impl<P: AuthProvider> ScopeMappingAuth<P> {
+    pub fn new(provider: P, mapper: impl ScopeMapper) -> Self {
+        Self {
+            provider,
+            mapper: Arc::new(mapper),
+        }
+    }
+
+    /// Private helper to replace the claims of `user` with the mapped scopes.
+    fn map(&self, mut user: User) -> User {
+        user.scopes = self.mapper.map(&user.scopes);
+        user
+    }
+}
+
+impl<P: AuthProvider> AuthProvider for ScopeMappingAuth<P> {
+    async fn verify(&self, token: &str) -> Result<User, AuthError> {
+        self.provider.verify(token).await.map(|user| self.map(user))
+    }
+
+    fn get_login_url(&self) -> Option<String> {
+        self.provider.get_login_url()
+    }
+
+    async fn exchange_code(&self, code: &str) -> Result<User, AuthError> {
+        self.provider
+            .exchange_code(code)
+            .await
+            .map(|user| self.map(user))
+    }
+}
+
+/// Removes scopes from specific users, regardless of what their token claims, e.g. to suspend
+/// an uploader without re-minting tokens.
+///
+/// Wrap the outermost provider, usually a [`JWTServiceAuthProvider`], so minted tokens are
+/// covered too. As `admin` grants every scope, deny it as well to restrict an admin.
+///
+/// ```
+/// # use aquila_server::prelude::*;
+/// # use aquila_core::prelude::*;
+/// # use aquila_auth_mock::MockAuth;
+/// # #[tokio::main]
+/// # async fn main() {
+/// let idp = MockAuth::default().with_token("token", ["read", "write"]);
+/// let auth = DenyListAuth::new(idp).with_denied("token", ["write"]);
+///
+/// assert_eq!(auth.verify("token").await.unwrap().scopes, ["read"]);
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct DenyListAuth<P: AuthProvider> {
+    provider: P,
+    denied: Arc<HashMap<String, HashSet<String>>>,
+}
+
+impl<P: AuthProvider> DenyListAuth<P> {
+    pub fn new(provider: P) -> Self {
+        Self {
+            provider,
+            denied: Default::default(),
+        }
+    }
+
+    /// Removes `scopes` from the user with the id `subject`.
+    pub fn with_denied(
+        mut self,
+        subject: impl Into<String>,
+        scopes: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        Arc::make_mut(&mut self.denied)
+            .entry(subject.into())
+            .or_default()
+            .extend(scopes.into_iter().map(Into::into));
+        self
+    }
+
+    /// Private helper to strip the denied scopes from `user`.
+    fn strip(&self, mut user: User) -> User {
+        if let Some(denied) = self.denied.get(&user.id) {
+            user.scopes.retain(|scope| !denied.contains(scope));
+        }
+        user
+    }
+}
+
+impl<P: AuthProvider> AuthProvider for DenyListAuth<P> {
+    async fn verify(&self, token: &str) -> Result<User, AuthError> {
+        self.provider
+            .verify(token)
+            .await
+            .map(|user| self.strip(user))
+    }
+
+    fn get_login_url(&self) -> Option<String> {
+        self.provider.get_login_url()
+    }
+
+    async fn exchange_code(&self, code: &str) -> Result<User, AuthError> {
+        self.provider
+            .exchange_code(code)
+            .await
+            .map(|user| self.strip(user))
+    }
+}
+
+/// Accepts a fixed set of long-lived tokens, each for a named user with its own scopes, e.g. for
+/// a CI pipeline or a server without an identity provider.
+///
+/// Tokens are only kept as SHA256 hashes and looked up by them, so they aren't compared byte by
+/// byte and the user id doesn't reveal them.
+///
+/// ```
+/// # use aquila_server::prelude::*;
+/// # use aquila_core::prelude::*;
+/// # #[tokio::main]
+/// # async fn main() {
+/// let auth = StaticTokenAuth::default().with_user("ci", "secret-token", ["read", "write"]);
+///
+/// let user = auth.verify("secret-token").await.unwrap();
+/// assert_eq!(user.id, "ci");
+/// assert_eq!(user.scopes, ["read", "write"]);
+/// assert!(matches!(auth.verify("ci").await, Err(AuthError::InvalidToken)));
+/// # }
+/// ```
+#[derive(Clone, Default)]
+pub struct StaticTokenAuth {
+    users: Arc<HashMap<[u8; 32], User>>,
+}
+
+impl StaticTokenAuth {
+    /// Accepts `token` as the user `id` with `scopes`.
+    pub fn with_user(
+        mut self,
+        id: impl Into<String>,
+        token: impl AsRef<[u8]>,
+        scopes: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        let user = User {
+            id: id.into(),
+            scopes: scopes.into_iter().map(Into::into).collect(),
+        };
+        Arc::make_mut(&mut self.users).insert(Sha256::digest(token).into(), user);
+        self
+    }
+}
+
+impl AuthProvider for StaticTokenAuth {
+    async fn verify(&self, token: &str) -> Result<User, AuthError> {
+        let hash: [u8; 32] = Sha256::digest(token).into();
+        self.users
+            .get(&hash)
+            .cloned()
+            .ok_or(AuthError::InvalidToken)
+    }
+}
@@ -0,0 +1,182 @@
+//! The client behind trusted reverse proxies, see [`ClientInfo`].
+
+use axum::extract::{ConnectInfo, FromRequestParts, Request};
+use axum::http::{HeaderMap, header, request::Parts};
+use axum::middleware::Next;
+use axum::response::Response;
+use ipnet::IpNet;
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+const FORWARDED_FOR: &str = "x-forwarded-for";
+const FORWARDED_PROTO: &str = "x-forwarded-proto";
+
+/// The client of a request and the scheme it used, e.g. for audit logs or IP based controls.
+///
+/// Behind a reverse proxy, the peer of the connection is the proxy. If it is one of the
+/// [`trusted_proxies`](crate::server::AquilaServerConfig::trusted_proxies), the client is read
+/// from the `Forwarded` header, or `X-Forwarded-For` and `X-Forwarded-Proto` without it.
+/// Headers of other peers are ignored, as anyone can send them. Without a trusted proxy, the
+/// scheme is `http`, as the router can't tell whether it is served over TLS.
+///
+/// The IP is `None` if the router isn't served with
+/// [`into_make_service_with_connect_info`](axum::Router::into_make_service_with_connect_info).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientInfo {
+    pub ip: Option<IpAddr>,
+    /// `http` or `https`, as seen by the client.
+    pub scheme: String,
+}
+
+impl ClientInfo {
+    /// Resolves the client of a request from `peer` with `headers`.
+    ///
+    /// Forwarded entries are read from right to left, skipping trusted proxies, so entries
+    /// prepended by the client can't spoof its IP.
+    ///
+    /// ```
+    /// # use aquila_server::prelude::*;
+    /// # use axum::http::HeaderMap;
+    /// let proxies = ["10.0.0.0/8".parse().unwrap()];
+    /// let peer = "10.0.0.2".parse().ok();
+    /// let mut headers = HeaderMap::new();
+    /// headers.insert("forwarded", "for=198.51.100.1, for=203.0.113.7;proto=https".parse().unwrap());
+    ///
+    /// let client = ClientInfo::resolve(peer, &headers, &proxies);
+    /// assert_eq!(client.ip, "203.0.113.7".parse().ok());
+    /// assert_eq!(client.scheme, "https");
+    ///
+    /// // Headers of untrusted peers are ignored.
+    /// let client = ClientInfo::resolve("192.0.2.1".parse().ok(), &headers, &proxies);
+    /// assert_eq!(client.ip, "192.0.2.1".parse().ok());
+    /// assert_eq!(client.scheme, "http");
+    /// ```
+    pub fn resolve(peer: Option<IpAddr>, headers: &HeaderMap, trusted_proxies: &[IpNet]) -> Self {
+        let mut client = ClientInfo {
+            ip: peer,
+            scheme: "http".into(),
+        };
+        let trusted = |ip: &IpAddr| {
+            let ip = ip.to_canonical();
+            trusted_proxies.iter().any(|net| net.contains(&ip))
+        };
+        if !peer.is_some_and(|peer| trusted(&peer)) {
+            return client;
+        }
+
+        // Each hop is added by a proxy and describes the connection it received from `ip`.
+        for hop in forwarded_hops(headers).into_iter().rev() {
+            // An entry the proxy couldn't identify ends the trusted chain.
+            let Some(ip) = hop.ip else {
+                break;
+            };
+            client.ip = Some(ip);
+            if let Some(proto) = hop.proto {
+                client.scheme = proto;
+            }
+            if !trusted(&ip) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for ClientInfo {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(client) = parts.extensions.get::<ClientInfo>() {
+            return Ok(client.clone());
+        }
+        let peer = connect_info(&parts.extensions);
+        Ok(ClientInfo::resolve(peer, &parts.headers, &[]))
+    }
+}
+
+/// A proxy hop of a `Forwarded` or `X-Forwarded-*` header.
+struct Hop {
+    ip: Option<IpAddr>,
+    proto: Option<String>,
+}
+
+/// The hops of the `Forwarded` header, or the `X-Forwarded-*` headers without it, in order.
+fn forwarded_hops(headers: &HeaderMap) -> Vec<Hop> {
+    let values = |name| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|val| val.to_str().ok())
+            .flat_map(|val| val.split(','))
+            .map(str::trim)
+            .collect::<Vec<_>>()
+    };
+
+    let forwarded = values(header::FORWARDED.as_str());
+    if !forwarded.is_empty() {
+        return forwarded
+            .into_iter()
+            .map(|element| {
+                let mut hop = Hop {
+                    ip: None,
+                    proto: None,
+                };
+                for pair in element.split(';') {
+                    let Some((key, value)) = pair.split_once('=') else {
+                        continue;
+                    };
+                    let value = value.trim().trim_matches('"');
+                    match key.trim().to_ascii_lowercase().as_str() {
+                        "for" => hop.ip = parse_node(value),
+                        "proto" => hop.proto = Some(value.to_ascii_lowercase()),
+                        _ => {}
+                    }
+                }
+                hop
+            })
+            .collect();
+    }
+
+    let mut hops: Vec<Hop> = values(FORWARDED_FOR)
+        .into_iter()
+        .map(|ip| Hop {
+            ip: parse_node(ip),
+            proto: None,
+        })
+        .collect();
+    // Only the last proxy's scheme is known, it belongs to the client it saw.
+    if let (Some(hop), Some(proto)) = (hops.last_mut(), values(FORWARDED_PROTO).last()) {
+        hop.proto = Some(proto.to_ascii_lowercase());
+    }
+    hops
+}
+
+/// Parses a node of a forwarded header, e.g. `192.0.2.1`, `192.0.2.1:80` or `[2001:db8::1]:80`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    node.parse::<IpAddr>()
+        .or_else(|_| node.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .ok()
+        .or_else(|| {
+            let ip = node.strip_prefix('[')?.split(']').next()?;
+            ip.parse().ok()
+        })
+}
+
+fn connect_info(extensions: &axum::http::Extensions) -> Option<IpAddr> {
+    extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(peer)| peer.ip())
+}
+
+/// Resolves the [`ClientInfo`] of every request once, so handlers and layers agree on it.
+pub(crate) async fn resolve_client(
+    trusted_proxies: Arc<[IpNet]>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let peer = connect_info(request.extensions());
+    let client = ClientInfo::resolve(peer, request.headers(), &trusted_proxies);
+    request.extensions_mut().insert(client);
+    next.run(request).await
+}
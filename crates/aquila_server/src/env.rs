@@ -0,0 +1,312 @@
+//! Configuration from `AQUILA_*` environment variables, see [`AquilaServerConfig::from_env`].
+
+use crate::ip_filter::{IpFilter, IpNet};
+use crate::jwt::TokenTtlCaps;
+use crate::server::{AquilaServerConfig, DownloadMode};
+use aquila_core::signing::signing_key_from_hex;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// An environment variable with an invalid value.
+#[derive(Debug)]
+pub struct EnvError {
+    pub var: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for EnvError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid {}: {}", self.var, self.message)
+    }
+}
+
+impl std::error::Error for EnvError {}
+
+impl AquilaServerConfig {
+    /// Reads the config from `AQUILA_*` environment variables, e.g. for container deployments.
+    ///
+    /// Unset or empty variables keep their [default](AquilaServerConfig::default):
+    ///
+    /// | Variable | Field | Format |
+    /// |---|---|---|
+    /// | `AQUILA_JWT_SECRET` | `jwt_secret` | string |
+    /// | `AQUILA_JWT_ISSUER` | `jwt_issuer` | string |
+    /// | `AQUILA_JWT_AUDIENCE` | `jwt_audience` | string |
+    /// | `AQUILA_CALLBACK` | `callback` | path, e.g. `/auth/callback` |
+    /// | `AQUILA_SIGNING_KEY` | `signing_key` | hex, see `generate_signing_key` |
+    /// | `AQUILA_TOKEN_TTL_CAPS` | `token_ttl_caps` | `scope=duration` list, e.g. `read=30d,write=12h` |
+    /// | `AQUILA_MINTABLE_SCOPES` | `mintable_scopes` | list, e.g. `read,deploy` |
+    /// | `AQUILA_ALLOW_ANONYMOUS_READ` | `allow_anonymous_read` | bool |
+    /// | `AQUILA_MANIFEST_VARS` | `manifest_vars` | `name=value` list |
+    /// | `AQUILA_MAX_BODY_SIZE` | `max_body_size` | size, e.g. `512M` |
+    /// | `AQUILA_VERIFY_UPLOADS` | `verify_uploads` | bool |
+    /// | `AQUILA_MAX_CONCURRENT_UPLOADS` | `max_concurrent_uploads` | number |
+    /// | `AQUILA_STREAM_UPLOAD_THRESHOLD` | `stream_upload_threshold` | size |
+    /// | `AQUILA_MANIFEST_CACHE_TTL` | `manifest_cache_ttl` | duration |
+    /// | `AQUILA_DOWNLOAD_MODE` | `download_mode` | `auto`, `proxy` or `redirect` |
+    /// | `AQUILA_ARCHIVE_PREFETCH` | `archive_prefetch` | number |
+    /// | `AQUILA_VERIFY_ON_READ` | `verify_on_read` | bool |
+    /// | `AQUILA_WRITE_ALLOW` | `write_ip_filter` | CIDR list, e.g. `10.0.0.0/8,192.168.1.7/32` |
+    /// | `AQUILA_WRITE_DENY` | `write_ip_filter` | CIDR list |
+    /// | `AQUILA_TRUSTED_PROXIES` | `trusted_proxies` | CIDR list |
+    ///
+    /// Lists are comma separated. Bools are `true`/`false`, `1`/`0`, `yes`/`no` or `on`/`off`.
+    /// Durations are seconds, or a number with an `s`, `m`, `h` or `d` suffix. Sizes are bytes,
+    /// or a number with a `K`, `M` or `G` suffix (powers of 1024).
+    ///
+    /// Fields without a variable, e.g. `transformer`, can be set afterwards:
+    ///
+    /// ```no_run
+    /// # use aquila_server::prelude::*;
+    /// # fn run() -> Result<(), EnvError> {
+    /// let config = AquilaServerConfig {
+    ///     transformer: None,
+    ///     ..AquilaServerConfig::from_env()?
+    /// };
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn from_env() -> Result<Self, EnvError> {
+        Self::from_vars(std::env::vars())
+    }
+
+    /// Like [`from_env`](Self::from_env), but reads the variables from `vars`.
+    ///
+    /// ```
+    /// # use aquila_server::prelude::*;
+    /// let config = AquilaServerConfig::from_vars([
+    ///     ("AQUILA_CALLBACK", "/login/callback"),
+    ///     ("AQUILA_ALLOW_ANONYMOUS_READ", "yes"),
+    ///     ("AQUILA_MINTABLE_SCOPES", "read, deploy"),
+    ///     ("AQUILA_TOKEN_TTL_CAPS", "read=30d,write=12h"),
+    ///     ("AQUILA_MAX_BODY_SIZE", "512M"),
+    ///     ("AQUILA_JWT_ISSUER", ""),
+    ///     ("AQUILA_DOWNLOAD_MODE", "Proxy"),
+    ///     ("AQUILA_WRITE_ALLOW", "10.0.0.0/8, 192.168.1.7"),
+    /// ])
+    /// .unwrap();
+    ///
+    /// assert_eq!(config.callback, "/login/callback");
+    /// assert!(config.allow_anonymous_read);
+    /// assert_eq!(config.mintable_scopes, ["read", "deploy"]);
+    /// assert_eq!(config.token_ttl_caps.clamp(&["read".into()], u64::MAX), 30 * 24 * 60 * 60);
+    /// assert_eq!(config.max_body_size, Some(512 * 1024 * 1024));
+    /// assert_eq!(config.jwt_issuer, None);
+    /// assert_eq!(config.download_mode, DownloadMode::Proxy);
+    /// let filter = config.write_ip_filter.unwrap();
+    /// assert!(filter.is_allowed("192.168.1.7".parse().unwrap()));
+    /// assert!(!filter.is_allowed("192.168.1.8".parse().unwrap()));
+    ///
+    /// let err = AquilaServerConfig::from_vars([("AQUILA_ALLOW_ANONYMOUS_READ", "maybe")]);
+    /// assert_eq!(err.unwrap_err().var, "AQUILA_ALLOW_ANONYMOUS_READ");
+    /// assert!(AquilaServerConfig::from_vars([("AQUILA_TOKEN_TTL_CAPS", "read=1w")]).is_err());
+    /// assert!(AquilaServerConfig::from_vars([("AQUILA_MAX_BODY_SIZE", "-1")]).is_err());
+    /// assert!(AquilaServerConfig::from_vars([("AQUILA_CALLBACK", "callback")]).is_err());
+    /// assert!(AquilaServerConfig::from_vars([("AQUILA_WRITE_DENY", "10.0.0.0/33")]).is_err());
+    /// ```
+    pub fn from_vars<K, V>(vars: impl IntoIterator<Item = (K, V)>) -> Result<Self, EnvError>
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        let vars = Vars(
+            vars.into_iter()
+                .map(|(key, value)| (key.into(), value.into()))
+                .filter(|(key, value)| key.starts_with("AQUILA_") && !value.trim().is_empty())
+                .collect(),
+        );
+        let mut config = Self::default();
+
+        if let Some(secret) = vars.get("AQUILA_JWT_SECRET") {
+            config.jwt_secret = secret.to_string();
+        }
+        config.jwt_issuer = vars.get("AQUILA_JWT_ISSUER").map(String::from);
+        config.jwt_audience = vars.get("AQUILA_JWT_AUDIENCE").map(String::from);
+
+        if let Some(callback) = vars.parse("AQUILA_CALLBACK", parse_path)? {
+            config.callback = callback;
+        }
+        config.signing_key = vars.parse("AQUILA_SIGNING_KEY", |value| {
+            signing_key_from_hex(value).map_err(|e| e.to_string())
+        })?;
+        if let Some(caps) = vars.parse("AQUILA_TOKEN_TTL_CAPS", parse_ttl_caps)? {
+            config.token_ttl_caps = caps;
+        }
+        if let Some(scopes) = vars.parse("AQUILA_MINTABLE_SCOPES", |value| Ok(parse_list(value)))? {
+            config.mintable_scopes = scopes;
+        }
+        if let Some(allow) = vars.parse("AQUILA_ALLOW_ANONYMOUS_READ", parse_bool)? {
+            config.allow_anonymous_read = allow;
+        }
+        config.manifest_vars = vars.parse("AQUILA_MANIFEST_VARS", |value| {
+            parse_pairs(value).map(|pairs| pairs.into_iter().collect())
+        })?;
+        config.max_body_size = vars.parse("AQUILA_MAX_BODY_SIZE", parse_size)?;
+        if let Some(verify) = vars.parse("AQUILA_VERIFY_UPLOADS", parse_bool)? {
+            config.verify_uploads = verify;
+        }
+        if let Some(threshold) = vars.parse("AQUILA_STREAM_UPLOAD_THRESHOLD", parse_size)? {
+            config.stream_upload_threshold = threshold as u64;
+        }
+        config.manifest_cache_ttl = vars
+            .parse("AQUILA_MANIFEST_CACHE_TTL", parse_duration)?
+            .map(Duration::from_secs);
+        config.max_concurrent_uploads =
+            vars.parse("AQUILA_MAX_CONCURRENT_UPLOADS", parse_number)?;
+        if let Some(mode) = vars.parse("AQUILA_DOWNLOAD_MODE", parse_download_mode)? {
+            config.download_mode = mode;
+        }
+        if let Some(prefetch) = vars.parse("AQUILA_ARCHIVE_PREFETCH", parse_number)? {
+            config.archive_prefetch = prefetch;
+        }
+        if let Some(verify) = vars.parse("AQUILA_VERIFY_ON_READ", parse_bool)? {
+            config.verify_on_read = verify;
+        }
+        let allow = vars.parse("AQUILA_WRITE_ALLOW", parse_networks)?;
+        let deny = vars.parse("AQUILA_WRITE_DENY", parse_networks)?;
+        if allow.is_some() || deny.is_some() {
+            let filter = IpFilter::default();
+            let filter = allow.into_iter().flatten().fold(filter, IpFilter::allow);
+            let filter = deny.into_iter().flatten().fold(filter, IpFilter::deny);
+            config.write_ip_filter = Some(filter);
+        }
+        if let Some(proxies) = vars.parse("AQUILA_TRUSTED_PROXIES", parse_networks)? {
+            config.trusted_proxies = proxies;
+        }
+
+        Ok(config)
+    }
+}
+
+/// The non-empty `AQUILA_*` variables.
+struct Vars(HashMap<String, String>);
+
+impl Vars {
+    fn get(&self, var: &str) -> Option<&str> {
+        self.0.get(var).map(|value| value.trim())
+    }
+
+    /// Parses `var` if set, attributing errors to it.
+    fn parse<T>(
+        &self,
+        var: &str,
+        parse: impl FnOnce(&str) -> Result<T, String>,
+    ) -> Result<Option<T>, EnvError> {
+        self.get(var)
+            .map(parse)
+            .transpose()
+            .map_err(|message| EnvError {
+                var: var.to_string(),
+                message,
+            })
+    }
+}
+
+fn parse_path(value: &str) -> Result<String, String> {
+    if !value.starts_with('/') {
+        return Err(format!("`{value}` must start with `/`"));
+    }
+    Ok(value.to_string())
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "1" | "yes" | "on" => Ok(true),
+        "false" | "0" | "no" | "off" => Ok(false),
+        _ => Err(format!("`{value}` is not a bool")),
+    }
+}
+
+fn parse_number(value: &str) -> Result<usize, String> {
+    value
+        .parse()
+        .map_err(|_| format!("`{value}` is not a number"))
+}
+
+fn parse_download_mode(value: &str) -> Result<DownloadMode, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "auto" => Ok(DownloadMode::Auto),
+        "proxy" => Ok(DownloadMode::Proxy),
+        "redirect" => Ok(DownloadMode::Redirect),
+        _ => Err(format!("`{value}` is not `auto`, `proxy` or `redirect`")),
+    }
+}
+
+fn parse_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Parses a list of networks, a single IP is a network of one.
+fn parse_networks(value: &str) -> Result<Vec<IpNet>, String> {
+    parse_list(value)
+        .into_iter()
+        .map(|network| {
+            network
+                .parse::<IpNet>()
+                .or_else(|_| network.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|_| format!("`{network}` is not a network, e.g. `10.0.0.0/8`"))
+        })
+        .collect()
+}
+
+/// Parses a `name=value` list.
+fn parse_pairs(value: &str) -> Result<Vec<(String, String)>, String> {
+    parse_list(value)
+        .into_iter()
+        .map(|pair| match pair.split_once('=') {
+            Some((name, value)) if !name.trim().is_empty() => {
+                Ok((name.trim().to_string(), value.trim().to_string()))
+            }
+            _ => Err(format!("`{pair}` is not a `name=value` pair")),
+        })
+        .collect()
+}
+
+fn parse_ttl_caps(value: &str) -> Result<TokenTtlCaps, String> {
+    parse_pairs(value)?
+        .into_iter()
+        .try_fold(TokenTtlCaps::default(), |caps, (scope, duration)| {
+            Ok(caps.with_cap(scope, parse_duration(&duration)?))
+        })
+}
+
+/// Parses a number with an optional unit suffix into the base unit.
+fn parse_number_with_unit(value: &str, units: &[(char, u64)]) -> Result<u64, String> {
+    let (number, multiplier) = match value.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => {
+            let unit = units
+                .iter()
+                .find(|(unit, _)| unit.eq_ignore_ascii_case(&c))
+                .ok_or_else(|| format!("unknown unit `{c}` in `{value}`"))?;
+            (&value[..i], unit.1)
+        }
+        _ => (value, 1),
+    };
+
+    number
+        .trim()
+        .parse::<u64>()
+        .map_err(|_| format!("`{value}` is not a number"))?
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("`{value}` is too large"))
+}
+
+/// Parses seconds, e.g. `3600`, `60m` or `30d`.
+fn parse_duration(value: &str) -> Result<u64, String> {
+    parse_number_with_unit(
+        value,
+        &[('s', 1), ('m', 60), ('h', 60 * 60), ('d', 24 * 60 * 60)],
+    )
+}
+
+/// Parses bytes, e.g. `1048576`, `1024K` or `1M`.
+fn parse_size(value: &str) -> Result<usize, String> {
+    let size = parse_number_with_unit(value, &[('k', 1 << 10), ('m', 1 << 20), ('g', 1 << 30)])?;
+    usize::try_from(size).map_err(|_| format!("`{value}` is too large"))
+}
@@ -0,0 +1,87 @@
+//! Source IP allow and deny lists, see `AquilaServerConfig::write_ip_filter`.
+
+use crate::client_info::ClientInfo;
+use axum::extract::Request;
+use axum::http::{Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::net::IpAddr;
+use std::sync::Arc;
+use tracing::warn;
+
+pub use ipnet::IpNet;
+
+/// Restricts requests by the source IP of the client.
+///
+/// An IP is allowed if it matches no `deny` network and, unless `allow` is empty, an `allow`
+/// network. The IP is the one of the [`ClientInfo`], so behind a reverse proxy, add it to the
+/// `trusted_proxies` of the config.
+///
+/// ```
+/// # use aquila_server::prelude::*;
+/// let filter = IpFilter::default()
+///     .allow("10.0.0.0/8".parse().unwrap())
+///     .deny("10.0.13.0/24".parse().unwrap());
+///
+/// assert!(filter.is_allowed("10.1.2.3".parse().unwrap()));
+/// assert!(!filter.is_allowed("10.0.13.37".parse().unwrap()));
+/// assert!(!filter.is_allowed("203.0.113.1".parse().unwrap()));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct IpFilter {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+}
+
+impl IpFilter {
+    /// Allows `network`. Once any network is allowed, all others are denied.
+    pub fn allow(mut self, network: IpNet) -> Self {
+        self.allow.push(network);
+        self
+    }
+
+    /// Denies `network`, even if it is within an allowed one.
+    pub fn deny(mut self, network: IpNet) -> Self {
+        self.deny.push(network);
+        self
+    }
+
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        if self.deny.iter().any(|net| net.contains(&ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip))
+    }
+}
+
+/// Rejects write requests of clients not allowed by `filter` with `403 Forbidden`.
+///
+/// Writes are requests with a method other than `GET`, `HEAD` or `OPTIONS`. Requests without a
+/// client IP are rejected too, see [`ClientInfo`].
+pub(crate) async fn filter_writes(filter: Arc<IpFilter>, request: Request, next: Next) -> Response {
+    if matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    ) {
+        return next.run(request).await;
+    }
+
+    let client = request
+        .extensions()
+        .get::<ClientInfo>()
+        .and_then(|client| client.ip);
+    let Some(client) = client else {
+        warn!("`write_ip_filter` is set, but the server is served without `ConnectInfo`");
+        return (StatusCode::FORBIDDEN, "Source IP unknown").into_response();
+    };
+
+    if !filter.is_allowed(client) {
+        return (
+            StatusCode::FORBIDDEN,
+            format!("Writes from {client} are not allowed"),
+        )
+            .into_response();
+    }
+    next.run(request).await
+}
@@ -1,19 +1,31 @@
+use crate::tenant::TENANT_SCOPE_PREFIX;
 use aquila_core::prelude::{AuthError, User};
 use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation, decode, encode};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+/// Scope of a token that only allows downloading one blob, e.g. `download:{hash}`,
+/// see [`JwtService::mint_download`].
+pub const DOWNLOAD_SCOPE_PREFIX: &str = "download:";
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,
     pub exp: usize,
     pub scopes: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
 }
 
 #[derive(Clone)]
 pub struct JwtService {
     encoding_key: EncodingKey,
     decoding_key: DecodingKey,
+    issuer: Option<String>,
+    audience: Option<String>,
 }
 
 impl JwtService {
@@ -21,9 +33,33 @@ impl JwtService {
         Self {
             encoding_key: EncodingKey::from_secret(secret.as_bytes()),
             decoding_key: DecodingKey::from_secret(secret.as_bytes()),
+            issuer: None,
+            audience: None,
         }
     }
 
+    /// Sets the `iss` claim of minted tokens, tokens of other issuers are rejected.
+    pub fn with_issuer(mut self, issuer: impl Into<String>) -> Self {
+        self.issuer = Some(issuer.into());
+        self
+    }
+
+    /// Sets the `aud` claim of minted tokens, tokens for other audiences are rejected.
+    ///
+    /// ```
+    /// # use aquila_server::jwt::JwtService;
+    /// let staging = JwtService::new("secret").with_audience("staging");
+    /// let production = JwtService::new("secret").with_audience("production");
+    ///
+    /// let token = staging.mint("ci".into(), vec!["read".into()], 60).unwrap();
+    /// assert!(staging.verify(&token).is_ok());
+    /// assert!(production.verify(&token).is_err());
+    /// ```
+    pub fn with_audience(mut self, audience: impl Into<String>) -> Self {
+        self.audience = Some(audience.into());
+        self
+    }
+
     pub fn mint(
         &self,
         subject: String,
@@ -35,6 +71,8 @@ impl JwtService {
             sub: subject,
             exp: expiration as usize,
             scopes,
+            iss: self.issuer.clone(),
+            aud: self.audience.clone(),
         };
 
         let token = encode(&Header::default(), &claims, &self.encoding_key)?;
@@ -42,7 +80,13 @@ impl JwtService {
     }
 
     pub fn verify(&self, token: &str) -> Result<User, AuthError> {
-        let validation = Validation::default();
+        let mut validation = Validation::default();
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        if let Some(audience) = &self.audience {
+            validation.set_audience(&[audience]);
+        }
         let token_data = decode::<Claims>(token, &self.decoding_key, &validation)
             .map_err(|_| AuthError::InvalidToken)?;
 
@@ -51,4 +95,96 @@ impl JwtService {
             scopes: token_data.claims.scopes,
         })
     }
+
+    /// Mints a token that only allows downloading the blob `hash`, e.g. for a shareable link.
+    ///
+    /// The tenant scopes of `user` are kept, so the blob is read from the same tenant.
+    ///
+    /// ```
+    /// # use aquila_server::jwt::JwtService;
+    /// # use aquila_core::prelude::User;
+    /// let jwt = JwtService::new("secret");
+    /// let user = User {
+    ///     id: "alice".into(),
+    ///     scopes: vec!["read".into(), "write".into(), "tenant:acme".into()],
+    /// };
+    ///
+    /// let token = jwt.mint_download(&user, "abc", 60).unwrap();
+    /// let reader = jwt.verify_download(&token, "abc").unwrap();
+    /// assert_eq!(reader.id, "alice");
+    /// assert_eq!(reader.scopes, ["tenant:acme", "read"]);
+    ///
+    /// assert!(jwt.verify_download(&token, "def").is_err());
+    /// assert!(jwt.verify(&token).unwrap().scopes.iter().all(|s| s != "read"));
+    /// ```
+    pub fn mint_download(
+        &self,
+        user: &User,
+        hash: &str,
+        duration_seconds: u64,
+    ) -> Result<String, anyhow::Error> {
+        let scopes = std::iter::once(format!("{DOWNLOAD_SCOPE_PREFIX}{hash}"))
+            .chain(tenant_scopes(&user.scopes))
+            .collect();
+        self.mint(user.id.clone(), scopes, duration_seconds)
+    }
+
+    /// Verifies a token of [`mint_download`](Self::mint_download) for `hash`, the returned
+    /// user only has the `read` scope and the tenant scopes of the signer.
+    pub fn verify_download(&self, token: &str, hash: &str) -> Result<User, AuthError> {
+        let user = self.verify(token)?;
+        let scope = format!("{DOWNLOAD_SCOPE_PREFIX}{hash}");
+        if !user.scopes.contains(&scope) {
+            return Err(AuthError::InvalidToken);
+        }
+
+        Ok(User {
+            scopes: tenant_scopes(&user.scopes)
+                .chain(["read".to_string()])
+                .collect(),
+            ..user
+        })
+    }
+}
+
+/// Private helper for the tenant scopes, see [`TENANT_SCOPE_PREFIX`].
+fn tenant_scopes(scopes: &[String]) -> impl Iterator<Item = String> + '_ {
+    scopes
+        .iter()
+        .filter(|scope| scope.starts_with(TENANT_SCOPE_PREFIX))
+        .cloned()
+}
+
+/// Maximum lifetimes of minted tokens per scope, in seconds.
+///
+/// A token is capped by the smallest cap of its scopes, scopes without a cap are unlimited.
+///
+/// ```
+/// # use aquila_server::jwt::TokenTtlCaps;
+/// let caps = TokenTtlCaps::default().with_cap("read", 30 * 24 * 60 * 60);
+///
+/// let scopes = vec!["read".to_string()];
+/// assert_eq!(caps.clamp(&scopes, 31_536_000), 2_592_000);
+/// assert_eq!(caps.clamp(&scopes, 3600), 3600);
+///
+/// let uncapped = vec!["deploy".to_string()];
+/// assert_eq!(caps.clamp(&uncapped, 31_536_000), 31_536_000);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct TokenTtlCaps(HashMap<String, u64>);
+
+impl TokenTtlCaps {
+    /// Caps tokens with `scope` to `max_seconds`.
+    pub fn with_cap(mut self, scope: impl Into<String>, max_seconds: u64) -> Self {
+        self.0.insert(scope.into(), max_seconds);
+        self
+    }
+
+    /// Clamps `duration_seconds` to the smallest cap of the given scopes.
+    pub fn clamp(&self, scopes: &[String], duration_seconds: u64) -> u64 {
+        scopes
+            .iter()
+            .filter_map(|scope| self.0.get(scope))
+            .fold(duration_seconds, |duration, &cap| duration.min(cap))
+    }
 }
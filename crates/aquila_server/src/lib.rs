@@ -17,6 +17,9 @@
 //! * **`write`**: to upload assets, publish manifests.
 //! * **`admin`**: Full access.
 //!
+//! With `allow_anonymous_read` in [`AquilaServerConfig`], requests without a token are granted
+//! `read`, e.g. for a public CDN.
+//!
 //! ## Example
 //!
 //! ```no_run
@@ -31,18 +34,36 @@
 //! let app = AquilaServer::default().build(storage, auth);
 //! # }
 //! ```
+//!
+//! For container deployments, the config can be read from `AQUILA_*` environment variables
+//! with [`AquilaServerConfig::from_env`].
 
 mod api;
 
 pub mod jwt;
 
+#[cfg(feature = "openapi")]
+pub mod openapi;
+
+#[cfg(feature = "tus")]
+pub mod tus;
+
 pub mod auth;
+pub mod client_info;
+pub mod env;
+pub mod ip_filter;
+pub mod retention;
 pub mod server;
 pub mod state;
+pub mod tenant;
 
 pub mod prelude {
     pub use crate::auth::*;
+    pub use crate::client_info::*;
+    pub use crate::env::*;
+    pub use crate::ip_filter::*;
     pub use crate::jwt::*;
+    pub use crate::retention::*;
     pub use crate::server::*;
     pub use crate::state::*;
 }
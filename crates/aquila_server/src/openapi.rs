@@ -0,0 +1,333 @@
+//! OpenAPI 3.1 document of the HTTP API, served at `GET /openapi.json` with a Swagger UI at
+//! `GET /docs`.
+//!
+//! Requires the `openapi` feature. Body schemas are generated from the protocol types,
+//! see `aquila_core::schema`.
+
+use aquila_core::format::ManifestFormat;
+use axum::Json;
+use axum::http::header;
+use axum::response::IntoResponse;
+use serde_json::{Map, Value, json};
+
+/// GET /openapi.json
+pub async fn get_openapi() -> impl IntoResponse {
+    Json(openapi())
+}
+
+/// GET /docs
+pub async fn swagger_ui() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/html; charset=utf-8")],
+        SWAGGER_UI,
+    )
+}
+
+const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+  <title>Aquila API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>SwaggerUIBundle({ url: "./openapi.json", dom_id: "#swagger-ui" });</script>
+</body>
+</html>"##;
+
+/// Builds the OpenAPI document for all routes of [`AquilaServer`](crate::server::AquilaServer).
+///
+/// The required scope of every operation is listed in its description and in `x-required-scope`.
+pub fn openapi() -> Value {
+    let hash = path_param("hash", "SHA256 hash of the asset");
+    let version = path_param("version", "Manifest version, or `latest`");
+
+    json!({
+        "openapi": "3.1.0",
+        "info": {
+            "title": "Aquila",
+            "description": "A modular asset server.",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "components": {
+            "schemas": component_schemas(),
+            "securitySchemes": {
+                "bearer": { "type": "http", "scheme": "bearer" },
+            },
+        },
+        "paths": {
+            "/health": {
+                "get": op("Health check", None, json!([]), None, text_response("`OK`")),
+            },
+            "/capabilities": {
+                "get": op("Features of this deployment", None, json!([]), None, json_response("ServerCapabilities")),
+            },
+            "/auth/login": {
+                "get": op("Start the login flow of the auth provider", None, json!([]), None, json!({
+                    "307": { "description": "Redirect to the provider" },
+                    "501": { "description": "The provider has no login flow" },
+                })),
+            },
+            "/auth/callback": {
+                "get": op("Finish the login flow, responds with a session token", None, json!([query_param("code", "Authorization code of the provider")]), None, json!({
+                    "200": { "description": "The session token", "content": { "application/json": {} } },
+                    "401": { "description": "The code was rejected" },
+                })),
+            },
+            "/auth/token": {
+                "post": op("Mint a long-lived token", Some("write"), json!([]), Some(json_body(json!({
+                    "type": "object",
+                    "required": ["subject"],
+                    "properties": {
+                        "subject": { "type": "string" },
+                        "duration_seconds": { "type": "integer", "description": "Default: 1 year" },
+                        "scopes": { "type": "array", "items": { "type": "string" }, "description": "Default: `read`" },
+                    },
+                }))), json!({
+                    "200": { "description": "The token", "content": { "application/json": { "schema": {
+                        "type": "object",
+                        "properties": {
+                            "token": { "type": "string" },
+                            "expires_in": { "type": "integer" },
+                        },
+                    }}}},
+                })),
+            },
+            "/assets": {
+                "post": op("Upload an asset, responds with its hash", Some("write"), json!([]), Some(binary_body()), json!({
+                    "200": { "description": "Already stored", "content": { "text/plain": {} } },
+                    "201": { "description": "Stored", "content": { "text/plain": {} } },
+                    "503": { "description": "Too many uploads in progress, retry after `Retry-After` seconds" },
+                })),
+            },
+            "/assets/exists": {
+                "post": op("Filter hashes to those already stored", Some("write"), json!([]), Some(json_body(string_array())), json!({
+                    "200": { "description": "The stored hashes", "content": { "application/json": { "schema": string_array() } } },
+                })),
+            },
+            "/assets/stream/{hash}": {
+                "put": op("Upload an asset as a stream", Some("write"), json!([hash]), Some(binary_body()), json!({
+                    "200": { "description": "Already stored" },
+                    "201": { "description": "Stored" },
+                    "503": { "description": "Too many uploads in progress, retry after `Retry-After` seconds" },
+                })),
+            },
+            "/assets/{hash}": {
+                "get": op("Download an asset", Some("read"), json!([
+                    hash,
+                    query_param("download", "Filename to save the asset as"),
+                    query_param("token", "Token of a signed link, instead of a bearer token"),
+                ]), None, json!({
+                    "200": { "description": "The asset", "content": { "application/octet-stream": {} } },
+                    "304": { "description": "Not modified, see `If-None-Match` and `If-Modified-Since`" },
+                    "307": { "description": "Redirect to a direct download URL" },
+                    "501": { "description": "`download_mode` is `redirect`, but the backend returned no URL" },
+                })),
+            },
+            "/assets/{hash}/sign": {
+                "get": op("Sign a link to download an asset without a token", Some("read"), json!([hash, query_param("ttl", "Lifetime of the link in seconds")]), None, json!({
+                    "200": { "description": "The link and its lifetime in seconds", "content": { "application/json": {} } },
+                })),
+            },
+            "/assets/{hash}/refs": {
+                "get": op("List the manifest versions referencing an asset", Some("read"), json!([hash]), None, json!({
+                    "200": { "description": "The versions, including `latest`", "content": { "application/json": {} } },
+                    "400": { "description": "Invalid hash" },
+                })),
+            },
+            "/assets/{hash}/transform": {
+                "get": op("Transform an asset, e.g. resize an image", Some("read"), json!([
+                    hash,
+                    query_param("w", "Target width"),
+                    query_param("h", "Target height"),
+                    query_param("format", "Target format, e.g. `webp`"),
+                ]), None, json!({
+                    "200": { "description": "The transformed asset" },
+                    "415": { "description": "The asset can't be transformed" },
+                    "501": { "description": "No transformer configured" },
+                })),
+            },
+            "/manifest": {
+                "post": op("Publish a manifest", Some("write"), json!([
+                    query_param("latest", "Also tag as `latest`, default: true"),
+                    query_param("dry_run", "Only validate and report"),
+                    query_param("require_blobs", "Fail if a referenced blob is missing, publish all or nothing"),
+                ]), Some(manifest_body()), json!({
+                    "200": { "description": "Dry run report", "content": { "application/json": { "schema": schema_ref("PublishReport") } } },
+                    "201": { "description": "Published" },
+                    "409": { "description": "`require_blobs` is set and referenced blobs are missing" },
+                })),
+            },
+            "/manifest/{version}": {
+                "get": op("Fetch a manifest", Some("read"), json!([version]), None, json!({
+                    "200": {
+                        "description": "OK",
+                        "headers": { "X-Aquila-Resolved-Version": { "description": "The version served, e.g. the one `latest` points to", "schema": { "type": "string" } } },
+                        "content": { "application/json": { "schema": schema_ref("AssetManifest") } },
+                    },
+                })),
+                "patch": op("Patch a manifest", Some("write"), json!([version]), Some(json_body(schema_ref("ManifestPatch"))), json_response("AssetManifest")),
+                "delete": op("Unpublish a manifest, repointing `latest` if needed", Some("write"), json!([version]), None, json!({
+                    "204": { "description": "Unpublished" },
+                })),
+            },
+            "/manifest/{version}/stats": {
+                "get": op("Statistics of a manifest", Some("read"), json!([version]), None, json_response("ManifestStats")),
+            },
+            "/manifest/{version}/archive": {
+                "get": op("Download all assets of a manifest as an archive", Some("read"), json!([version, query_param("format", "`tar` (default)")]), None, json!({
+                    "200": { "description": "The archive", "content": { "application/x-tar": {} } },
+                })),
+            },
+            "/manifests": {
+                "get": op("List manifest versions", Some("read"), page_params(), None, json_response("ManifestPage")),
+            },
+            "/admin/stats": {
+                "get": op("Content store statistics", Some("admin"), json!([]), None, json_response("StorageStats")),
+            },
+            "/admin/blobs": {
+                "get": op("List stored blobs", Some("admin"), page_params(), None, json_response("BlobPage")),
+            },
+            "/admin/prune-manifests": {
+                "post": op("Unpublish all but the newest versions, never `latest` or `keep_versions`", Some("admin"), json!([
+                    query_param("keep", "Number of versions to keep, including `latest`"),
+                    query_param("keep_versions", "Comma-separated versions to keep in addition, e.g. releases"),
+                    query_param("dry_run", "Only report"),
+                ]), None, json_response("PruneReport")),
+            },
+            "/admin/pins": {
+                "get": op("List pinned blobs", Some("admin"), json!([]), None, json!({
+                    "200": { "description": "Hashes of the pinned blobs", "content": { "application/json": { "schema": { "type": "array", "items": { "type": "string" } } } } },
+                })),
+            },
+            "/admin/pins/{hash}": {
+                "post": op("Pin a blob, so it is kept without referencing manifests", Some("admin"), json!([hash]), None, json!({
+                    "204": { "description": "Pinned" },
+                })),
+                "delete": op("Unpin a blob", Some("admin"), json!([hash]), None, json!({
+                    "204": { "description": "Unpinned" },
+                })),
+            },
+        },
+    })
+}
+
+/// An operation, adding the security requirement and common error responses.
+fn op(
+    summary: &str,
+    scope: Option<&str>,
+    parameters: Value,
+    request_body: Option<Value>,
+    mut responses: Value,
+) -> Value {
+    let mut op = json!({ "summary": summary, "parameters": parameters });
+
+    if let Some(scope) = scope {
+        op["description"] = json!(format!("Requires the `{scope}` scope (or `admin`)."));
+        op["security"] = json!([{ "bearer": [] }]);
+        op["x-required-scope"] = json!(scope);
+        responses["400"] = json!({ "description": "Invalid request" });
+        responses["401"] = json!({ "description": "Missing or invalid token" });
+        responses["403"] = json!({ "description": format!("Missing `{scope}` scope") });
+        responses["404"] = json!({ "description": "Not found" });
+    }
+    if let Some(body) = request_body {
+        op["requestBody"] = body;
+    }
+    op["responses"] = responses;
+
+    op
+}
+
+fn path_param(name: &str, description: &str) -> Value {
+    json!({ "name": name, "in": "path", "required": true, "description": description, "schema": { "type": "string" } })
+}
+
+fn query_param(name: &str, description: &str) -> Value {
+    json!({ "name": name, "in": "query", "required": false, "description": description, "schema": { "type": "string" } })
+}
+
+fn page_params() -> Value {
+    json!([
+        query_param("limit", "Items per page, default: 100, max: 1000"),
+        query_param("cursor", "`next_cursor` of the previous page"),
+    ])
+}
+
+fn schema_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{name}") })
+}
+
+fn string_array() -> Value {
+    json!({ "type": "array", "items": { "type": "string" } })
+}
+
+fn json_body(schema: Value) -> Value {
+    json!({ "required": true, "content": { "application/json": { "schema": schema } } })
+}
+
+/// An [`AssetManifest`](aquila_core::manifest::AssetManifest) in every enabled format.
+fn manifest_body() -> Value {
+    let content: Map<String, Value> = ManifestFormat::ALL
+        .iter()
+        .map(|format| {
+            let schema = json!({ "schema": schema_ref("AssetManifest") });
+            (format.content_type().to_string(), schema)
+        })
+        .collect();
+    json!({ "required": true, "content": content })
+}
+
+fn binary_body() -> Value {
+    json!({ "required": true, "content": { "application/octet-stream": {} } })
+}
+
+fn json_response(name: &str) -> Value {
+    json!({ "200": { "description": "OK", "content": { "application/json": { "schema": schema_ref(name) } } } })
+}
+
+fn text_response(description: &str) -> Value {
+    json!({ "200": { "description": description, "content": { "text/plain": {} } } })
+}
+
+/// The protocol type schemas, with nested definitions moved into the components.
+fn component_schemas() -> Map<String, Value> {
+    let mut components = Map::new();
+    for (name, schema) in aquila_core::schema::schemas() {
+        let Ok(mut schema) = serde_json::to_value(&schema) else {
+            continue;
+        };
+        if let Some(schema) = schema.as_object_mut() {
+            schema.remove("$schema");
+            if let Some(Value::Object(defs)) = schema.remove("$defs") {
+                components.extend(defs);
+            }
+        }
+        components.insert(name.to_string(), schema);
+    }
+
+    rewrite_refs(Value::Object(components))
+        .as_object()
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Points references to nested definitions at the components instead.
+fn rewrite_refs(value: Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, value)| match (key.as_str(), &value) {
+                    ("$ref", Value::String(target)) => {
+                        let target = target.replace("#/$defs/", "#/components/schemas/");
+                        (key, Value::String(target))
+                    }
+                    _ => (key, rewrite_refs(value)),
+                })
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(values.into_iter().map(rewrite_refs).collect()),
+        value => value,
+    }
+}
@@ -0,0 +1,54 @@
+//! Blobs a garbage collection must keep, see [`protected_blobs`].
+
+use aquila_core::error::StorageError;
+use aquila_core::manifest::AssetManifest;
+use aquila_core::traits::StorageBackend;
+use std::collections::BTreeSet;
+use std::time::{Duration, SystemTime};
+
+/// Path of the pinned blob hashes, a JSON array.
+pub const PINS_PATH: &str = "pins.json";
+
+/// Reads the pinned blobs, an empty set if none were pinned yet.
+pub async fn read_pins<S: StorageBackend>(storage: &S) -> anyhow::Result<BTreeSet<String>> {
+    match storage.read_file(PINS_PATH).await {
+        Ok(data) => Ok(serde_json::from_slice(&data)?),
+        Err(StorageError::NotFound(_)) => Ok(BTreeSet::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Returns the blobs a garbage collection of `storage` must keep, for rollbacks.
+///
+/// These are the pinned blobs and the ones referenced by the `keep_last` newest manifests by
+/// `published_at` or by manifests published within `window`.
+pub async fn protected_blobs<S: StorageBackend>(
+    storage: &S,
+    keep_last: usize,
+    window: Duration,
+) -> anyhow::Result<BTreeSet<String>> {
+    let mut manifests = Vec::new();
+    for version in storage.list_manifests().await? {
+        let data = storage
+            .read_file(&storage.get_manifest_path(&version))
+            .await?;
+        // `latest` only points to another version, unless it is an old full copy.
+        let value: serde_json::Value = serde_json::from_slice(&data)?;
+        if value.is_string() {
+            continue;
+        }
+        manifests.push(AssetManifest::migrate(value)?);
+    }
+    manifests.sort_by_key(|m| std::cmp::Reverse(m.published_at));
+
+    let now = SystemTime::now();
+    let mut blobs = read_pins(storage).await?;
+    for (i, manifest) in manifests.iter().enumerate() {
+        // Published in the future counts as recent.
+        let age = now.duration_since(manifest.published_at.into());
+        if i < keep_last || !age.is_ok_and(|age| age > window) {
+            blobs.extend(manifest.assets.values().map(|info| info.hash.clone()));
+        }
+    }
+    Ok(blobs)
+}
@@ -3,8 +3,14 @@ use aquila_core::prelude::*;
 use axum::{
     Router,
     extract::DefaultBodyLimit,
+    middleware,
     routing::{get, post, put},
 };
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tower_http::limit::RequestBodyLimitLayer;
 use tower_http::trace::TraceLayer;
 use tracing::warn;
 
@@ -12,11 +18,16 @@ use tracing::warn;
 #[derive(Clone, Debug, Default)]
 pub struct AquilaServer {
     config: AquilaServerConfig,
+    /// Built by [`preflight`](Self::preflight), so it is ready before the first request.
+    references: ReferenceIndex,
 }
 
 impl AquilaServer {
     pub fn new(config: AquilaServerConfig) -> Self {
-        Self { config }
+        Self {
+            config,
+            references: Default::default(),
+        }
     }
 }
 
@@ -28,52 +39,413 @@ pub struct AquilaServerConfig {
     ///
     /// **NOTE:** This should be set to a secure value!
     pub jwt_secret: String,
+    /// If set, minted tokens carry this `iss` claim and tokens without it are rejected.
+    ///
+    /// Defaults to `None`.
+    pub jwt_issuer: Option<String>,
+    /// If set, minted tokens carry this `aud` claim and tokens without it are rejected.
+    ///
+    /// Defaults to `None`.
+    pub jwt_audience: Option<String>,
     /// The callback URL for the auth provider.
     ///
     /// Defaults to `/auth/callback`.
     pub callback: String,
+    /// If set, published manifests are signed with this key,
+    /// so clients can verify them with the corresponding verifying key.
+    ///
+    /// See [`generate_signing_key`] and `AquilaClient::fetch_manifest_verified`.
+    pub signing_key: Option<SigningKey>,
+    /// Maximum lifetimes of tokens minted via `/auth/token` per scope.
+    ///
+    /// Defaults to no caps. Tokens minted by admins are never capped.
+    pub token_ttl_caps: TokenTtlCaps,
+    /// Scopes that non-admins can request when minting tokens via `/auth/token`.
+    ///
+    /// Defaults to `read`. Admins can mint any scope except `admin` and `write`, which can
+    /// never be minted, whether listed here or not.
+    pub mintable_scopes: Vec<String>,
+    /// If set, assets and manifests can be downloaded without a token,
+    /// e.g. for a public CDN. Uploads and publishing still require authentication.
+    ///
+    /// Defaults to `false`.
+    pub allow_anonymous_read: bool,
+    /// If set, `GET /assets/{hash}/transform` runs this transformer, e.g. to resize images.
+    ///
+    /// Defaults to `None`, the route responds with `501 Not Implemented`.
+    pub transformer: Option<Arc<dyn AssetTransformer>>,
+    /// If set, `{{name}}` placeholders in manifests are replaced with these values when fetched,
+    /// e.g. `{"cdn": "cdn.example.com"}` for a per environment `base_url`.
+    ///
+    /// Only `base_url` is substituted, see [`AssetManifest::substitute`].
+    /// Defaults to `None`, manifests are served exactly as published.
+    pub manifest_vars: Option<HashMap<String, String>>,
+    /// Maximum size of request bodies in bytes, e.g. uploads. Larger requests are rejected with
+    /// `413 Payload Too Large`.
+    ///
+    /// Defaults to `None`, bodies are not limited.
+    pub max_body_size: Option<usize>,
+    /// If set, streamed uploads are hashed while received and deleted if the hash doesn't match
+    /// the path, see `PUT /assets/stream/{hash}`.
+    ///
+    /// Defaults to `true`.
+    ///
+    /// **WARNING**: Disabling this saves CPU on large uploads, but the server then trusts the
+    /// client: a buggy or malicious client can store any content under any hash, and every client
+    /// downloading that hash gets the wrong data. Only disable it if all clients with the `write`
+    /// scope are trusted and verify their uploads themselves.
+    pub verify_uploads: bool,
+    /// Maximum number of uploads handled at the same time by `POST /assets` and
+    /// `PUT /assets/stream/{hash}`, e.g. to not run out of file descriptors or S3 connections.
+    /// Further uploads are rejected with `503 Service Unavailable` and a `Retry-After` header.
+    /// A limit of `0` is raised to `1`.
+    ///
+    /// Defaults to `None`, uploads are not limited.
+    pub max_concurrent_uploads: Option<usize>,
+    /// Uploads with a `Content-Length` of at least this many bytes are streamed to storage,
+    /// smaller ones are buffered in memory. Applies to both `POST /assets` and
+    /// `PUT /assets/stream/{hash}`, so clients can use either regardless of the size.
+    ///
+    /// Large `POST /assets` uploads without an `X-Content-SHA256` header are spooled to a temp
+    /// file, as their hash is only known at the end. Uploads without a `Content-Length` are
+    /// always streamed, and all uploads are buffered if the backend doesn't support streaming.
+    ///
+    /// Defaults to 8 MiB.
+    pub stream_upload_threshold: u64,
+    /// If set, `GET /manifest/{version}` responses are cached in memory for this long,
+    /// e.g. for a hot `latest` manifest. Every manifest write through this server clears the
+    /// cache, the TTL only bounds how long writes by other servers or tools go unnoticed.
+    ///
+    /// Defaults to `None`, manifests are read from storage on every request.
+    pub manifest_cache_ttl: Option<Duration>,
+    /// Whether `GET /assets/{hash}` redirects to direct download URLs, e.g. S3 presigned URLs,
+    /// or proxies the asset through the server, see [`DownloadMode`].
+    ///
+    /// Defaults to [`DownloadMode::Auto`].
+    pub download_mode: DownloadMode,
+    /// Number of blobs `GET /manifest/{version}/archive` reads ahead while streaming, so
+    /// versions with many small assets aren't bound by the storage latency. Each read ahead
+    /// blob is held in memory until sent. A value of `0` is raised to `1`, i.e. no read ahead.
+    ///
+    /// Defaults to 8.
+    pub archive_prefetch: usize,
+    /// If set, `GET /assets/{hash}` hashes proxied blobs before sending them and fails with
+    /// `500 Internal Server Error` if they don't match, e.g. to detect bit rot or backend bugs.
+    /// Redirected downloads aren't checked.
+    ///
+    /// Defaults to `false`, as it costs a full hash of every download.
+    pub verify_on_read: bool,
+    /// If set, only clients allowed by this filter can make write requests, i.e. requests with
+    /// a method other than `GET`, `HEAD` or `OPTIONS`. Others are rejected with
+    /// `403 Forbidden`, regardless of their token.
+    ///
+    /// The source IP is taken from the connection, so the router must be served with
+    /// [`into_make_service_with_connect_info`](Router::into_make_service_with_connect_info).
+    ///
+    /// Defaults to `None`, writes are allowed from anywhere.
+    pub write_ip_filter: Option<IpFilter>,
+    /// Reverse proxies whose `Forwarded` or `X-Forwarded-*` headers are trusted to report the
+    /// client, e.g. a load balancer, see [`ClientInfo`]. Headers of other peers are ignored.
+    ///
+    /// Defaults to none.
+    pub trusted_proxies: Vec<IpNet>,
+}
+
+/// How `GET /assets/{hash}` serves assets, see [`AquilaServerConfig::download_mode`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DownloadMode {
+    /// Redirects if the backend returns a direct download URL, otherwise proxies.
+    #[default]
+    Auto,
+    /// Always proxies, so clients never see storage URLs and every download hits the server,
+    /// e.g. for access logs.
+    Proxy,
+    /// Always redirects, downloads fail with `501 Not Implemented` if the backend returns no
+    /// direct download URL.
+    Redirect,
 }
 
 const DEFAULT_SECRET: &str = "TOP_SECRET";
 
+const DEFAULT_STREAM_UPLOAD_THRESHOLD: u64 = 8 * 1024 * 1024;
+
+const DEFAULT_ARCHIVE_PREFETCH: usize = 8;
+
 impl Default for AquilaServerConfig {
     fn default() -> Self {
         Self {
             jwt_secret: DEFAULT_SECRET.to_string(),
+            jwt_issuer: None,
+            jwt_audience: None,
             callback: "/auth/callback".to_string(),
+            signing_key: None,
+            token_ttl_caps: TokenTtlCaps::default(),
+            mintable_scopes: vec!["read".to_string()],
+            allow_anonymous_read: false,
+            transformer: None,
+            manifest_vars: None,
+            max_body_size: None,
+            verify_uploads: true,
+            max_concurrent_uploads: None,
+            stream_upload_threshold: DEFAULT_STREAM_UPLOAD_THRESHOLD,
+            manifest_cache_ttl: None,
+            download_mode: DownloadMode::default(),
+            archive_prefetch: DEFAULT_ARCHIVE_PREFETCH,
+            verify_on_read: false,
+            write_ip_filter: None,
+            trusted_proxies: Vec::new(),
         }
     }
 }
 
+impl AquilaServerConfig {
+    /// Returns the [`JwtService`] for the configured secret, issuer and audience.
+    ///
+    /// Use this for a [`JWTServiceAuthProvider`], so it accepts the tokens minted by the server.
+    pub fn jwt_service(&self) -> JwtService {
+        let mut jwt_service = JwtService::new(&self.jwt_secret);
+        if let Some(issuer) = &self.jwt_issuer {
+            jwt_service = jwt_service.with_issuer(issuer);
+        }
+        if let Some(audience) = &self.jwt_audience {
+            jwt_service = jwt_service.with_audience(audience);
+        }
+        jwt_service
+    }
+}
+
 impl AquilaServer {
     pub fn build<S: StorageBackend, A: AuthProvider>(self, storage: S, auth: A) -> Router {
+        let jwt_service = self.config.jwt_service();
         let AquilaServerConfig {
             jwt_secret,
+            jwt_issuer: _,
+            jwt_audience: _,
             callback,
-            ..
+            signing_key,
+            token_ttl_caps,
+            mintable_scopes,
+            allow_anonymous_read,
+            transformer,
+            manifest_vars,
+            max_body_size,
+            verify_uploads,
+            max_concurrent_uploads,
+            stream_upload_threshold,
+            manifest_cache_ttl,
+            download_mode,
+            archive_prefetch,
+            verify_on_read,
+            write_ip_filter,
+            trusted_proxies,
         } = self.config;
         if jwt_secret == DEFAULT_SECRET {
             warn!("Default JWT secret used. Consider setting `jwt_secret` to a secure value!")
         }
-        let jwt_service = JwtService::new(&jwt_secret);
+        if download_mode == DownloadMode::Redirect && !storage.capabilities().download_urls {
+            warn!("`download_mode` is `Redirect`, but the storage backend has no download URLs.")
+        }
         let state = AppState {
             storage,
             auth,
             jwt_service,
+            stats_cache: Default::default(),
+            manifest_cache: Default::default(),
+            references: self.references,
+            pins_lock: Default::default(),
+            manifest_cache_ttl,
+            signing_key,
+            token_ttl_caps,
+            mintable_scopes,
+            allow_anonymous_read,
+            transformer,
+            manifest_vars,
+            max_body_size,
+            verify_uploads,
+            stream_upload_threshold,
+            download_mode,
+            archive_prefetch: archive_prefetch.max(1),
+            verify_on_read,
+            upload_permits: max_concurrent_uploads.map(|max| Arc::new(Semaphore::new(max.max(1)))),
+            #[cfg(feature = "tus")]
+            tus_uploads: Default::default(),
         };
 
-        Router::new()
+        let router = Router::new();
+
+        #[cfg(feature = "openapi")]
+        let router = router
+            .route("/openapi.json", get(crate::openapi::get_openapi))
+            .route("/docs", get(crate::openapi::swagger_ui));
+
+        #[cfg(feature = "tus")]
+        let router = router.merge(crate::tus::routes());
+
+        let router = router
             .route("/health", get(|| async { "OK" }))
+            .route("/capabilities", get(api::get_capabilities))
             .route("/auth/login", get(api::auth_login))
             .route("/auth/token", post(api::issue_token))
             .route(callback.as_str(), get(api::auth_callback))
             .route("/assets/{hash}", get(api::download_asset))
+            .route("/assets/{hash}/transform", get(api::transform_asset))
+            .route("/assets/{hash}/sign", get(api::sign_asset))
+            .route("/assets/{hash}/refs", get(api::get_asset_refs))
             .route("/assets/stream/{hash}", put(api::upload_asset_stream))
             .route("/assets", post(api::upload_asset))
-            .route("/manifest/{version}", get(api::get_manifest))
+            .route("/assets/exists", post(api::assets_exist))
+            .route(
+                "/manifest/{version}",
+                get(api::get_manifest)
+                    .patch(api::patch_manifest)
+                    .delete(api::delete_manifest),
+            )
+            .route("/manifest/{version}/stats", get(api::get_manifest_stats))
+            .route(
+                "/manifest/{version}/archive",
+                get(api::get_manifest_archive),
+            )
             .route("/manifest", post(api::publish_manifest))
-            .layer(DefaultBodyLimit::disable())
-            .layer(TraceLayer::new_for_http())
-            .with_state(state)
+            .route("/manifests", get(api::list_manifests))
+            .route("/admin/stats", get(api::get_storage_stats))
+            .route("/admin/blobs", get(api::list_blobs))
+            .route("/admin/pins", get(api::list_pins))
+            .route("/admin/prune-manifests", post(api::prune_manifests))
+            .route(
+                "/admin/pins/{hash}",
+                post(api::pin_blob).delete(api::unpin_blob),
+            )
+            .layer(DefaultBodyLimit::disable());
+
+        let router = match write_ip_filter {
+            Some(filter) => {
+                let filter = Arc::new(filter);
+                router.layer(middleware::from_fn(move |request, next| {
+                    crate::ip_filter::filter_writes(filter.clone(), request, next)
+                }))
+            }
+            None => router,
+        };
+
+        // Resolved before the filter, which reads it.
+        let trusted_proxies: Arc<[IpNet]> = trusted_proxies.into();
+        let router = router.layer(middleware::from_fn(move |request, next| {
+            crate::client_info::resolve_client(trusted_proxies.clone(), request, next)
+        }));
+
+        // Unlike `DefaultBodyLimit`, this also limits streamed bodies.
+        let router = match max_body_size {
+            Some(limit) => router.layer(RequestBodyLimitLayer::new(limit)),
+            None => router,
+        };
+
+        router.layer(TraceLayer::new_for_http()).with_state(state)
+    }
+}
+
+/// Path checked by [`AquilaServer::preflight`] to reach the storage, it doesn't have to exist.
+const PREFLIGHT_PROBE: &str = "preflight";
+
+/// A configuration that breaks the server, see [`AquilaServer::preflight`].
+#[derive(Debug)]
+pub enum PreflightError {
+    /// The storage backend can't be reached, e.g. wrong credentials or bucket.
+    Storage(StorageError),
+    /// `download_mode` is [`DownloadMode::Redirect`], but the backend has no download URLs.
+    RedirectWithoutDownloadUrls,
+}
+
+impl std::fmt::Display for PreflightError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Storage(e) => write!(f, "Storage is not reachable: {e}"),
+            Self::RedirectWithoutDownloadUrls => write!(
+                f,
+                "`download_mode` is `Redirect`, but the storage backend has no download URLs"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PreflightError {}
+
+/// A working but risky configuration, see [`AquilaServer::preflight`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PreflightWarning {
+    /// `jwt_secret` is the default, anyone can mint tokens.
+    DefaultJwtSecret,
+    /// `verify_uploads` is disabled, clients can store any content under any hash.
+    UnverifiedUploads,
+    /// The manifests couldn't be scanned for blob references, e.g. one is corrupted. The scan is
+    /// retried on the first `GET /assets/{hash}/refs`.
+    UnindexedReferences,
+}
+
+impl std::fmt::Display for PreflightWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DefaultJwtSecret => write!(f, "Default JWT secret used"),
+            Self::UnverifiedUploads => write!(f, "Uploads are not verified"),
+            Self::UnindexedReferences => write!(f, "Blob references could not be indexed"),
+        }
+    }
+}
+
+impl AquilaServer {
+    /// Checks the config and `storage` before serving, so misconfigurations don't only surface
+    /// at request time.
+    ///
+    /// Fails if the server wouldn't work, e.g. the storage is unreachable, and returns warnings
+    /// for risky settings, e.g. the default JWT secret. Also builds the [`ReferenceIndex`], pass
+    /// the same `storage` to [`build`](Self::build).
+    ///
+    /// ```
+    /// # use aquila_server::prelude::*;
+    /// # use aquila_fs::FileSystemStorage;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let storage = FileSystemStorage::new(std::env::temp_dir().join("aquila_preflight"));
+    /// let server = AquilaServer::default();
+    ///
+    /// let warnings = server.preflight(&storage).await.unwrap();
+    /// assert_eq!(warnings, [PreflightWarning::DefaultJwtSecret]);
+    ///
+    /// let server = AquilaServer::new(AquilaServerConfig {
+    ///     download_mode: DownloadMode::Redirect,
+    ///     ..Default::default()
+    /// });
+    /// assert!(server.preflight(&storage).await.is_err());
+    /// # }
+    /// ```
+    pub async fn preflight<S: StorageBackend>(
+        &self,
+        storage: &S,
+    ) -> Result<Vec<PreflightWarning>, PreflightError> {
+        storage
+            .exists(PREFLIGHT_PROBE)
+            .await
+            .map_err(PreflightError::Storage)?;
+
+        if self.config.download_mode == DownloadMode::Redirect
+            && !storage.capabilities().download_urls
+        {
+            return Err(PreflightError::RedirectWithoutDownloadUrls);
+        }
+
+        let mut warnings = Vec::new();
+        if storage.capabilities().listing
+            && let Err(e) = self.references.build(storage).await
+        {
+            warn!("Failed to index blob references: {e:?}");
+            warnings.push(PreflightWarning::UnindexedReferences);
+        }
+        if self.config.jwt_secret == DEFAULT_SECRET {
+            warnings.push(PreflightWarning::DefaultJwtSecret);
+        }
+        if !self.config.verify_uploads {
+            warnings.push(PreflightWarning::UnverifiedUploads);
+        }
+        Ok(warnings)
     }
 }
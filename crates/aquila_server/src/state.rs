@@ -1,9 +1,238 @@
-use crate::jwt::JwtService;
-use aquila_core::traits::{AuthProvider, StorageBackend};
+use crate::jwt::{JwtService, TokenTtlCaps};
+use crate::server::DownloadMode;
+use aquila_core::manifest::{AssetManifest, StorageStats};
+use aquila_core::signing::SigningKey;
+use aquila_core::traits::{AssetTransformer, AuthProvider, StorageBackend, User};
+use axum::http::HeaderValue;
+use bytes::Bytes;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{RwLock, Semaphore};
 
 #[derive(Clone)]
 pub struct AppState<S: StorageBackend + Clone, A: AuthProvider + Clone> {
     pub storage: S,
     pub auth: A,
     pub jwt_service: JwtService,
+    pub stats_cache: StatsCache,
+    pub manifest_cache: ManifestCache,
+    pub references: ReferenceIndex,
+    /// How long `manifest_cache` entries are reused, `None` if the cache is disabled.
+    pub manifest_cache_ttl: Option<Duration>,
+    pub signing_key: Option<SigningKey>,
+    pub token_ttl_caps: TokenTtlCaps,
+    pub mintable_scopes: Vec<String>,
+    pub allow_anonymous_read: bool,
+    pub transformer: Option<Arc<dyn AssetTransformer>>,
+    pub manifest_vars: Option<HashMap<String, String>>,
+    pub max_body_size: Option<usize>,
+    pub verify_uploads: bool,
+    pub stream_upload_threshold: u64,
+    pub download_mode: DownloadMode,
+    pub archive_prefetch: usize,
+    pub verify_on_read: bool,
+    /// Serializes updates of the pinned blobs, see `POST /admin/pins/{hash}`.
+    pub pins_lock: Arc<tokio::sync::Mutex<()>>,
+    /// Slots of `max_concurrent_uploads`, `None` if unlimited.
+    pub upload_permits: Option<Arc<Semaphore>>,
+    #[cfg(feature = "tus")]
+    pub tus_uploads: crate::tus::TusUploads,
+}
+
+impl<S: StorageBackend, A: AuthProvider> AppState<S, A> {
+    /// The storage for a request made by `user`, see [`StorageBackend::with_context`].
+    ///
+    /// Storage wide admin routes, e.g. `GET /admin/stats`, use [`storage`](Self::storage) as is.
+    pub fn storage_for(&self, user: &User) -> S {
+        self.storage.with_context(&user.into())
+    }
+}
+
+/// Caches the result of the expensive storage scan behind `GET /admin/stats`.
+#[derive(Clone, Default)]
+pub struct StatsCache(Arc<Mutex<Option<(Instant, StorageStats)>>>);
+
+impl StatsCache {
+    /// Returns the cached stats if they are younger than `ttl`.
+    pub fn get(&self, ttl: Duration) -> Option<StorageStats> {
+        let cache = self.0.lock().ok()?;
+        cache
+            .as_ref()
+            .filter(|(computed_at, _)| computed_at.elapsed() < ttl)
+            .map(|(_, stats)| stats.clone())
+    }
+
+    pub fn set(&self, stats: StorageStats) {
+        if let Ok(mut cache) = self.0.lock() {
+            *cache = Some((Instant::now(), stats));
+        }
+    }
+}
+
+/// A manifest as served by `GET /manifest/{version}`.
+#[derive(Clone)]
+pub struct CachedManifest {
+    pub body: Bytes,
+    /// Quoted SHA256 of the body.
+    pub etag: String,
+    /// The `version` of the manifest, `None` if it isn't a valid header value.
+    pub version: Option<HeaderValue>,
+}
+
+/// Caches the responses of `GET /manifest/{version}` by manifest path, so hot manifests like
+/// `latest` aren't read from storage on every request.
+///
+/// Every manifest write clears the whole cache, which is simpler than tracking which versions
+/// `latest` was copied from, as writes are rare.
+#[derive(Clone, Default)]
+pub struct ManifestCache(Arc<Mutex<ManifestCacheState>>);
+
+#[derive(Default)]
+struct ManifestCacheState {
+    /// Bumped by every invalidation, so a read that raced with a write isn't cached.
+    generation: u64,
+    entries: HashMap<String, (Instant, CachedManifest)>,
+}
+
+impl ManifestCache {
+    /// Returns the cached manifest at `path` if it is younger than `ttl`.
+    pub fn get(&self, path: &str, ttl: Duration) -> Option<CachedManifest> {
+        let cache = self.0.lock().ok()?;
+        cache
+            .entries
+            .get(path)
+            .filter(|(cached_at, _)| cached_at.elapsed() < ttl)
+            .map(|(_, manifest)| manifest.clone())
+    }
+
+    /// The current generation, pass it to [`set`](Self::set) before reading from storage.
+    pub fn generation(&self) -> u64 {
+        self.0
+            .lock()
+            .map(|cache| cache.generation)
+            .unwrap_or_default()
+    }
+
+    /// Caches `manifest` unless the cache was invalidated since `generation`.
+    pub fn set(&self, path: String, manifest: CachedManifest, generation: u64) {
+        if let Ok(mut cache) = self.0.lock()
+            && cache.generation == generation
+        {
+            cache.entries.insert(path, (Instant::now(), manifest));
+        }
+    }
+
+    /// Clears the cache, call after every manifest write.
+    pub fn invalidate(&self) {
+        if let Ok(mut cache) = self.0.lock() {
+            cache.generation += 1;
+            cache.entries.clear();
+        }
+    }
+}
+
+/// Indexes which manifests reference which blobs, for `GET /assets/{hash}/refs`, so questions
+/// like "is this blob still used?" don't scan all manifests every time.
+///
+/// The index is built by scanning all manifests at startup in [`AquilaServer::preflight`], or on
+/// first use if that wasn't called, and kept up to date by every successful manifest write and
+/// delete through this server. Manifests written by other servers or tools are missed until a
+/// restart. Requires a backend that supports listing.
+///
+/// [`AquilaServer::preflight`]: crate::server::AquilaServer::preflight
+#[derive(Clone, Debug, Default)]
+pub struct ReferenceIndex(Arc<RwLock<Option<References>>>);
+
+#[derive(Debug, Default)]
+struct References {
+    /// Manifest paths by referenced hash.
+    by_hash: HashMap<String, BTreeSet<String>>,
+    /// Referenced hashes by manifest path.
+    by_manifest: HashMap<String, Vec<String>>,
+}
+
+impl References {
+    fn insert(&mut self, path: String, manifest: &AssetManifest) {
+        self.remove(&path);
+
+        let hashes: Vec<String> = manifest.assets.values().map(|a| a.hash.clone()).collect();
+        for hash in &hashes {
+            self.by_hash
+                .entry(hash.clone())
+                .or_default()
+                .insert(path.clone());
+        }
+        self.by_manifest.insert(path, hashes);
+    }
+
+    fn remove(&mut self, path: &str) {
+        for hash in self.by_manifest.remove(path).unwrap_or_default() {
+            if let Some(paths) = self.by_hash.get_mut(&hash) {
+                paths.remove(path);
+                if paths.is_empty() {
+                    self.by_hash.remove(&hash);
+                }
+            }
+        }
+    }
+}
+
+impl ReferenceIndex {
+    /// Returns the paths of the manifests referencing `hash`, building the index if needed.
+    pub async fn get<S: StorageBackend>(
+        &self,
+        storage: &S,
+        hash: &str,
+    ) -> anyhow::Result<BTreeSet<String>> {
+        if let Some(refs) = self.0.read().await.as_ref() {
+            return Ok(refs.by_hash.get(hash).cloned().unwrap_or_default());
+        }
+
+        self.build(storage).await?;
+        Ok(self
+            .0
+            .read()
+            .await
+            .as_ref()
+            .and_then(|refs| refs.by_hash.get(hash).cloned())
+            .unwrap_or_default())
+    }
+
+    /// Builds the index by scanning all manifests of `storage`, unless it is built already.
+    pub async fn build<S: StorageBackend>(&self, storage: &S) -> anyhow::Result<()> {
+        // Writes wait for the scan, so none of them are lost.
+        let mut index = self.0.write().await;
+        if index.is_some() {
+            return Ok(());
+        }
+
+        let mut refs = References::default();
+        for version in storage.list_manifests().await? {
+            let path = storage.get_manifest_path(&version);
+            let data = storage.read_file(&path).await?;
+            // `latest` only points to another version, unless it is an old full copy.
+            let value: serde_json::Value = serde_json::from_slice(&data)?;
+            if value.is_string() {
+                continue;
+            }
+            refs.insert(path, &AssetManifest::migrate(value)?);
+        }
+        *index = Some(refs);
+        Ok(())
+    }
+
+    /// Records that the manifest at `path` was written, call after every manifest write.
+    pub async fn insert(&self, path: String, manifest: &AssetManifest) {
+        if let Some(refs) = self.0.write().await.as_mut() {
+            refs.insert(path, manifest);
+        }
+    }
+
+    /// Records that the manifest at `path` was deleted, call after every manifest delete.
+    pub async fn remove(&self, path: &str) {
+        if let Some(refs) = self.0.write().await.as_mut() {
+            refs.remove(path);
+        }
+    }
 }
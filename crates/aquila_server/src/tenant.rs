@@ -0,0 +1,288 @@
+//! Multi-tenant storage, see [`TenantStorage`].
+//!
+//! ## Usage
+//!
+//! ```no_run
+//! # use aquila_server::prelude::*;
+//! # use aquila_server::tenant::TenantStorage;
+//! # use aquila_fs::FileSystemStorage;
+//! # use aquila_auth_mock::AllowAllAuth;
+//! let storage = TenantStorage::new(FileSystemStorage::new("./assets"));
+//! let app = AquilaServer::default().build(storage, AllowAllAuth);
+//! ```
+
+use aquila_core::prelude::*;
+use bytes::Bytes;
+use futures::Stream;
+use std::pin::Pin;
+
+/// Scope prefix naming the tenant of a user, e.g. `tenant:acme`.
+pub const TENANT_SCOPE_PREFIX: &str = "tenant:";
+
+/// Directory of the objects of a tenant, e.g. `tenants/acme/{hash}`.
+const TENANTS_DIR: &str = "tenants";
+
+/// Separates the tenant from the version in manifest names, e.g. `manifests/acme@v1.0`.
+///
+/// Manifests stay next to each other so listing and compression of the inner backend keep working.
+const MANIFEST_SEPARATOR: char = '@';
+
+/// Stores the data of every tenant under its own prefix, so tenants can't read each other's
+/// blobs or manifests, even with the same hash.
+///
+/// The tenant of a request is the `tenant:{name}` scope of the user, or else the user id,
+/// see [`StorageBackend::with_context`]. Objects go to `tenants/{tenant}/`, manifests are
+/// stored as `{tenant}@{version}` next to the untenanted ones. Tokens minted via `/auth/token`
+/// keep the tenant of the caller.
+///
+/// Deduplication only happens within a tenant, the same asset uploaded by two tenants is stored
+/// twice. With [`with_shared_blobs`](Self::with_shared_blobs), blobs are stored and deduplicated
+/// across tenants instead and only manifests and other objects are kept apart.
+///
+/// Without a request context, e.g. for the storage wide admin routes, paths are used as is.
+/// Those see all manifests, but only the blobs outside of `tenants/`, i.e. the shared ones.
+///
+/// ```
+/// # use aquila_core::prelude::*;
+/// # use aquila_fs::FileSystemStorage;
+/// # use aquila_server::tenant::TenantStorage;
+/// # #[tokio::main]
+/// # async fn main() {
+/// # let root = std::env::temp_dir().join("aquila_tenant_doctest");
+/// let storage = TenantStorage::new(FileSystemStorage::new(&root));
+/// let user = |id: &str| RequestContext {
+///     subject: id.into(),
+///     scopes: vec!["write".into()],
+/// };
+/// let (a, b) = (storage.with_context(&user("a")), storage.with_context(&user("b")));
+///
+/// let hash = "ab".repeat(32);
+/// a.write_blob(&hash, "data".into()).await.unwrap();
+/// a.write_manifest("v1", "{}".into()).await.unwrap();
+/// assert!(a.exists(&hash).await.unwrap());
+/// assert!(!b.exists(&hash).await.unwrap());
+/// assert_eq!(a.list_manifests().await.unwrap(), ["v1"]);
+/// assert!(b.list_manifests().await.unwrap().is_empty());
+/// # std::fs::remove_dir_all(&root).unwrap();
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct TenantStorage<S: StorageBackend> {
+    inner: S,
+    shared_blobs: bool,
+    /// Path safe name of the tenant of the current request.
+    tenant: Option<String>,
+}
+
+impl<S: StorageBackend> TenantStorage<S> {
+    /// Wraps `inner`, isolating all data per tenant.
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            shared_blobs: false,
+            tenant: None,
+        }
+    }
+
+    /// Share blobs between tenants, so they are deduplicated across tenants again.
+    ///
+    /// A tenant can then read the blobs of another tenant if it knows their hashes, e.g. from
+    /// a leaked manifest. Manifests and derived objects are still isolated.
+    pub fn with_shared_blobs(mut self, shared: bool) -> Self {
+        self.shared_blobs = shared;
+        self
+    }
+
+    /// Private helper for the manifest name of `version` of the current tenant.
+    fn manifest_version(&self, version: &str) -> String {
+        match &self.tenant {
+            Some(tenant) => format!("{tenant}{MANIFEST_SEPARATOR}{version}"),
+            None => version.to_string(),
+        }
+    }
+
+    /// Private helper to map a path to the current tenant.
+    ///
+    /// Manifest paths come from [`get_manifest_path`](StorageBackend::get_manifest_path) and are
+    /// already mapped.
+    fn path(&self, path: &str) -> Result<String, StorageError> {
+        let Some(tenant) = &self.tenant else {
+            return Ok(path.to_string());
+        };
+        if path.split('/').any(|segment| segment == "..") {
+            return Err(StorageError::NotFound(path.to_string()));
+        }
+        if (self.shared_blobs && is_blob_hash(path))
+            || path.starts_with(&self.inner.get_manifest_path(&self.manifest_version("")))
+        {
+            return Ok(path.to_string());
+        }
+
+        Ok(format!("{TENANTS_DIR}/{tenant}/{path}"))
+    }
+
+    /// Private helper, the blobs of a single tenant can't be listed.
+    fn check_blob_listing(&self) -> Result<(), StorageError> {
+        if self.tenant.is_some() && !self.shared_blobs {
+            return Err(StorageError::Generic(
+                "Listing blobs of a tenant is not supported".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Escapes everything but ASCII alphanumerics, `-` and `_`, so distinct tenants never share
+/// a prefix and can't contain path separators.
+fn tenant_name(ctx: &RequestContext) -> String {
+    let name = ctx
+        .scopes
+        .iter()
+        .find_map(|scope| scope.strip_prefix(TENANT_SCOPE_PREFIX))
+        .unwrap_or(&ctx.subject);
+
+    name.bytes()
+        .map(|b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' => (b as char).to_string(),
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+impl<S: StorageBackend> StorageBackend for TenantStorage<S> {
+    async fn write_blob(&self, hash: &str, data: Bytes) -> Result<bool, StorageError> {
+        self.inner.write_blob(&self.path(hash)?, data).await
+    }
+
+    async fn write_stream(
+        &self,
+        hash: &str,
+        stream: Pin<Box<dyn Stream<Item = Result<Bytes, std::io::Error>> + Send>>,
+        content_length: Option<u64>,
+    ) -> Result<bool, StorageError> {
+        self.inner
+            .write_stream(&self.path(hash)?, stream, content_length)
+            .await
+    }
+
+    async fn put_object(&self, path: &str, data: Bytes) -> Result<(), StorageError> {
+        self.inner.put_object(&self.path(path)?, data).await
+    }
+
+    async fn write_manifest(&self, version: &str, data: Bytes) -> Result<(), StorageError> {
+        self.inner
+            .write_manifest(&self.manifest_version(version), data)
+            .await
+    }
+
+    async fn read_file(&self, path: &str) -> Result<Bytes, StorageError> {
+        self.inner.read_file(&self.path(path)?).await
+    }
+
+    async fn exists(&self, path: &str) -> Result<bool, StorageError> {
+        self.inner.exists(&self.path(path)?).await
+    }
+
+    async fn exists_many(&self, paths: &[String]) -> Result<Vec<bool>, StorageError> {
+        let paths = paths
+            .iter()
+            .map(|path| self.path(path))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.inner.exists_many(&paths).await
+    }
+
+    async fn stat(&self, path: &str) -> Result<ObjectMeta, StorageError> {
+        self.inner.stat(&self.path(path)?).await
+    }
+
+    fn capabilities(&self) -> StorageCapabilities {
+        StorageCapabilities {
+            tenants: true,
+            ..self.inner.capabilities()
+        }
+    }
+
+    fn get_manifest_path(&self, version: &str) -> String {
+        self.inner
+            .get_manifest_path(&self.manifest_version(version))
+    }
+
+    async fn get_download_url(&self, path: &str) -> Result<Option<String>, StorageError> {
+        self.inner.get_download_url(&self.path(path)?).await
+    }
+
+    async fn get_download_url_as(
+        &self,
+        path: &str,
+        filename: &str,
+    ) -> Result<Option<String>, StorageError> {
+        self.inner
+            .get_download_url_as(&self.path(path)?, filename)
+            .await
+    }
+
+    async fn get_download_url_unchecked(
+        &self,
+        path: &str,
+        filename: Option<&str>,
+    ) -> Result<Option<String>, StorageError> {
+        self.inner
+            .get_download_url_unchecked(&self.path(path)?, filename)
+            .await
+    }
+
+    /// Lists the blobs of the inner backend, tenants only see them if blobs are shared.
+    async fn list_blobs(&self) -> Result<Vec<BlobInfo>, StorageError> {
+        self.check_blob_listing()?;
+        self.inner.list_blobs().await
+    }
+
+    /// Lists the manifests of the current tenant, or all manifests without a tenant.
+    async fn list_manifests(&self) -> Result<Vec<String>, StorageError> {
+        let versions = self.inner.list_manifests().await?;
+        if self.tenant.is_none() {
+            return Ok(versions);
+        }
+
+        let prefix = self.manifest_version("");
+        Ok(versions
+            .into_iter()
+            .filter_map(|version| version.strip_prefix(&prefix).map(String::from))
+            .collect())
+    }
+
+    async fn list_blobs_page(
+        &self,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<Page<BlobInfo>, StorageError> {
+        self.check_blob_listing()?;
+        self.inner.list_blobs_page(cursor, limit).await
+    }
+
+    async fn list_manifests_page(
+        &self,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<Page<String>, StorageError> {
+        if self.tenant.is_none() {
+            return self.inner.list_manifests_page(cursor, limit).await;
+        }
+
+        let mut versions = self.list_manifests().await?;
+        versions.sort();
+        Page::paginate(versions, cursor, limit)
+    }
+
+    async fn delete_file(&self, path: &str) -> Result<(), StorageError> {
+        self.inner.delete_file(&self.path(path)?).await
+    }
+
+    fn with_context(&self, ctx: &RequestContext) -> Self {
+        Self {
+            inner: self.inner.with_context(ctx),
+            shared_blobs: self.shared_blobs,
+            tenant: Some(tenant_name(ctx)),
+        }
+    }
+}
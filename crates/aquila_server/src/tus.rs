@@ -0,0 +1,446 @@
+//! Resumable uploads via the [tus](https://tus.io) protocol (v1.0.0) at `/files`, so existing
+//! tus clients can upload assets.
+//!
+//! Requires the `tus` feature. Besides the core protocol, the `creation` and `termination`
+//! extensions are supported, uploads must declare their `Upload-Length`.
+//!
+//! Every `PATCH` is stored as a part at `uploads/{id}-{n}`. Once all bytes are received, the
+//! parts are streamed into a blob under the computed hash, which the last `PATCH` responds with
+//! in the `X-Content-SHA256` header. This requires a backend with streaming support, creating an
+//! upload responds with `501 Not Implemented` otherwise.
+//!
+//! Upload state is kept in memory, so uploads don't survive a restart. Parts of abandoned
+//! uploads are left in storage.
+
+use crate::api::{ApiError, CONTENT_SHA256_HEADER, StatusError, check_scope};
+use crate::auth::AuthenticatedUser;
+use crate::state::AppState;
+
+use aquila_core::prelude::*;
+use axum::Router;
+use axum::extract::{Path, Request, State};
+use axum::http::{HeaderMap, HeaderValue, Method, StatusCode, header};
+use axum::middleware::{self, Next};
+use axum::response::{AppendHeaders, IntoResponse, Response};
+use axum::routing::{patch, post};
+use futures::{StreamExt, TryStreamExt, stream};
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+
+/// The supported protocol version.
+pub const TUS_VERSION: &str = "1.0.0";
+
+/// The supported protocol extensions.
+pub const TUS_EXTENSIONS: &str = "creation,termination";
+
+const TUS_RESUMABLE: &str = "Tus-Resumable";
+const UPLOAD_LENGTH: &str = "Upload-Length";
+const UPLOAD_OFFSET: &str = "Upload-Offset";
+
+/// The `Content-Type` required for `PATCH` requests.
+const OFFSET_OCTET_STREAM: &str = "application/offset+octet-stream";
+
+/// The routes of the protocol, merged into the router by [`AquilaServer`](crate::server::AquilaServer).
+pub fn routes<S: StorageBackend, A: AuthProvider>() -> Router<AppState<S, A>> {
+    Router::new()
+        .route("/files", post(create_upload).options(get_options))
+        .route(
+            "/files/{id}",
+            patch(append_upload)
+                .head(get_upload)
+                .delete(terminate_upload),
+        )
+        .layer(middleware::from_fn(tus_resumable))
+}
+
+/// In-progress uploads, by id.
+#[derive(Clone, Default)]
+pub struct TusUploads(Arc<Mutex<HashMap<String, TusUpload>>>);
+
+struct TusUpload {
+    /// Id of the user that created the upload, other users can't see it.
+    owner: String,
+    length: u64,
+    offset: u64,
+    /// Storage paths of the received parts, in order.
+    parts: Vec<String>,
+    /// Hash state of the bytes received so far.
+    hasher: Sha256,
+    /// Set while a `PATCH` is in progress.
+    locked: bool,
+}
+
+impl TusUploads {
+    /// Runs `f` on the upload `id` of `owner`, `404 Not Found` if there is none.
+    fn with<T>(
+        &self,
+        id: &str,
+        owner: &str,
+        f: impl FnOnce(&mut TusUpload) -> Result<T, ApiError>,
+    ) -> Result<T, ApiError> {
+        let mut uploads = self
+            .0
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Internal Error: Uploads mutex poisoned"))?;
+
+        match uploads.get_mut(id) {
+            Some(upload) if upload.owner == owner => f(upload),
+            _ => Err(status(StatusCode::NOT_FOUND, "Upload not found")),
+        }
+    }
+
+    fn insert(&self, id: String, upload: TusUpload) {
+        if let Ok(mut uploads) = self.0.lock() {
+            uploads.insert(id, upload);
+        }
+    }
+
+    fn remove(&self, id: &str) -> Option<TusUpload> {
+        self.0.lock().ok()?.remove(id)
+    }
+}
+
+/// Unlocks an upload when dropped, also if the client disconnects during a `PATCH` and the
+/// handler is dropped with it.
+struct UploadLock {
+    uploads: TusUploads,
+    id: String,
+    owner: String,
+}
+
+impl Drop for UploadLock {
+    fn drop(&mut self) {
+        let _ = self.uploads.with(&self.id, &self.owner, |upload| {
+            upload.locked = false;
+            Ok(())
+        });
+    }
+}
+
+fn status(status: StatusCode, message: impl Into<String>) -> ApiError {
+    ApiError::from(StatusError(status, message.into()))
+}
+
+fn parse_header(headers: &HeaderMap, name: &str) -> Result<u64, ApiError> {
+    headers
+        .get(name)
+        .and_then(|val| val.to_str().ok())
+        .and_then(|val| val.parse().ok())
+        .ok_or_else(|| {
+            status(
+                StatusCode::BAD_REQUEST,
+                format!("Missing or invalid {name}"),
+            )
+        })
+}
+
+/// Rejects requests of other protocol versions and adds `Tus-Resumable` to every response.
+async fn tus_resumable(request: Request, next: Next) -> Response {
+    let supported = request.method() == Method::OPTIONS
+        || request
+            .headers()
+            .get(TUS_RESUMABLE)
+            .is_some_and(|version| version == TUS_VERSION);
+
+    let mut response = if supported {
+        next.run(request).await
+    } else {
+        (
+            StatusCode::PRECONDITION_FAILED,
+            [("Tus-Version", TUS_VERSION)],
+        )
+            .into_response()
+    };
+    response
+        .headers_mut()
+        .insert(TUS_RESUMABLE, HeaderValue::from_static(TUS_VERSION));
+    response
+}
+
+/// OPTIONS /files
+async fn get_options() -> impl IntoResponse {
+    (
+        StatusCode::NO_CONTENT,
+        [
+            ("Tus-Version", TUS_VERSION),
+            ("Tus-Extension", TUS_EXTENSIONS),
+        ],
+    )
+}
+
+/// POST /files
+async fn create_upload<S: StorageBackend, A: AuthProvider>(
+    State(state): State<AppState<S, A>>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    check_scope(&user, "write")?;
+
+    if !state.storage.capabilities().streaming {
+        return Err(status(
+            StatusCode::NOT_IMPLEMENTED,
+            "Resumable uploads require a storage backend with streaming support",
+        ));
+    }
+    let length = parse_header(&headers, UPLOAD_LENGTH)?;
+
+    let mut id = [0u8; 16];
+    OsRng.fill_bytes(&mut id);
+    let id = hex::encode(id);
+    let location = format!("/files/{id}");
+
+    // Nothing will be appended, store the empty blob right away. The upload is still
+    // registered, so its location reports the final offset until it is terminated.
+    let mut headers = vec![(header::LOCATION.as_str(), location)];
+    if length == 0 {
+        let hash = hex::encode(Sha256::digest([]));
+        state
+            .storage_for(&user)
+            .write_blob(&hash, Default::default())
+            .await?;
+        headers.push((CONTENT_SHA256_HEADER, hash));
+    }
+
+    state.tus_uploads.insert(
+        id,
+        TusUpload {
+            owner: user.id,
+            length,
+            offset: 0,
+            parts: Vec::new(),
+            hasher: Sha256::new(),
+            locked: false,
+        },
+    );
+
+    Ok((StatusCode::CREATED, AppendHeaders(headers)).into_response())
+}
+
+/// HEAD /files/{id}
+async fn get_upload<S: StorageBackend, A: AuthProvider>(
+    State(state): State<AppState<S, A>>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    check_scope(&user, "write")?;
+
+    let (offset, length) = state
+        .tus_uploads
+        .with(&id, &user.id, |upload| Ok((upload.offset, upload.length)))?;
+
+    Ok((
+        StatusCode::OK,
+        [
+            (UPLOAD_OFFSET, offset.to_string()),
+            (UPLOAD_LENGTH, length.to_string()),
+            ("Cache-Control", "no-store".to_string()),
+        ],
+    ))
+}
+
+/// PATCH /files/{id}
+///
+/// Appends the body at `Upload-Offset`. The last `PATCH` stores the blob and responds with its
+/// hash in `X-Content-SHA256`.
+async fn append_upload<S: StorageBackend, A: AuthProvider>(
+    State(state): State<AppState<S, A>>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    Path(id): Path<String>,
+    request: Request,
+) -> Result<impl IntoResponse, ApiError> {
+    check_scope(&user, "write")?;
+
+    let headers = request.headers();
+    if headers
+        .get(header::CONTENT_TYPE)
+        .is_none_or(|content_type| content_type != OFFSET_OCTET_STREAM)
+    {
+        return Err(status(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!("Content-Type must be {OFFSET_OCTET_STREAM}"),
+        ));
+    }
+    let offset = parse_header(headers, UPLOAD_OFFSET)?;
+    let content_length = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|val| val.to_str().ok())
+        .and_then(|val| val.parse::<u64>().ok());
+
+    let (part, hasher, remaining) = state.tus_uploads.with(&id, &user.id, |upload| {
+        if upload.locked {
+            return Err(status(StatusCode::LOCKED, "Upload in progress"));
+        }
+        if upload.offset != offset {
+            return Err(status(
+                StatusCode::CONFLICT,
+                format!("Upload-Offset is {}", upload.offset),
+            ));
+        }
+        let remaining = upload.length - upload.offset;
+        if content_length.is_some_and(|len| len > remaining) {
+            return Err(status(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "Body exceeds Upload-Length",
+            ));
+        }
+
+        upload.locked = true;
+        let part = format!("uploads/{id}-{}", upload.parts.len());
+        Ok((part, upload.hasher.clone(), remaining))
+    })?;
+    let lock = UploadLock {
+        uploads: state.tus_uploads.clone(),
+        id: id.clone(),
+        owner: user.id.clone(),
+    };
+
+    let storage = state.storage_for(&user);
+    let result = append(&storage, &id, &part, hasher, remaining, request).await;
+    let appended = result.as_ref().ok().cloned().flatten();
+
+    let (offset, done) = state.tus_uploads.with(&id, &user.id, |upload| {
+        if let Some((hasher, received)) = appended {
+            upload.offset += received;
+            upload.hasher = hasher;
+            upload.parts.push(part);
+        }
+        Ok((upload.offset, upload.offset == upload.length))
+    })?;
+    // Unlock, the upload is only removed once its blob is stored.
+    drop(lock);
+    result?;
+
+    let mut headers = vec![(UPLOAD_OFFSET, offset.to_string())];
+    if done {
+        let hash = complete(&state, &storage, &id, &user.id).await?;
+        headers.push((CONTENT_SHA256_HEADER, hash));
+    }
+
+    Ok((StatusCode::NO_CONTENT, AppendHeaders(headers)))
+}
+
+/// Stores the body of a `PATCH` as the part at `path`, returns the new hash state and the number
+/// of received bytes, `None` if the body was empty.
+async fn append<S: StorageBackend>(
+    storage: &S,
+    id: &str,
+    path: &str,
+    hasher: Sha256,
+    remaining: u64,
+    request: Request,
+) -> Result<Option<(Sha256, u64)>, ApiError> {
+    let body = request
+        .into_body()
+        .into_data_stream()
+        .map_err(std::io::Error::other);
+
+    // Like `PUT /assets/stream/{hash}`, the hash state is folded through the stream.
+    let (result_tx, result_rx) = oneshot::channel();
+    let stream = stream::unfold(
+        (body, hasher, 0u64, Some(result_tx)),
+        move |(mut body, mut hasher, mut received, result_tx)| async move {
+            let result_tx = result_tx?;
+            match body.next().await {
+                Some(Ok(chunk)) => {
+                    received += chunk.len() as u64;
+                    if received > remaining {
+                        let _ = result_tx.send(Err(()));
+                        let err = std::io::Error::other("Body exceeds Upload-Length");
+                        return Some((Err(err), (body, hasher, received, None)));
+                    }
+                    hasher.update(&chunk);
+                    Some((Ok(chunk), (body, hasher, received, Some(result_tx))))
+                }
+                Some(Err(e)) => Some((Err(e), (body, hasher, received, None))),
+                None => {
+                    let _ = result_tx.send(Ok((hasher, received)));
+                    None
+                }
+            }
+        },
+    );
+
+    let written = storage.write_stream(path, Box::pin(stream), None).await;
+
+    match (written, result_rx.await) {
+        (Ok(_), Ok(Ok((_, 0)))) => {
+            let _ = storage.delete_file(path).await;
+            Ok(None)
+        }
+        (Ok(_), Ok(Ok((hasher, received)))) => Ok(Some((hasher, received))),
+        (written, result) => {
+            if let Err(e) = storage.delete_file(path).await {
+                tracing::error!("Failed to delete part {path} of upload {id}: {e}");
+            }
+            if let Ok(Err(())) = result {
+                return Err(status(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    "Body exceeds Upload-Length",
+                ));
+            }
+            written?;
+            Err(status(StatusCode::BAD_REQUEST, "Incomplete upload"))
+        }
+    }
+}
+
+/// Streams the parts of a complete upload into a blob, returns its hash.
+async fn complete<S: StorageBackend, A: AuthProvider>(
+    state: &AppState<S, A>,
+    storage: &S,
+    id: &str,
+    owner: &str,
+) -> Result<String, ApiError> {
+    let (hash, length, parts) = state.tus_uploads.with(id, owner, |upload| {
+        let hash = hex::encode(upload.hasher.clone().finalize());
+        Ok((hash, upload.length, upload.parts.clone()))
+    })?;
+
+    let reader = storage.clone();
+    let data = stream::iter(parts.clone())
+        .then(move |part| {
+            let reader = reader.clone();
+            async move { reader.read_file(&part).await }
+        })
+        .map_err(std::io::Error::other);
+    storage
+        .write_stream(&hash, Box::pin(data), Some(length))
+        .await?;
+
+    state.tus_uploads.remove(id);
+    delete_parts(storage, &parts).await;
+
+    Ok(hash)
+}
+
+/// DELETE /files/{id}
+async fn terminate_upload<S: StorageBackend, A: AuthProvider>(
+    State(state): State<AppState<S, A>>,
+    AuthenticatedUser(user): AuthenticatedUser,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    check_scope(&user, "write")?;
+
+    state.tus_uploads.with(&id, &user.id, |upload| {
+        if upload.locked {
+            return Err(status(StatusCode::LOCKED, "Upload in progress"));
+        }
+        Ok(())
+    })?;
+    if let Some(upload) = state.tus_uploads.remove(&id) {
+        delete_parts(&state.storage_for(&user), &upload.parts).await;
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn delete_parts<S: StorageBackend>(storage: &S, parts: &[String]) {
+    for part in parts {
+        if let Err(e) = storage.delete_file(part).await {
+            tracing::error!("Failed to delete upload part {part}: {e}");
+        }
+    }
+}
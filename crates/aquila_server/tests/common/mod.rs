@@ -0,0 +1,50 @@
+//! Fixture shared by the integration tests.
+#![allow(dead_code)]
+
+use aquila_auth_mock::MockAuth;
+use aquila_fs::FileSystemStorage;
+use axum::Router;
+use axum::body::Body;
+use axum::http::{Request, Response};
+use bytes::Bytes;
+use std::path::PathBuf;
+use tower::ServiceExt;
+
+/// A [`FileSystemStorage`] in a temporary directory, removed on drop.
+pub struct TempStorage {
+    pub root: PathBuf,
+    pub storage: FileSystemStorage,
+}
+
+impl TempStorage {
+    /// Starts out empty, `name` must be unique within the test binary.
+    pub fn new(name: &str) -> Self {
+        let root = std::env::temp_dir().join(format!("aquila_{name}_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        Self {
+            storage: FileSystemStorage::new(&root),
+            root,
+        }
+    }
+}
+
+impl Drop for TempStorage {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
+
+/// Accepts the token `writer` with `read` and `write`.
+pub fn writer() -> MockAuth {
+    MockAuth::default().with_token("writer", ["read", "write"])
+}
+
+pub async fn send(app: &Router, request: Request<Body>) -> Response<Body> {
+    app.clone().oneshot(request).await.unwrap()
+}
+
+pub async fn read_body(response: Response<Body>) -> Bytes {
+    axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap()
+}
@@ -0,0 +1,223 @@
+//! Asserts how assets are downloaded, redirected, linked and verified.
+
+mod common;
+
+use aquila_auth_mock::MockAuth;
+use aquila_core::prelude::*;
+use aquila_fs::FileSystemStorage;
+use aquila_server::prelude::*;
+use axum::Router;
+use axum::body::Body;
+use axum::http::{Method, Request, StatusCode, header};
+use bytes::Bytes;
+use common::{TempStorage, read_body, send};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Downloads `hash` with the given conditional headers, returns the status and headers.
+async fn download(
+    app: &Router,
+    hash: &str,
+    conditions: &[(header::HeaderName, &str)],
+) -> (StatusCode, header::HeaderMap) {
+    let mut request = Request::builder()
+        .uri(format!("/assets/{hash}"))
+        .header("Authorization", "Bearer user");
+    for (name, value) in conditions {
+        request = request.header(name, *value);
+    }
+    let response = send(app, request.body(Body::empty()).unwrap()).await;
+    (response.status(), response.headers().clone())
+}
+
+#[tokio::test]
+async fn conditional_download() {
+    let temp = TempStorage::new("conditional");
+    let auth = MockAuth::default().with_token("user", ["read", "write"]);
+    let app = AquilaServer::default().build(temp.storage.clone(), auth);
+
+    let hash = hex::encode(Sha256::digest(b"data"));
+    let upload = Request::builder()
+        .method(Method::PUT)
+        .uri(format!("/assets/stream/{hash}"))
+        .header("Authorization", "Bearer user")
+        .body(Body::from("data"))
+        .unwrap();
+    let response = send(&app, upload).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let (status, headers) = download(&app, &hash, &[]).await;
+    assert_eq!(status, StatusCode::OK);
+    let etag = headers[header::ETAG].to_str().unwrap().to_string();
+    let modified = headers[header::LAST_MODIFIED].to_str().unwrap().to_string();
+    assert_eq!(etag, format!("\"{hash}\""));
+
+    let (status, headers) = download(&app, &hash, &[(header::IF_NONE_MATCH, &etag)]).await;
+    assert_eq!(status, StatusCode::NOT_MODIFIED);
+    assert_eq!(headers[header::LAST_MODIFIED], modified.as_str());
+
+    let since = [(header::IF_MODIFIED_SINCE, modified.as_str())];
+    assert_eq!(
+        download(&app, &hash, &since).await.0,
+        StatusCode::NOT_MODIFIED
+    );
+
+    let old = [(header::IF_MODIFIED_SINCE, "Thu, 01 Jan 1970 00:00:00 GMT")];
+    assert_eq!(download(&app, &hash, &old).await.0, StatusCode::OK);
+
+    // A mismatching ETag wins over a current date.
+    let both = [
+        (header::IF_NONE_MATCH, "\"other\""),
+        (header::IF_MODIFIED_SINCE, modified.as_str()),
+    ];
+    assert_eq!(download(&app, &hash, &both).await.0, StatusCode::OK);
+
+    let missing = "ab".repeat(32);
+    let (status, _) = download(&app, &missing, &[(header::IF_NONE_MATCH, "*")]).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn redirect_mode_without_urls() {
+    let temp = TempStorage::new("redirect");
+    let storage = temp.storage.clone();
+    let hash = hex::encode(Sha256::digest(b"data"));
+    storage.write_blob(&hash, "data".into()).await.unwrap();
+
+    let config = AquilaServerConfig {
+        download_mode: DownloadMode::Redirect,
+        ..Default::default()
+    };
+    let auth = MockAuth::default().with_token("user", ["read"]);
+    let app = AquilaServer::new(config).build(storage, auth);
+
+    // The filesystem backend has no download URLs to redirect to.
+    let (status, _) = download(&app, &hash, &[]).await;
+    assert_eq!(status, StatusCode::NOT_IMPLEMENTED);
+}
+
+/// A backend with download URLs and without `stat`, counting reads.
+#[derive(Clone)]
+struct Presigned {
+    inner: FileSystemStorage,
+    reads: Arc<AtomicUsize>,
+}
+
+impl StorageBackend for Presigned {
+    async fn write_blob(&self, hash: &str, data: Bytes) -> Result<bool, StorageError> {
+        self.inner.write_blob(hash, data).await
+    }
+
+    async fn put_object(&self, path: &str, data: Bytes) -> Result<(), StorageError> {
+        self.inner.put_object(path, data).await
+    }
+
+    async fn write_manifest(&self, version: &str, data: Bytes) -> Result<(), StorageError> {
+        self.inner.write_manifest(version, data).await
+    }
+
+    async fn read_file(&self, path: &str) -> Result<Bytes, StorageError> {
+        self.reads.fetch_add(1, Ordering::SeqCst);
+        self.inner.read_file(path).await
+    }
+
+    async fn exists(&self, path: &str) -> Result<bool, StorageError> {
+        self.inner.exists(path).await
+    }
+
+    async fn get_download_url(&self, path: &str) -> Result<Option<String>, StorageError> {
+        Ok(Some(format!("https://cdn.example.com/{path}")))
+    }
+
+    async fn delete_file(&self, path: &str) -> Result<(), StorageError> {
+        self.inner.delete_file(path).await
+    }
+}
+
+#[tokio::test]
+async fn redirect_without_read() {
+    let temp = TempStorage::new("presigned");
+    let storage = Presigned {
+        inner: temp.storage.clone(),
+        reads: Default::default(),
+    };
+    let hash = hex::encode(Sha256::digest(b"data"));
+    storage.write_blob(&hash, "data".into()).await.unwrap();
+
+    let auth = MockAuth::default().with_token("user", ["read"]);
+    let app = AquilaServer::default().build(storage.clone(), auth);
+
+    let (status, headers) = download(&app, &hash, &[]).await;
+    assert_eq!(status, StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(
+        headers[header::LOCATION],
+        format!("https://cdn.example.com/{hash}").as_str()
+    );
+
+    let (status, _) = download(&app, &"ab".repeat(32), &[]).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(storage.reads.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn signed_link() {
+    let temp = TempStorage::new("signed");
+    let storage = temp.storage.clone();
+    let hash = hex::encode(Sha256::digest(b"data"));
+    storage.write_blob(&hash, "data".into()).await.unwrap();
+
+    let auth = MockAuth::default().with_token("user", ["read"]);
+    let app = AquilaServer::default().build(storage, auth);
+
+    let request = Request::builder()
+        .uri(format!("/assets/{hash}/sign?ttl=60"))
+        .header("Authorization", "Bearer user")
+        .body(Body::empty())
+        .unwrap();
+    let response = send(&app, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = read_body(response).await;
+    let link: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let url = link["url"].as_str().unwrap();
+    assert_eq!(link["expires_in"], 60);
+
+    let get = async |uri: String| {
+        let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+        send(&app, request).await.status()
+    };
+    assert_eq!(get(url.to_string()).await, StatusCode::OK);
+    assert_eq!(get(format!("{url}x")).await, StatusCode::UNAUTHORIZED);
+
+    // The link is bound to its blob.
+    let other = "ab".repeat(32);
+    let token = url.split_once("?token=").unwrap().1;
+    let uri = format!("/assets/{other}?token={token}");
+    assert_eq!(get(uri).await, StatusCode::UNAUTHORIZED);
+    let uri = format!("/manifest/latest?token={token}");
+    assert_eq!(get(uri).await, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn verify_on_read() {
+    let temp = TempStorage::new("verify_read");
+    let storage = temp.storage.clone();
+    let hash = hex::encode(Sha256::digest(b"data"));
+    let corrupted = hex::encode(Sha256::digest(b"other"));
+    storage.write_blob(&hash, "data".into()).await.unwrap();
+    // Bypasses the server, as bit rot would.
+    storage.write_blob(&corrupted, "dato".into()).await.unwrap();
+
+    let config = AquilaServerConfig {
+        verify_on_read: true,
+        ..Default::default()
+    };
+    let auth = MockAuth::default().with_token("user", ["read"]);
+    let app = AquilaServer::new(config).build(storage, auth);
+
+    assert_eq!(download(&app, &hash, &[]).await.0, StatusCode::OK);
+    assert_eq!(
+        download(&app, &corrupted, &[]).await.0,
+        StatusCode::INTERNAL_SERVER_ERROR
+    );
+}
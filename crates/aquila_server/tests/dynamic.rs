@@ -0,0 +1,38 @@
+//! Asserts that backends picked at runtime can be served.
+
+mod common;
+
+use aquila_core::dynamic::{BoxedAuthProvider, BoxedStorage};
+use aquila_server::prelude::*;
+use axum::body::Body;
+use axum::http::{Method, Request, StatusCode};
+use common::{TempStorage, read_body, send, writer};
+use sha2::{Digest, Sha256};
+
+#[tokio::test]
+async fn boxed_backends() {
+    let temp = TempStorage::new("dynamic");
+    let storage = BoxedStorage::new(temp.storage.clone());
+    let auth = BoxedAuthProvider::new(writer());
+    let app = AquilaServer::default().build(storage, auth);
+
+    let hash = hex::encode(Sha256::digest(b"data"));
+    let upload = Request::builder()
+        .method(Method::PUT)
+        .uri(format!("/assets/stream/{hash}"))
+        .header("Authorization", "Bearer writer")
+        .body(Body::from("data"))
+        .unwrap();
+    let response = send(&app, upload).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let download = Request::builder()
+        .uri(format!("/assets/{hash}"))
+        .header("Authorization", "Bearer writer")
+        .body(Body::empty())
+        .unwrap();
+    let response = send(&app, download).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = read_body(response).await;
+    assert_eq!(body, "data");
+}
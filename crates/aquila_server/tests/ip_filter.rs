@@ -0,0 +1,103 @@
+//! Asserts that `write_ip_filter` only lets writes from allowed networks through.
+
+mod common;
+
+use aquila_server::prelude::*;
+use axum::Router;
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::{Method, Request, StatusCode};
+use common::{TempStorage, send, writer};
+use std::net::SocketAddr;
+
+/// Sends a request from `peer`, with an optional forwarded header, `Forwarded` if it contains
+/// `for=`, `X-Forwarded-For` otherwise.
+async fn send_from(
+    app: &Router,
+    method: Method,
+    uri: &str,
+    peer: Option<&str>,
+    forwarded: Option<&str>,
+) -> StatusCode {
+    let mut request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("Authorization", "Bearer writer");
+    if let Some(forwarded) = forwarded {
+        let name = if forwarded.contains("for=") {
+            "Forwarded"
+        } else {
+            "X-Forwarded-For"
+        };
+        request = request.header(name, forwarded);
+    }
+    let mut request = request.body(Body::from("data")).unwrap();
+    if let Some(peer) = peer {
+        let peer: SocketAddr = peer.parse().unwrap();
+        request.extensions_mut().insert(ConnectInfo(peer));
+    }
+    send(app, request).await.status()
+}
+
+#[tokio::test]
+async fn write_ip_filter() {
+    let temp = TempStorage::new("ip_filter");
+    let filter = IpFilter::default()
+        .allow("10.0.0.0/8".parse().unwrap())
+        .deny("10.0.13.0/24".parse().unwrap());
+    let config = AquilaServerConfig {
+        write_ip_filter: Some(filter),
+        trusted_proxies: vec!["127.0.0.1/32".parse().unwrap()],
+        ..Default::default()
+    };
+    let app = AquilaServer::new(config).build(temp.storage.clone(), writer());
+
+    let write = |peer, forwarded| send_from(&app, Method::POST, "/assets", peer, forwarded);
+    assert_eq!(
+        write(Some("10.1.2.3:1234"), None).await,
+        StatusCode::CREATED
+    );
+    assert_eq!(
+        write(Some("10.0.13.37:1234"), None).await,
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        write(Some("203.0.113.1:1234"), None).await,
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(write(None, None).await, StatusCode::FORBIDDEN);
+
+    // Reads are not filtered.
+    let read = send_from(
+        &app,
+        Method::GET,
+        "/manifests",
+        Some("203.0.113.1:1234"),
+        None,
+    )
+    .await;
+    assert_eq!(read, StatusCode::OK);
+
+    // Behind the trusted proxy, the last untrusted entry is the client.
+    let proxy = Some("127.0.0.1:1234");
+    assert_eq!(write(proxy, Some("10.1.2.3")).await, StatusCode::OK);
+    assert_eq!(
+        write(proxy, Some("203.0.113.1")).await,
+        StatusCode::FORBIDDEN
+    );
+    let spoofed = Some("10.1.2.3, 203.0.113.1");
+    assert_eq!(write(proxy, spoofed).await, StatusCode::FORBIDDEN);
+    let forwarded = Some("for=203.0.113.1, for=\"10.1.2.3:4711\";proto=https");
+    assert_eq!(write(proxy, forwarded).await, StatusCode::OK);
+    let chained = Some("for=10.1.2.3, for=127.0.0.1");
+    assert_eq!(write(proxy, chained).await, StatusCode::OK);
+    let unknown = Some("for=unknown");
+    assert_eq!(write(proxy, unknown).await, StatusCode::FORBIDDEN);
+
+    // Other peers can't claim an allowed IP.
+    let untrusted = Some("203.0.113.1:1234");
+    assert_eq!(
+        write(untrusted, Some("10.1.2.3")).await,
+        StatusCode::FORBIDDEN
+    );
+}
@@ -0,0 +1,498 @@
+//! Asserts how manifests are published, cached, stored and served.
+
+mod common;
+
+use aquila_auth_mock::MockAuth;
+use aquila_core::prelude::*;
+use aquila_fs::FileSystemStorage;
+use aquila_server::prelude::*;
+use axum::Router;
+use axum::body::Body;
+use axum::http::{Method, Request, Response, StatusCode, header};
+use bytes::Bytes;
+use common::{TempStorage, read_body, send, writer};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+
+fn publish(version: &str) -> Request<Body> {
+    let manifest = serde_json::json!({
+        "version": version,
+        "published_at": "2024-01-01T00:00:00Z",
+        "published_by": "test",
+        "assets": {}
+    });
+    Request::builder()
+        .method(Method::POST)
+        .uri("/manifest")
+        .header("Authorization", "Bearer writer")
+        .header("Content-Type", "application/json")
+        .body(Body::from(manifest.to_string()))
+        .unwrap()
+}
+
+/// Fetches `latest`, returns its version and ETag.
+async fn latest(app: &Router) -> (String, String) {
+    let request = Request::builder()
+        .uri("/manifest/latest")
+        .header("Authorization", "Bearer writer")
+        .body(Body::empty())
+        .unwrap();
+    let response = send(app, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let etag = response.headers()[header::ETAG]
+        .to_str()
+        .unwrap()
+        .to_string();
+    let body = read_body(response).await;
+    let manifest: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    (manifest["version"].as_str().unwrap().to_string(), etag)
+}
+
+#[tokio::test]
+async fn cached_latest_follows_publish() {
+    let temp = TempStorage::new("manifest_cache");
+    let config = AquilaServerConfig {
+        manifest_cache_ttl: Some(Duration::from_secs(60)),
+        ..Default::default()
+    };
+    let app = AquilaServer::new(config).build(temp.storage.clone(), writer());
+
+    assert_eq!(
+        send(&app, publish("v1")).await.status(),
+        StatusCode::CREATED
+    );
+    let (version, etag) = latest(&app).await;
+    assert_eq!(version, "v1");
+    assert_eq!(latest(&app).await, (version, etag.clone()));
+
+    let request = Request::builder()
+        .uri("/manifest/latest")
+        .header("Authorization", "Bearer writer")
+        .header(header::IF_NONE_MATCH, &etag)
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(send(&app, request).await.status(), StatusCode::NOT_MODIFIED);
+
+    assert_eq!(
+        send(&app, publish("v2")).await.status(),
+        StatusCode::CREATED
+    );
+    let (version, new_etag) = latest(&app).await;
+    assert_eq!(version, "v2");
+    assert_ne!(new_etag, etag);
+}
+
+#[tokio::test]
+async fn corrupted_manifest() {
+    let temp = TempStorage::new("manifest_corrupt");
+    let storage = &temp.storage;
+    let app = AquilaServer::default().build(storage.clone(), writer());
+
+    assert_eq!(
+        send(&app, publish("v1")).await.status(),
+        StatusCode::CREATED
+    );
+    assert_eq!(latest(&app).await.0, "v1");
+
+    // Bypasses the server, as bit rot would.
+    let manifest = serde_json::json!({
+        "version": "v1",
+        "published_at": "2024-01-01T00:00:00Z",
+        "published_by": "someone else",
+        "assets": {}
+    });
+    storage
+        .write_manifest("latest", manifest.to_string().into())
+        .await
+        .unwrap();
+
+    let request = Request::builder()
+        .uri("/manifest/latest")
+        .header("Authorization", "Bearer writer")
+        .body(Body::empty())
+        .unwrap();
+    let response = send(&app, request).await;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    let body = read_body(response).await;
+    assert!(String::from_utf8_lossy(&body).contains("checksum mismatch"));
+}
+
+#[tokio::test]
+async fn publish_requiring_blobs() {
+    let temp = TempStorage::new("manifest_blobs");
+    let storage = &temp.storage;
+    let app = AquilaServer::default().build(storage.clone(), writer());
+
+    assert_eq!(
+        send(&app, publish("v1")).await.status(),
+        StatusCode::CREATED
+    );
+
+    let hash = "ab".repeat(32);
+    let release = || {
+        let manifest = serde_json::json!({
+            "version": "v2",
+            "published_at": "2024-01-01T00:00:00Z",
+            "published_by": "test",
+            "assets": {
+                "image.png": { "hash": hash, "size": 4, "mime_type": "image/png" }
+            }
+        });
+        Request::builder()
+            .method(Method::POST)
+            .uri("/manifest?require_blobs=true")
+            .header("Authorization", "Bearer writer")
+            .header("Content-Type", "application/json")
+            .body(Body::from(manifest.to_string()))
+            .unwrap()
+    };
+
+    let response = send(&app, release()).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let body = read_body(response).await;
+    assert!(String::from_utf8_lossy(&body).contains(&hash));
+    assert!(!storage.exists("manifests/v2").await.unwrap());
+    assert_eq!(latest(&app).await.0, "v1");
+
+    storage.write_blob(&hash, "data".into()).await.unwrap();
+    assert_eq!(send(&app, release()).await.status(), StatusCode::CREATED);
+    assert_eq!(latest(&app).await.0, "v2");
+}
+
+#[tokio::test]
+async fn prune_manifests() {
+    let temp = TempStorage::new("manifest_prune");
+    let storage = &temp.storage;
+    let auth = MockAuth::default().with_token("writer", ["read", "write", "admin"]);
+    let app = AquilaServer::default().build(storage.clone(), auth);
+
+    // `latest` points to the oldest version, as after a rollback.
+    for (version, day) in [("v1", 1), ("v2", 2), ("v3", 3), ("v4", 4)] {
+        let manifest = serde_json::json!({
+            "version": version,
+            "published_at": format!("2024-01-0{day}T00:00:00Z"),
+            "published_by": "test",
+            "assets": {}
+        });
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(format!("/manifest?latest={}", version == "v1"))
+            .header("Authorization", "Bearer writer")
+            .header("Content-Type", "application/json")
+            .body(Body::from(manifest.to_string()))
+            .unwrap();
+        assert_eq!(send(&app, request).await.status(), StatusCode::CREATED);
+    }
+
+    let prune = |query: &str| {
+        Request::builder()
+            .method(Method::POST)
+            .uri(format!("/admin/prune-manifests?{query}"))
+            .header("Authorization", "Bearer writer")
+            .body(Body::empty())
+            .unwrap()
+    };
+    let report = |response: Response<Body>| async {
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = read_body(response).await;
+        serde_json::from_slice::<serde_json::Value>(&body).unwrap()["removed"].clone()
+    };
+
+    let removed = report(send(&app, prune("keep=2&dry_run=true")).await).await;
+    assert_eq!(removed, serde_json::json!(["v2", "v3"]));
+    assert!(storage.exists("manifests/v2").await.unwrap());
+
+    // A release is kept in addition to `keep`.
+    let removed = report(send(&app, prune("keep=2&keep_versions=v2")).await).await;
+    assert_eq!(removed, serde_json::json!(["v3"]));
+    assert!(storage.exists("manifests/v2").await.unwrap());
+
+    let removed = report(send(&app, prune("keep=2")).await).await;
+    assert_eq!(removed, serde_json::json!(["v2"]));
+    for (version, kept) in [("v1", true), ("v2", false), ("v3", false), ("v4", true)] {
+        let path = format!("manifests/{version}");
+        assert_eq!(storage.exists(&path).await.unwrap(), kept, "{version}");
+    }
+    assert_eq!(latest(&app).await.0, "v1");
+
+    let removed = report(send(&app, prune("keep=0")).await).await;
+    assert_eq!(removed, serde_json::json!(["v4"]));
+}
+
+#[cfg(all(feature = "yaml", feature = "toml"))]
+#[tokio::test]
+async fn publish_yaml_and_toml() {
+    let temp = TempStorage::new("formats");
+    let app = AquilaServer::default().build(temp.storage.clone(), writer());
+
+    let yaml = "
+version: v1
+published_at: 2024-01-01T00:00:00Z
+published_by: test
+assets:
+  textures/a.png: { hash: abababababababababababababababababababababababababababababababab, size: 1 }
+";
+    // Native TOML datetimes are accepted too.
+    let toml = r#"
+version = "v2"
+published_at = 2024-01-02T00:00:00Z
+published_by = "test"
+
+[assets."textures/a.png"]
+hash = "abababababababababababababababababababababababababababababababab"
+size = 1
+"#;
+
+    for (content_type, body) in [
+        ("application/yaml", yaml),
+        ("text/toml; charset=utf-8", toml),
+    ] {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/manifest")
+            .header("Authorization", "Bearer writer")
+            .header("Content-Type", content_type)
+            .body(Body::from(body))
+            .unwrap();
+        assert_eq!(send(&app, request).await.status(), StatusCode::CREATED);
+    }
+    assert_eq!(latest(&app).await.0, "v2");
+
+    // Stored as JSON.
+    let request = Request::builder()
+        .uri("/manifest/v1")
+        .header("Authorization", "Bearer writer")
+        .body(Body::empty())
+        .unwrap();
+    let response = send(&app, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = read_body(response).await;
+    let manifest: AssetManifest = serde_json::from_slice(&body).unwrap();
+    assert_eq!(manifest.assets["textures/a.png"].hash, "ab".repeat(32));
+
+    let invalid = Request::builder()
+        .method(Method::POST)
+        .uri("/manifest")
+        .header("Authorization", "Bearer writer")
+        .header("Content-Type", "application/yaml")
+        .body(Body::from("version: [unclosed"))
+        .unwrap();
+    assert_eq!(
+        send(&app, invalid).await.status(),
+        StatusCode::UNPROCESSABLE_ENTITY
+    );
+}
+
+#[tokio::test]
+async fn resolved_latest_version() {
+    let temp = TempStorage::new("resolved");
+    let app = AquilaServer::default().build(temp.storage.clone(), writer());
+
+    for version in ["v1", "v1.3"] {
+        assert_eq!(
+            send(&app, publish(version)).await.status(),
+            StatusCode::CREATED
+        );
+    }
+
+    let get = |etag: Option<&str>| {
+        let mut request = Request::builder()
+            .uri("/manifest/latest")
+            .header("Authorization", "Bearer writer");
+        if let Some(etag) = etag {
+            request = request.header(header::IF_NONE_MATCH, etag);
+        }
+        send(&app, request.body(Body::empty()).unwrap())
+    };
+
+    let response = get(None).await;
+    assert_eq!(response.headers()["X-Aquila-Resolved-Version"], "v1.3");
+    let etag = response.headers()[header::ETAG]
+        .to_str()
+        .unwrap()
+        .to_string();
+    // The stored `latest` keeps the version it was published as.
+    assert_eq!(latest(&app).await.0, "v1.3");
+
+    let response = get(Some(&etag)).await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(response.headers()["X-Aquila-Resolved-Version"], "v1.3");
+}
+
+#[tokio::test]
+async fn latest_pointer() {
+    let temp = TempStorage::new("manifest_pointer");
+    let storage = &temp.storage;
+    let app = AquilaServer::default().build(storage.clone(), writer());
+    let stored = || async {
+        let path = storage.get_manifest_path("latest");
+        storage.read_file(&path).await.unwrap()
+    };
+
+    assert_eq!(
+        send(&app, publish("v1")).await.status(),
+        StatusCode::CREATED
+    );
+    assert_eq!(stored().await.as_ref(), b"\"v1\"");
+    assert_eq!(latest(&app).await.0, "v1");
+
+    // A full copy, as written by older versions, is still read.
+    let manifest = serde_json::json!({
+        "version": "v1",
+        "published_at": "2024-01-01T00:00:00Z",
+        "published_by": "test",
+        "assets": {}
+    });
+    storage
+        .write_manifest("latest", manifest.to_string().into())
+        .await
+        .unwrap();
+    let checksum = format!("checksums/{}", storage.get_manifest_path("latest"));
+    storage.delete_file(&checksum).await.unwrap();
+    assert_eq!(latest(&app).await.0, "v1");
+
+    assert_eq!(
+        send(&app, publish("v2")).await.status(),
+        StatusCode::CREATED
+    );
+    assert_eq!(stored().await.as_ref(), b"\"v2\"");
+    assert_eq!(latest(&app).await.0, "v2");
+
+    let request = Request::builder()
+        .method(Method::DELETE)
+        .uri("/manifest/v2")
+        .header("Authorization", "Bearer writer")
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(send(&app, request).await.status(), StatusCode::NO_CONTENT);
+    assert_eq!(stored().await.as_ref(), b"\"v1\"");
+    assert_eq!(latest(&app).await.0, "v1");
+}
+
+#[tokio::test]
+async fn reserved_latest_version() {
+    let temp = TempStorage::new("manifest_reserved");
+    let app = AquilaServer::default().build(temp.storage.clone(), writer());
+
+    assert_eq!(
+        send(&app, publish("v1")).await.status(),
+        StatusCode::CREATED
+    );
+    assert_eq!(
+        send(&app, publish("latest")).await.status(),
+        StatusCode::BAD_REQUEST
+    );
+    assert_eq!(latest(&app).await.0, "v1");
+}
+
+#[tokio::test]
+async fn path_like_version() {
+    let temp = TempStorage::new("manifest_path_version");
+    let app = AquilaServer::default().build(temp.storage.clone(), writer());
+
+    for version in ["../escaped", "a/b", "a\\b", ".."] {
+        assert_eq!(
+            send(&app, publish(version)).await.status(),
+            StatusCode::BAD_REQUEST,
+            "{version}"
+        );
+    }
+    assert!(!temp.root.join("escaped").exists());
+    assert!(!temp.root.join("manifests").exists());
+}
+
+#[tokio::test]
+async fn unsupported_schema() {
+    let temp = TempStorage::new("manifest_schema");
+    let app = AquilaServer::default().build(temp.storage.clone(), writer());
+
+    let manifest = serde_json::json!({
+        "schema_version": 99,
+        "version": "v1",
+        "published_at": "2024-01-01T00:00:00Z",
+        "published_by": "test",
+        "assets": {}
+    });
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri("/manifest")
+        .header("Authorization", "Bearer writer")
+        .header("Content-Type", "application/json")
+        .body(Body::from(manifest.to_string()))
+        .unwrap();
+    let response = send(&app, request).await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let body = read_body(response).await;
+    assert!(String::from_utf8_lossy(&body).contains("schema version: 99"));
+}
+
+#[tokio::test]
+async fn interrupted_manifest_write() {
+    let temp = TempStorage::new("manifest_interrupted");
+    let storage = &temp.storage;
+    let app = AquilaServer::default().build(storage.clone(), writer());
+
+    assert_eq!(
+        send(&app, publish("v1")).await.status(),
+        StatusCode::CREATED
+    );
+
+    // The checksum of a second publish was written, the manifest itself wasn't.
+    let stored = storage.read_file("manifests/v1").await.unwrap();
+    let checksums = format!(
+        "{}\n{}",
+        "ab".repeat(32),
+        hex::encode(Sha256::digest(&stored))
+    );
+    storage
+        .put_object("checksums/manifests/v1", checksums.into())
+        .await
+        .unwrap();
+
+    let request = Request::builder()
+        .uri("/manifest/v1")
+        .header("Authorization", "Bearer writer")
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(send(&app, request).await.status(), StatusCode::OK);
+}
+
+/// Keeps the default [`StorageBackend::put_object`].
+#[derive(Clone)]
+struct NoObjects(FileSystemStorage);
+
+impl StorageBackend for NoObjects {
+    async fn write_blob(&self, hash: &str, data: Bytes) -> Result<bool, StorageError> {
+        self.0.write_blob(hash, data).await
+    }
+
+    async fn write_manifest(&self, version: &str, data: Bytes) -> Result<(), StorageError> {
+        self.0.write_manifest(version, data).await
+    }
+
+    async fn read_file(&self, path: &str) -> Result<Bytes, StorageError> {
+        self.0.read_file(path).await
+    }
+
+    async fn exists(&self, path: &str) -> Result<bool, StorageError> {
+        self.0.exists(path).await
+    }
+
+    async fn delete_file(&self, path: &str) -> Result<(), StorageError> {
+        self.0.delete_file(path).await
+    }
+}
+
+#[tokio::test]
+async fn publish_without_objects() {
+    let temp = TempStorage::new("manifest_no_objects");
+    let app = AquilaServer::default().build(NoObjects(temp.storage.clone()), writer());
+
+    assert_eq!(
+        send(&app, publish("v1")).await.status(),
+        StatusCode::CREATED
+    );
+    assert_eq!(latest(&app).await.0, "v1");
+    assert!(!temp.root.join("checksums").exists());
+}
@@ -0,0 +1,116 @@
+//! Asserts that admins can pin and unpin blobs.
+
+mod common;
+
+use aquila_auth_mock::MockAuth;
+use aquila_core::prelude::*;
+use aquila_server::prelude::*;
+use axum::Router;
+use axum::body::Body;
+use axum::http::{Method, Request, StatusCode};
+use common::{TempStorage, read_body, send};
+use std::time::Duration;
+
+async fn call(app: &Router, method: Method, uri: &str) -> (StatusCode, String) {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("Authorization", "Bearer admin")
+        .body(Body::empty())
+        .unwrap();
+    let response = send(app, request).await;
+    let status = response.status();
+    let body = read_body(response).await;
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+#[tokio::test]
+async fn pin_and_unpin() {
+    let temp = TempStorage::new("pins");
+    let storage = temp.storage.clone();
+    let hash = "ab".repeat(32);
+    storage.write_blob(&hash, "data".into()).await.unwrap();
+
+    let auth = MockAuth::default().with_token("admin", ["admin"]);
+    let app = AquilaServer::default().build(storage, auth);
+
+    let pin = format!("/admin/pins/{hash}");
+    assert_eq!(
+        call(&app, Method::POST, &pin).await.0,
+        StatusCode::NO_CONTENT
+    );
+    assert_eq!(
+        call(&app, Method::POST, &pin).await.0,
+        StatusCode::NO_CONTENT
+    );
+    let (status, body) = call(&app, Method::GET, "/admin/pins").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, format!("[\"{hash}\"]"));
+
+    let missing = format!("/admin/pins/{}", "cd".repeat(32));
+    assert_eq!(
+        call(&app, Method::POST, &missing).await.0,
+        StatusCode::NOT_FOUND
+    );
+    let invalid = "/admin/pins/pins.json";
+    assert_eq!(
+        call(&app, Method::POST, invalid).await.0,
+        StatusCode::BAD_REQUEST
+    );
+
+    assert_eq!(
+        call(&app, Method::DELETE, &pin).await.0,
+        StatusCode::NO_CONTENT
+    );
+    assert_eq!(call(&app, Method::GET, "/admin/pins").await.1, "[]");
+}
+
+#[tokio::test]
+async fn protected_blobs() {
+    let temp = TempStorage::new("pins_protected");
+    let storage = &temp.storage;
+    let hashes = ["aa", "bb", "cc", "dd"].map(|b| b.repeat(32));
+    let [older, old, recent, pinned] = hashes.each_ref().map(String::as_str);
+
+    // `v3` is published in the future, i.e. within any window.
+    for (version, published_at, hash) in [
+        ("v1", "2020-01-01T00:00:00Z", older),
+        ("v2", "2021-01-01T00:00:00Z", old),
+        ("v3", "2999-01-01T00:00:00Z", recent),
+    ] {
+        let manifest = serde_json::json!({
+            "version": version,
+            "published_at": published_at,
+            "published_by": "test",
+            "assets": {
+                "image.png": { "hash": hash, "size": 4, "mime_type": "image/png" }
+            }
+        });
+        storage
+            .write_manifest(version, manifest.to_string().into())
+            .await
+            .unwrap();
+    }
+    storage
+        .write_manifest("latest", "\"v3\"".into())
+        .await
+        .unwrap();
+    storage.write_blob(pinned, "data".into()).await.unwrap();
+
+    let auth = MockAuth::default().with_token("admin", ["admin"]);
+    let app = AquilaServer::default().build(storage.clone(), auth);
+    let pin = format!("/admin/pins/{pinned}");
+    assert_eq!(
+        call(&app, Method::POST, &pin).await.0,
+        StatusCode::NO_CONTENT
+    );
+
+    let day = Duration::from_secs(24 * 60 * 60);
+    let blobs = async |keep_last, window| {
+        let blobs = aquila_server::retention::protected_blobs(storage, keep_last, window).await;
+        blobs.unwrap().into_iter().collect::<Vec<_>>()
+    };
+    assert_eq!(blobs(0, day).await, [recent, pinned]);
+    assert_eq!(blobs(2, day).await, [old, recent, pinned]);
+    assert_eq!(blobs(0, Duration::MAX).await, hashes);
+}
@@ -0,0 +1,143 @@
+//! Asserts that blob references follow publishes and unpublishes.
+
+mod common;
+
+use aquila_core::prelude::*;
+use aquila_fs::FileSystemStorage;
+use aquila_server::prelude::*;
+use axum::Router;
+use axum::body::Body;
+use axum::http::{Method, Request, StatusCode};
+use bytes::Bytes;
+use common::{TempStorage, read_body, send, writer};
+
+async fn call(app: &Router, method: Method, uri: &str, body: Body) -> (StatusCode, String) {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("Authorization", "Bearer writer")
+        .header("Content-Type", "application/json")
+        .body(body)
+        .unwrap();
+    let response = send(app, request).await;
+    let status = response.status();
+    let body = read_body(response).await;
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+/// Publishes `version` referencing `hash`, without tagging it as `latest`.
+async fn publish(app: &Router, version: &str, hash: &str) {
+    let manifest = serde_json::json!({
+        "version": version,
+        "published_at": "2024-01-01T00:00:00Z",
+        "published_by": "test",
+        "assets": {
+            "image.png": { "hash": hash, "size": 4, "mime_type": "image/png" }
+        }
+    });
+    let body = Body::from(manifest.to_string());
+    let (status, _) = call(app, Method::POST, "/manifest?latest=false", body).await;
+    assert_eq!(status, StatusCode::CREATED);
+}
+
+async fn refs(app: &Router, hash: &str) -> String {
+    let uri = format!("/assets/{hash}/refs");
+    let (status, body) = call(app, Method::GET, &uri, Body::empty()).await;
+    assert_eq!(status, StatusCode::OK);
+    body
+}
+
+#[tokio::test]
+async fn refs_follow_manifests() {
+    let temp = TempStorage::new("refs");
+    let app = AquilaServer::default().build(temp.storage.clone(), writer());
+
+    let (shared, other) = ("ab".repeat(32), "cd".repeat(32));
+    // Built from the stored manifests on first use.
+    publish(&app, "v1", &shared).await;
+    assert_eq!(refs(&app, &shared).await, r#"["v1"]"#);
+
+    // Updated incrementally afterwards.
+    publish(&app, "v2", &shared).await;
+    publish(&app, "v3", &other).await;
+    assert_eq!(refs(&app, &shared).await, r#"["v1","v2"]"#);
+    assert_eq!(refs(&app, &other).await, r#"["v3"]"#);
+
+    let (status, _) = call(&app, Method::DELETE, "/manifest/v1", Body::empty()).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert_eq!(refs(&app, &shared).await, r#"["v2"]"#);
+    assert_eq!(refs(&app, &"ef".repeat(32)).await, "[]");
+
+    let (status, _) = call(&app, Method::GET, "/assets/refs/refs", Body::empty()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+/// A backend whose manifest writes fail.
+#[derive(Clone)]
+struct ReadOnlyManifests(FileSystemStorage);
+
+impl StorageBackend for ReadOnlyManifests {
+    async fn write_blob(&self, hash: &str, data: Bytes) -> Result<bool, StorageError> {
+        self.0.write_blob(hash, data).await
+    }
+
+    async fn put_object(&self, path: &str, data: Bytes) -> Result<(), StorageError> {
+        self.0.put_object(path, data).await
+    }
+
+    async fn write_manifest(&self, _version: &str, _data: Bytes) -> Result<(), StorageError> {
+        Err(StorageError::Generic("read only".into()))
+    }
+
+    async fn read_file(&self, path: &str) -> Result<Bytes, StorageError> {
+        self.0.read_file(path).await
+    }
+
+    async fn exists(&self, path: &str) -> Result<bool, StorageError> {
+        self.0.exists(path).await
+    }
+
+    fn capabilities(&self) -> StorageCapabilities {
+        self.0.capabilities()
+    }
+
+    async fn list_manifests(&self) -> Result<Vec<String>, StorageError> {
+        self.0.list_manifests().await
+    }
+
+    async fn delete_file(&self, path: &str) -> Result<(), StorageError> {
+        self.0.delete_file(path).await
+    }
+}
+
+#[tokio::test]
+async fn refs_indexed_at_startup() {
+    let temp = TempStorage::new("refs_startup");
+    let hash = "ab".repeat(32);
+    let manifest = serde_json::json!({
+        "version": "v1",
+        "published_at": "2024-01-01T00:00:00Z",
+        "published_by": "test",
+        "assets": {
+            "image.png": { "hash": hash, "size": 4, "mime_type": "image/png" }
+        }
+    });
+    temp.storage
+        .write_manifest("v1", manifest.to_string().into())
+        .await
+        .unwrap();
+
+    let storage = ReadOnlyManifests(temp.storage.clone());
+    let server = AquilaServer::default();
+    let warnings = server.preflight(&storage).await.unwrap();
+    assert!(!warnings.contains(&PreflightWarning::UnindexedReferences));
+    let app = server.build(storage, writer());
+
+    // The failed publish isn't indexed.
+    let mut manifest = manifest;
+    manifest["version"] = "v2".into();
+    let body = Body::from(manifest.to_string());
+    let (status, _) = call(&app, Method::POST, "/manifest?latest=false", body).await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(refs(&app, &hash).await, r#"["v1"]"#);
+}
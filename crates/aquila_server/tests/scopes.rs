@@ -0,0 +1,258 @@
+//! Asserts the status of every route for every kind of token.
+
+mod common;
+
+use aquila_auth_mock::MockAuth;
+use aquila_core::prelude::*;
+use aquila_server::prelude::*;
+use axum::Router;
+use axum::body::Body;
+use axum::http::{Method, Request, StatusCode};
+use bytes::Bytes;
+use common::{TempStorage, send};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+const BLOB: &[u8] = b"hello aquila";
+
+struct Route {
+    method: Method,
+    uri: String,
+    body: String,
+    /// `None` for public routes.
+    scope: Option<&'static str>,
+    success: StatusCode,
+}
+
+impl Route {
+    fn new(method: Method, uri: impl Into<String>, scope: Option<&'static str>) -> Self {
+        Self {
+            method,
+            uri: uri.into(),
+            body: String::new(),
+            scope,
+            success: StatusCode::OK,
+        }
+    }
+
+    fn body(mut self, body: impl Into<String>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    fn success(mut self, status: StatusCode) -> Self {
+        self.success = status;
+        self
+    }
+
+    /// The status a user with `scopes` should get, `None` if unauthenticated.
+    fn expected(&self, scopes: Option<&[&str]>) -> StatusCode {
+        let Some(required) = self.scope else {
+            return self.success;
+        };
+        match scopes {
+            None => StatusCode::UNAUTHORIZED,
+            Some(scopes) if scopes.iter().any(|s| *s == "admin" || *s == required) => self.success,
+            Some(_) => StatusCode::FORBIDDEN,
+        }
+    }
+}
+
+async fn setup(config: AquilaServerConfig, name: &str) -> (Router, String, TempStorage) {
+    let temp = TempStorage::new(name);
+    let storage = temp.storage.clone();
+
+    let hash = hex::encode(Sha256::digest(BLOB));
+    storage
+        .write_blob(&hash, Bytes::from_static(BLOB))
+        .await
+        .unwrap();
+
+    let manifest = AssetManifest {
+        version: "v1".into(),
+        published_by: "test".into(),
+        assets: HashMap::from([(
+            "hello.txt".to_string(),
+            AssetInfo {
+                hash: hash.clone(),
+                size: BLOB.len() as u64,
+                mime_type: None,
+            },
+        )]),
+        ..Default::default()
+    };
+    let data = Bytes::from(manifest.to_canonical_vec().unwrap());
+    storage.write_manifest("v1", data).await.unwrap();
+
+    let auth = MockAuth::default()
+        .with_token("reader", ["read"])
+        .with_token("writer", ["write"])
+        .with_token("admin", ["admin"]);
+
+    (AquilaServer::new(config).build(storage, auth), hash, temp)
+}
+
+fn routes(hash: &str) -> Vec<Route> {
+    let manifest = serde_json::json!({
+        "version": "v2",
+        "published_at": "2024-01-01T00:00:00Z",
+        "published_by": "test",
+        "assets": {
+            "hello.txt": { "hash": hash, "size": BLOB.len(), "mime_type": null }
+        }
+    });
+
+    vec![
+        Route::new(Method::GET, "/health", None),
+        Route::new(Method::GET, "/capabilities", None),
+        Route::new(Method::GET, format!("/assets/{hash}"), Some("read")),
+        Route::new(Method::GET, "/manifest/v1", Some("read")),
+        Route::new(Method::GET, "/manifest/v1/stats", Some("read")),
+        Route::new(Method::GET, "/manifest/v1/archive", Some("read")),
+        Route::new(Method::GET, "/manifests?limit=1", Some("read")),
+        Route::new(Method::POST, "/assets", Some("write")).body(String::from_utf8_lossy(BLOB)),
+        Route::new(Method::PUT, format!("/assets/stream/{hash}"), Some("write"))
+            .body(String::from_utf8_lossy(BLOB)),
+        Route::new(Method::POST, "/assets/exists", Some("write")).body(format!("[\"{hash}\"]")),
+        Route::new(Method::POST, "/manifest", Some("write"))
+            .body(manifest.to_string())
+            .success(StatusCode::CREATED),
+        Route::new(Method::PATCH, "/manifest/v1", Some("write")).body("{}"),
+        Route::new(Method::DELETE, "/manifest/missing", Some("write"))
+            .success(StatusCode::NOT_FOUND),
+        Route::new(Method::POST, "/auth/token", Some("write")).body(r#"{"subject":"test"}"#),
+        Route::new(Method::GET, "/admin/stats", Some("admin")),
+        Route::new(Method::GET, "/admin/blobs?limit=1", Some("admin")),
+    ]
+}
+
+#[tokio::test]
+async fn scope_matrix() {
+    let (app, hash, _temp) = setup(AquilaServerConfig::default(), "scopes").await;
+
+    let users: [(Option<&str>, Option<&[&str]>); 5] = [
+        (None, None),
+        (Some("unknown"), None),
+        (Some("reader"), Some(&["read"])),
+        (Some("writer"), Some(&["write"])),
+        (Some("admin"), Some(&["admin"])),
+    ];
+
+    for route in routes(&hash) {
+        for (token, scopes) in users {
+            let mut request = Request::builder()
+                .method(route.method.clone())
+                .uri(&route.uri)
+                .header("Content-Type", "application/json");
+            if let Some(token) = token {
+                request = request.header("Authorization", format!("Bearer {token}"));
+            }
+            let request = request.body(Body::from(route.body.clone())).unwrap();
+
+            let response = send(&app, request).await;
+            assert_eq!(
+                response.status(),
+                route.expected(scopes),
+                "{} {} with token {token:?}",
+                route.method,
+                route.uri,
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn anonymous_read() {
+    let config = AquilaServerConfig {
+        allow_anonymous_read: true,
+        ..Default::default()
+    };
+    let (app, hash, _temp) = setup(config, "anonymous").await;
+
+    for route in routes(&hash) {
+        let request = Request::builder()
+            .method(route.method.clone())
+            .uri(&route.uri)
+            .header("Content-Type", "application/json");
+
+        // Anonymous users only get `read`, everything else still requires a token.
+        let scopes: Option<&[&str]> = (route.scope == Some("read")).then_some(&["read"]);
+        let expected = route.expected(scopes);
+        let response = send(&app, request.body(Body::from(route.body.clone())).unwrap()).await;
+        assert_eq!(
+            response.status(),
+            expected,
+            "{} {}",
+            route.method,
+            route.uri
+        );
+    }
+
+    // A token that is sent must still be valid.
+    let request = Request::builder()
+        .uri("/manifest/v1")
+        .header("Authorization", "Bearer unknown")
+        .body(Body::empty())
+        .unwrap();
+    let response = send(&app, request).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn denied_scope() {
+    let temp = TempStorage::new("denied");
+    let config = AquilaServerConfig::default();
+    let idp = MockAuth::default()
+        .with_token("uploader", ["read", "write"])
+        .with_token("other", ["read", "write"]);
+    let auth = DenyListAuth::new(JWTServiceAuthProvider::new(config.jwt_service(), idp))
+        .with_denied("uploader", ["write"]);
+    let app = AquilaServer::new(config).build(temp.storage.clone(), auth);
+
+    let call = async |method: Method, uri: &str, token: &str| {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("Authorization", format!("Bearer {token}"))
+            .body(Body::from(BLOB))
+            .unwrap();
+        send(&app, request).await.status()
+    };
+
+    let status = call(Method::POST, "/assets", "uploader").await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let status = call(Method::GET, "/manifests", "uploader").await;
+    assert_eq!(status, StatusCode::OK);
+    let status = call(Method::POST, "/assets", "other").await;
+    assert_eq!(status, StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn mintable_scopes() {
+    let config = AquilaServerConfig {
+        mintable_scopes: vec!["read".into(), "deploy".into()],
+        ..Default::default()
+    };
+    let (app, _, _temp) = setup(config, "mintable").await;
+
+    // Without tenants, tokens can be minted for any subject, e.g. a service.
+    let mint = async |token: &str, scope: &str| {
+        let body = serde_json::json!({ "subject": "game_client_v1", "scopes": [scope] });
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/auth/token")
+            .header("Authorization", format!("Bearer {token}"))
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        send(&app, request).await.status()
+    };
+
+    assert_eq!(mint("writer", "deploy").await, StatusCode::OK);
+    assert_eq!(mint("writer", "billing").await, StatusCode::FORBIDDEN);
+    assert_eq!(mint("admin", "billing").await, StatusCode::OK);
+    for scope in ["admin", "write"] {
+        assert_eq!(mint("writer", scope).await, StatusCode::FORBIDDEN);
+        assert_eq!(mint("admin", scope).await, StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,98 @@
+//! Asserts that tenants stay apart and that the storage wide routes handle their data.
+
+mod common;
+
+use aquila_auth_mock::MockAuth;
+use aquila_server::prelude::*;
+use aquila_server::tenant::TenantStorage;
+use axum::Router;
+use axum::body::Body;
+use axum::http::{Method, Request, StatusCode};
+use common::{TempStorage, read_body, send};
+
+async fn call(
+    app: &Router,
+    method: Method,
+    uri: &str,
+    token: &str,
+    body: serde_json::Value,
+) -> (StatusCode, serde_json::Value) {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("Authorization", format!("Bearer {token}"))
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let response = send(app, request).await;
+    let status = response.status();
+    let body = read_body(response).await;
+    (status, serde_json::from_slice(&body).unwrap_or_default())
+}
+
+#[tokio::test]
+async fn cross_tenant_mint() {
+    let temp = TempStorage::new("tenants");
+    let config = AquilaServerConfig::default();
+    let idp = MockAuth::default()
+        .with_token("alice", ["read", "write"])
+        .with_token("bob", ["read", "write"]);
+    let auth = JWTServiceAuthProvider::new(config.jwt_service(), idp);
+    let storage = TenantStorage::new(temp.storage.clone());
+    let app = AquilaServer::new(config).build(storage, auth);
+
+    let manifest = serde_json::json!({
+        "version": "v1",
+        "published_at": "2024-01-01T00:00:00Z",
+        "published_by": "bob",
+        "assets": {}
+    });
+    let (status, _) = call(&app, Method::POST, "/manifest", "bob", manifest).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let mint = |body| call(&app, Method::POST, "/auth/token", "alice", body);
+    let (status, _) = mint(serde_json::json!({ "subject": "bob" })).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let request = serde_json::json!({ "subject": "alice", "scopes": ["read", "tenant:bob"] });
+    let (status, _) = mint(request).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, body) = mint(serde_json::json!({ "subject": "alice" })).await;
+    assert_eq!(status, StatusCode::OK);
+    let token = body["token"].as_str().unwrap();
+    let (status, _) = call(&app, Method::GET, "/manifest/v1", token, Default::default()).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = call(&app, Method::GET, "/manifest/v1", "bob", Default::default()).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn admin_stats() {
+    let temp = TempStorage::new("tenant_stats");
+    let auth = MockAuth::default()
+        .with_token("alice", ["read", "write"])
+        .with_token("bob", ["read", "write"])
+        .with_token("admin", ["admin"]);
+    let storage = TenantStorage::new(temp.storage.clone());
+    let app = AquilaServer::default().build(storage, auth);
+
+    for tenant in ["alice", "bob"] {
+        let manifest = serde_json::json!({
+            "version": "v1",
+            "published_at": "2024-01-01T00:00:00Z",
+            "published_by": tenant,
+            "assets": {
+                "a.png": { "hash": "ab".repeat(32), "size": 4, "mime_type": "image/png" }
+            }
+        });
+        let (status, _) = call(&app, Method::POST, "/manifest", tenant, manifest).await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    // Both tenants stored a `latest` pointer next to their manifest.
+    let empty = Default::default();
+    let (status, stats) = call(&app, Method::GET, "/admin/stats", "admin", empty).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(stats["manifest_count"], 2);
+    assert_eq!(stats["logical_bytes"], 8);
+}
@@ -0,0 +1,144 @@
+//! Asserts the tus protocol at `/files`: creation, offsets, completion and its error statuses.
+#![cfg(feature = "tus")]
+
+mod common;
+
+use aquila_server::prelude::*;
+use axum::Router;
+use axum::body::Body;
+use axum::http::{Method, Request, Response, StatusCode, header};
+use bytes::Bytes;
+use common::{TempStorage, read_body, send, writer};
+use futures::channel::mpsc;
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+
+fn request(method: Method, uri: &str) -> axum::http::request::Builder {
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("Authorization", "Bearer writer")
+        .header("Tus-Resumable", "1.0.0")
+}
+
+fn header<'a>(response: &'a Response<Body>, name: &str) -> &'a str {
+    response.headers()[name].to_str().unwrap()
+}
+
+/// Creates an upload of `length` bytes, returns its location.
+async fn create(app: &Router, length: u64) -> String {
+    let create = request(Method::POST, "/files")
+        .header("Upload-Length", length)
+        .body(Body::empty())
+        .unwrap();
+    let response = send(app, create).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    header(&response, "location").to_string()
+}
+
+fn patch(location: &str, offset: u64, body: impl Into<Body>) -> Request<Body> {
+    request(Method::PATCH, location)
+        .header(header::CONTENT_TYPE, "application/offset+octet-stream")
+        .header("Upload-Offset", offset)
+        .body(body.into())
+        .unwrap()
+}
+
+async fn offset(app: &Router, location: &str) -> String {
+    let response = send(
+        app,
+        request(Method::HEAD, location).body(Body::empty()).unwrap(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    header(&response, "upload-offset").to_string()
+}
+
+fn setup(name: &str) -> (Router, TempStorage) {
+    let temp = TempStorage::new(&format!("tus_{name}"));
+    let app = AquilaServer::default().build(temp.storage.clone(), writer());
+    (app, temp)
+}
+
+#[tokio::test]
+async fn resumable_upload() {
+    let (app, _temp) = setup("upload");
+    let location = create(&app, 10).await;
+    assert_eq!(offset(&app, &location).await, "0");
+
+    let response = send(&app, patch(&location, 0, "hello")).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(header(&response, "upload-offset"), "5");
+    assert_eq!(offset(&app, &location).await, "5");
+
+    let response = send(&app, patch(&location, 0, "hello")).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let response = send(&app, patch(&location, 5, "aquila")).await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(offset(&app, &location).await, "5");
+
+    let response = send(&app, patch(&location, 5, "world")).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let hash = hex::encode(Sha256::digest("helloworld"));
+    assert_eq!(header(&response, "x-content-sha256"), hash);
+
+    let download = request(Method::GET, &format!("/assets/{hash}"))
+        .body(Body::empty())
+        .unwrap();
+    let response = send(&app, download).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = read_body(response).await;
+    assert_eq!(body, "helloworld");
+
+    // Completed uploads are gone.
+    let head = request(Method::HEAD, &location)
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(send(&app, head).await.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn empty_upload() {
+    let (app, _temp) = setup("empty");
+    let location = create(&app, 0).await;
+    assert_eq!(offset(&app, &location).await, "0");
+
+    let response = send(&app, patch(&location, 0, Body::empty())).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let hash = hex::encode(Sha256::digest([]));
+    assert_eq!(header(&response, "x-content-sha256"), hash);
+}
+
+#[tokio::test]
+async fn locked_upload() {
+    let (app, _temp) = setup("locked");
+    let location = create(&app, 10).await;
+
+    // A `PATCH` whose client stalls after the first chunk.
+    let (mut tx, rx) = mpsc::channel::<Result<Bytes, std::io::Error>>(1);
+    tx.try_send(Ok(Bytes::from_static(b"hello"))).unwrap();
+    let stalled = tokio::spawn({
+        let app = app.clone();
+        let request = patch(&location, 0, Body::from_stream(rx));
+        async move { send(&app, request).await }
+    });
+
+    // Probes at a wrong offset, `409 Conflict` until the upload is locked.
+    let mut locked = false;
+    for _ in 0..100 {
+        if send(&app, patch(&location, 1, "ello")).await.status() == StatusCode::LOCKED {
+            locked = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(locked);
+
+    // The client disconnects, the upload can be resumed.
+    stalled.abort();
+    let _ = stalled.await;
+    drop(tx);
+    assert_eq!(offset(&app, &location).await, "0");
+    let response = send(&app, patch(&location, 0, "helloworld")).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+}
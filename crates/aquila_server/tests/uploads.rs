@@ -0,0 +1,159 @@
+//! Asserts how blob uploads are streamed, checked and limited.
+
+mod common;
+
+use aquila_auth_mock::MockAuth;
+use aquila_server::prelude::*;
+use axum::body::Body;
+use axum::http::{Method, Request, StatusCode, header};
+use bytes::Bytes;
+use common::{TempStorage, read_body, send};
+use futures::channel::mpsc;
+use futures::{SinkExt, stream};
+use sha2::{Digest, Sha256};
+use tower::ServiceExt;
+
+/// Lists all files below `dir`, recursively.
+fn files(dir: &std::path::Path) -> Vec<std::path::PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .flat_map(|entry| match entry.file_type() {
+            Ok(kind) if kind.is_dir() => files(&entry.path()),
+            _ => vec![entry.path()],
+        })
+        .collect()
+}
+
+/// A streamed upload of `hash` with `body`.
+fn upload(hash: &str, body: Body) -> Request<Body> {
+    Request::builder()
+        .method(Method::PUT)
+        .uri(format!("/assets/stream/{hash}"))
+        .header("Authorization", "Bearer writer")
+        .body(body)
+        .unwrap()
+}
+
+#[tokio::test]
+async fn aborted_stream_upload() {
+    let temp = TempStorage::new("aborted");
+    let auth = MockAuth::default().with_token("writer", ["write"]);
+    let app = AquilaServer::default().build(temp.storage.clone(), auth);
+
+    // The client sends the first chunk, then disconnects.
+    let body = stream::iter([
+        Ok(Bytes::from_static(b"partial")),
+        Err(std::io::Error::from(std::io::ErrorKind::ConnectionReset)),
+    ]);
+    let response = send(&app, upload(&"ab".repeat(32), Body::from_stream(body))).await;
+    assert!(response.status().is_server_error());
+    assert_eq!(files(&temp.root), Vec::<std::path::PathBuf>::new());
+}
+
+#[tokio::test]
+async fn content_length_mismatch() {
+    let temp = TempStorage::new("length");
+    let config = AquilaServerConfig {
+        stream_upload_threshold: 0,
+        verify_uploads: false,
+        ..Default::default()
+    };
+    let auth = MockAuth::default().with_token("writer", ["write"]);
+    let app = AquilaServer::new(config).build(temp.storage.clone(), auth);
+
+    let hash = hex::encode(Sha256::digest(b"data"));
+    let sized = |len: usize| {
+        let mut request = upload(
+            &hash,
+            Body::from_stream(stream::iter([Ok::<_, std::io::Error>(Bytes::from_static(
+                b"data",
+            ))])),
+        );
+        request
+            .headers_mut()
+            .insert(header::CONTENT_LENGTH, len.into());
+        request
+    };
+
+    for len in [3, 5] {
+        let response = send(&app, sized(len)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(files(&temp.root), Vec::<std::path::PathBuf>::new());
+    }
+
+    let response = send(&app, sized(4)).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn streamed_upload_without_hash() {
+    let temp = TempStorage::new("unhashed");
+    let config = AquilaServerConfig {
+        stream_upload_threshold: 0,
+        ..Default::default()
+    };
+    let auth = MockAuth::default().with_token("writer", ["write"]);
+    let app = AquilaServer::new(config).build(temp.storage.clone(), auth);
+
+    let hash = hex::encode(Sha256::digest(b"data"));
+    let post = |len: Option<usize>| {
+        let chunks = [b"da", b"ta"].map(|chunk| Ok::<_, std::io::Error>(Bytes::from_static(chunk)));
+        let mut request = Request::builder()
+            .method(Method::POST)
+            .uri("/assets")
+            .header("Authorization", "Bearer writer");
+        if let Some(len) = len {
+            request = request.header(header::CONTENT_LENGTH, len);
+        }
+        request
+            .body(Body::from_stream(stream::iter(chunks)))
+            .unwrap()
+    };
+
+    let response = send(&app, post(Some(5))).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(files(&temp.root), Vec::<std::path::PathBuf>::new());
+
+    for (len, status) in [(None, StatusCode::CREATED), (Some(4), StatusCode::OK)] {
+        let response = send(&app, post(len)).await;
+        assert_eq!(response.status(), status);
+        let body = read_body(response).await;
+        assert_eq!(body, hash.as_bytes());
+    }
+}
+
+#[tokio::test]
+async fn concurrent_upload_limit() {
+    let temp = TempStorage::new("limit");
+    let config = AquilaServerConfig {
+        max_concurrent_uploads: Some(1),
+        ..Default::default()
+    };
+    let auth = MockAuth::default().with_token("writer", ["write"]);
+    let app = AquilaServer::new(config).build(temp.storage.clone(), auth);
+
+    // The first upload holds the only slot until its body ends. Once the second chunk is
+    // accepted, its handler is reading the body.
+    let (mut sender, receiver) = mpsc::channel::<Result<Bytes, std::io::Error>>(0);
+    let first = tokio::spawn(
+        app.clone()
+            .oneshot(upload(&"ab".repeat(32), Body::from_stream(receiver))),
+    );
+    for chunk in ["a", "b"] {
+        sender.send(Ok(Bytes::from(chunk))).await.unwrap();
+    }
+
+    let hash = hex::encode(Sha256::digest(b"data"));
+    let response = send(&app, upload(&hash, Body::from("data"))).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert!(response.headers().contains_key(header::RETRY_AFTER));
+
+    drop(sender);
+    first.await.unwrap().unwrap();
+
+    let response = send(&app, upload(&hash, Body::from("data"))).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+}
@@ -0,0 +1,178 @@
+//! # Aquila Server
+//!
+//! A standalone server, so deployments don't have to copy an example.
+//!
+//! ## Installation
+//!
+//! From source:
+//! ```bash
+//! cargo install --path crates/aquila_server_bin
+//! ```
+//!
+//! ## Configuration
+//!
+//! The backends are read from `--config` or `AQUILA_CONFIG`, see `aquila::factory`.
+//! Without a config file, assets are stored in `--data-dir` and a static `--admin-token`
+//! is accepted. The server itself reads the `AQUILA_*` variables, see
+//! `AquilaServerConfig::from_env`. It refuses to start without `AQUILA_JWT_SECRET`.
+//!
+//! With the `tls` feature, `--tls-cert` and `--tls-key` serve HTTPS.
+
+#[cfg(feature = "tls")]
+mod tls;
+
+use anyhow::Context;
+use aquila::factory::{AuthConfig, FactoryConfig, ServerFactory, StorageConfig};
+use aquila::prelude::*;
+use clap::Parser;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use tracing::{info, warn};
+
+#[derive(Parser)]
+#[command(name = "aquila-server")]
+#[command(about = "Aquila Asset Server")]
+struct Args {
+    /// Config file of the storage backend and auth provider
+    #[arg(short, long, env = "AQUILA_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Address to listen on
+    #[arg(short, long, env = "AQUILA_ADDR", default_value = "0.0.0.0:3000")]
+    addr: String,
+
+    /// Storage directory, if no config file is given
+    #[arg(long, env = "AQUILA_DATA_DIR", default_value = "./aquila_data")]
+    data_dir: PathBuf,
+
+    /// Token granting all scopes, if no config file is given
+    #[arg(long, env = "AQUILA_ADMIN_TOKEN", hide_env_values = true)]
+    admin_token: Option<String>,
+
+    /// PEM certificate chain, serves HTTPS
+    #[cfg(feature = "tls")]
+    #[arg(long, env = "AQUILA_TLS_CERT", requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM private key of the certificate
+    #[cfg(feature = "tls")]
+    #[arg(long, env = "AQUILA_TLS_KEY", requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+}
+
+impl Args {
+    /// The config file, or filesystem storage and the admin token.
+    fn factory_config(&self) -> anyhow::Result<FactoryConfig> {
+        if let Some(path) = &self.config {
+            return FactoryConfig::from_file(path).with_context(|| path.display().to_string());
+        }
+
+        let mut storage = toml::Table::new();
+        let data_dir = self.data_dir.to_string_lossy().into_owned();
+        storage.insert("path".into(), data_dir.into());
+
+        let mut users = toml::Table::new();
+        match &self.admin_token {
+            Some(token) => {
+                let mut admin = toml::Table::new();
+                admin.insert("token".into(), token.clone().into());
+                let scopes = ["admin", "read", "write"].map(toml::Value::from);
+                admin.insert("scopes".into(), scopes.to_vec().into());
+                users.insert("admin".into(), admin.into());
+            }
+            None => warn!("No AQUILA_ADMIN_TOKEN or config file, only minted tokens are accepted"),
+        }
+        let mut auth = toml::Table::new();
+        auth.insert("users".into(), users.into());
+
+        Ok(FactoryConfig {
+            storage: StorageConfig {
+                backend: "fs".into(),
+                options: storage,
+            },
+            auth: AuthConfig {
+                provider: "static".into(),
+                options: auth,
+            },
+        })
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+    let args = Args::parse();
+
+    // Config
+    let config = args.factory_config()?;
+    let server_config = AquilaServerConfig::from_env()?;
+
+    // Providers
+    let backends = ServerFactory::default()
+        .build(&config, &server_config)
+        .await?;
+
+    // Check
+    let server = AquilaServer::new(server_config);
+    for warning in server.preflight(&backends.storage).await? {
+        // Anyone could mint tokens with the public default.
+        if warning == PreflightWarning::DefaultJwtSecret {
+            anyhow::bail!("Set AQUILA_JWT_SECRET, e.g. to the output of `aquila generate-secret`");
+        }
+        warn!("{warning}");
+    }
+
+    // Build
+    let app = server.build(backends.storage, backends.auth);
+
+    // Serve
+    let listener = tokio::net::TcpListener::bind(&args.addr)
+        .await
+        .with_context(|| format!("Failed to bind {}", args.addr))?;
+
+    #[cfg(feature = "tls")]
+    if let (Some(cert), Some(key)) = (&args.tls_cert, &args.tls_key) {
+        info!("Server listening on https://{}", args.addr);
+        return tls::serve(listener, app, cert, key, shutdown_signal()).await;
+    }
+
+    info!("Server listening on http://{}", args.addr);
+    // The client address is needed by `write_ip_filter`.
+    let app = app.into_make_service_with_connect_info::<SocketAddr>();
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal())
+        .await?;
+    Ok(())
+}
+
+/// Resolves on `Ctrl+C` or `SIGTERM`, after which open requests are finished.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!("Failed to listen for Ctrl+C: {e}");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                warn!("Failed to listen for SIGTERM: {e}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+    info!("Shutting down");
+}
@@ -0,0 +1,165 @@
+//! # Aquila Throttle
+//! [![Crates.io](https://img.shields.io/crates/v/aquila_throttle.svg)](https://crates.io/crates/aquila_throttle)
+//! [![Downloads](https://img.shields.io/crates/d/aquila_throttle.svg)](https://crates.io/crates/aquila_throttle)
+//! [![Docs](https://docs.rs/aquila_throttle/badge.svg)](https://docs.rs/aquila_throttle/)
+//!
+//! A concurrency limiter for any [`StorageBackend`].
+//!
+//! [`ThrottledStorage`] wraps another backend and allows at most a fixed number of reads and
+//! writes to run at the same time, the rest wait in line until a slot frees up.
+//! This smooths out bursts of uploads, e.g. to avoid being throttled by S3 or running out of
+//! file descriptors on the file system.
+//!
+//! Metadata operations like [`StorageBackend::exists`] and listing are not limited.
+//!
+//! ## Usage
+//!
+//! ```no_run
+//! # use aquila_throttle::ThrottledStorage;
+//! # use aquila_core::prelude::*;
+//! # fn run<S: StorageBackend>(inner: S) {
+//! // At most 32 reads and writes at a time
+//! let storage = ThrottledStorage::new(inner, 32);
+//! # }
+//! ```
+
+use aquila_core::prelude::*;
+use bytes::Bytes;
+use futures::Stream;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+#[derive(Clone)]
+pub struct ThrottledStorage<S: StorageBackend> {
+    inner: S,
+    /// Shared by all clones, so the limit applies to the whole server.
+    permits: Arc<Semaphore>,
+}
+
+impl<S: StorageBackend> ThrottledStorage<S> {
+    /// Wraps `inner`, allowing at most `max_concurrent` reads and writes at a time.
+    ///
+    /// A limit of `0` is raised to `1`.
+    pub fn new(inner: S, max_concurrent: usize) -> Self {
+        Self {
+            inner,
+            permits: Arc::new(Semaphore::new(max_concurrent.max(1))),
+        }
+    }
+
+    /// Private helper to wait for a free slot.
+    async fn acquire(&self) -> Result<SemaphorePermit<'_>, StorageError> {
+        self.permits
+            .acquire()
+            .await
+            .map_err(|e| StorageError::Generic(format!("Throttle error: {e}")))
+    }
+}
+
+impl<S: StorageBackend> StorageBackend for ThrottledStorage<S> {
+    async fn write_blob(&self, hash: &str, data: Bytes) -> Result<bool, StorageError> {
+        let _permit = self.acquire().await?;
+        self.inner.write_blob(hash, data).await
+    }
+
+    async fn write_stream(
+        &self,
+        hash: &str,
+        stream: Pin<Box<dyn Stream<Item = Result<Bytes, std::io::Error>> + Send>>,
+        content_length: Option<u64>,
+    ) -> Result<bool, StorageError> {
+        let _permit = self.acquire().await?;
+        self.inner.write_stream(hash, stream, content_length).await
+    }
+
+    async fn put_object(&self, path: &str, data: Bytes) -> Result<(), StorageError> {
+        let _permit = self.acquire().await?;
+        self.inner.put_object(path, data).await
+    }
+
+    async fn write_manifest(&self, version: &str, data: Bytes) -> Result<(), StorageError> {
+        let _permit = self.acquire().await?;
+        self.inner.write_manifest(version, data).await
+    }
+
+    async fn read_file(&self, path: &str) -> Result<Bytes, StorageError> {
+        let _permit = self.acquire().await?;
+        self.inner.read_file(path).await
+    }
+
+    async fn exists(&self, path: &str) -> Result<bool, StorageError> {
+        self.inner.exists(path).await
+    }
+
+    async fn stat(&self, path: &str) -> Result<ObjectMeta, StorageError> {
+        self.inner.stat(path).await
+    }
+
+    async fn exists_many(&self, paths: &[String]) -> Result<Vec<bool>, StorageError> {
+        self.inner.exists_many(paths).await
+    }
+
+    fn get_manifest_path(&self, version: &str) -> String {
+        self.inner.get_manifest_path(version)
+    }
+
+    async fn get_download_url(&self, path: &str) -> Result<Option<String>, StorageError> {
+        self.inner.get_download_url(path).await
+    }
+
+    async fn get_download_url_as(
+        &self,
+        path: &str,
+        filename: &str,
+    ) -> Result<Option<String>, StorageError> {
+        self.inner.get_download_url_as(path, filename).await
+    }
+
+    async fn get_download_url_unchecked(
+        &self,
+        path: &str,
+        filename: Option<&str>,
+    ) -> Result<Option<String>, StorageError> {
+        self.inner.get_download_url_unchecked(path, filename).await
+    }
+
+    fn capabilities(&self) -> StorageCapabilities {
+        self.inner.capabilities()
+    }
+
+    async fn list_blobs(&self) -> Result<Vec<BlobInfo>, StorageError> {
+        self.inner.list_blobs().await
+    }
+
+    async fn list_manifests(&self) -> Result<Vec<String>, StorageError> {
+        self.inner.list_manifests().await
+    }
+
+    async fn list_blobs_page(
+        &self,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<Page<BlobInfo>, StorageError> {
+        self.inner.list_blobs_page(cursor, limit).await
+    }
+
+    async fn list_manifests_page(
+        &self,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<Page<String>, StorageError> {
+        self.inner.list_manifests_page(cursor, limit).await
+    }
+
+    async fn delete_file(&self, path: &str) -> Result<(), StorageError> {
+        self.inner.delete_file(path).await
+    }
+
+    fn with_context(&self, ctx: &RequestContext) -> Self {
+        Self {
+            inner: self.inner.with_context(ctx),
+            permits: self.permits.clone(),
+        }
+    }
+}
@@ -0,0 +1,67 @@
+//! # Configured Server Example
+//!
+//! Showcases a server whose backends are picked by a config file, see [`aquila::factory`].
+//!
+//! ## Requirements
+//!
+//! Create `aquila.toml`, or point `AQUILA_CONFIG` to another file:
+//!
+//! ```toml
+//! [storage]
+//! backend = "fs"
+//! path = "./aquila_data"
+//!
+//! [auth]
+//! provider = "mock"
+//! tokens = { "${AQUILA_ADMIN_TOKEN}" = ["admin", "read", "write"] }
+//! ```
+//!
+//! The server itself reads the `AQUILA_*` variables, see `AquilaServerConfig::from_env`.
+//!
+//! ## Usage
+//!
+//! ```sh
+//! cargo run --example configured_server --features "factory fs mock_auth"
+//! ```
+
+use aquila::factory::{FactoryConfig, ServerFactory};
+use aquila::prelude::*;
+use std::env;
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt::init();
+
+    // Config
+    let path = env::var("AQUILA_CONFIG").unwrap_or_else(|_| "aquila.toml".to_string());
+    let config = FactoryConfig::from_file(&path).unwrap_or_else(|e| panic!("{path}: {e}"));
+    let server_config = AquilaServerConfig::from_env().expect("Invalid AQUILA_* configuration");
+
+    // Providers
+    let backends = ServerFactory::default()
+        .build(&config, &server_config)
+        .await
+        .unwrap_or_else(|e| panic!("{path}: {e}"));
+
+    // Check
+    let server = AquilaServer::new(server_config);
+    let warnings = server
+        .preflight(&backends.storage)
+        .await
+        .expect("Invalid configuration");
+    for warning in warnings {
+        eprintln!("Warning: {warning}");
+    }
+
+    // Build
+    let app = server.build(backends.storage, backends.auth);
+
+    // Serve
+    let port = env::var("PORT").unwrap_or_else(|_| "3000".to_string());
+    let addr = format!("0.0.0.0:{port}");
+    println!("Server listening on http://{addr}");
+    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+    // The client address is needed by `write_ip_filter`.
+    let app = app.into_make_service_with_connect_info::<std::net::SocketAddr>();
+    axum::serve(listener, app).await.unwrap();
+}
@@ -0,0 +1,26 @@
+//! # Export Schema Example
+//!
+//! Writes the JSON Schemas of the protocol types to a directory,
+//! e.g. to generate types for a C# or TypeScript client.
+//!
+//! ## Usage
+//!
+//! ```sh
+//! cargo run --example export_schema --features "schema" -- ./schemas
+//! ```
+
+use aquila::schema::schemas;
+use std::path::PathBuf;
+
+fn main() -> anyhow::Result<()> {
+    let dir = PathBuf::from(std::env::args().nth(1).unwrap_or_else(|| "schemas".into()));
+    std::fs::create_dir_all(&dir)?;
+
+    for (name, schema) in schemas() {
+        let path = dir.join(format!("{name}.schema.json"));
+        std::fs::write(&path, serde_json::to_string_pretty(&schema)?)?;
+        println!("Wrote {}", path.display());
+    }
+
+    Ok(())
+}
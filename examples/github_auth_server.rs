@@ -9,6 +9,11 @@
 //! - `GITHUB_CLIENT_ID`
 //! - `GITHUB_CLIENT_SECRET`
 //!
+//! Optionally set `AQUILA_SIGNING_KEY` to sign published manifests (see `aquila generate-signing-key`).
+//!
+//! Optionally set `AQUILA_GITHUB_SCOPES` to grant scopes by organization or team,
+//! e.g. `MyGameStudio=read, MyGameStudio/artists=read write`.
+//!
 //! ## Usage
 //!
 //! ```sh
@@ -17,6 +22,7 @@
 
 use aquila::prelude::*;
 use std::env;
+use std::sync::Arc;
 
 #[tokio::main]
 async fn main() {
@@ -24,13 +30,18 @@ async fn main() {
 
     // Config
     let required_org = env::var("AQUILA_GITHUB_ORG").ok();
+    let scope_mapper = env::var("AQUILA_GITHUB_SCOPES").ok().map(|scopes| {
+        let table: ScopeTable = scopes.parse().expect("Invalid AQUILA_GITHUB_SCOPES");
+        Arc::new(table) as Arc<dyn ScopeMapper>
+    });
 
-    // In Production this should be a long, random string generated and set by you.
+    // Reads e.g. `AQUILA_JWT_SECRET` and `AQUILA_SIGNING_KEY`, see `AquilaServerConfig::from_env`.
+    // In Production the secret should be a long, random string generated and set by you.
     // For this example, fall back to "TOP_SECRET" (the default) if none is provided.
-    let jwt_secret = env::var("AQUILA_JWT_SECRET").unwrap_or("TOP_SECRET".to_string());
+    let config = AquilaServerConfig::from_env().expect("Invalid AQUILA_* configuration");
 
-    // Must match the callback route in the GitHub app and the server config callback, see below.
-    let redirect_uri = "http://localhost:3000/auth/callback".to_string();
+    // Must match the callback route in the GitHub app and `AQUILA_CALLBACK` (default: `/auth/callback`).
+    let redirect_uri = format!("http://localhost:3000{}", config.callback);
     let gh_cfg = env::var("GITHUB_CLIENT_ID")
         .and_then(|client_id| {
             env::var("GITHUB_CLIENT_SECRET").map(|client_secret| GithubConfig {
@@ -38,6 +49,7 @@ async fn main() {
                 client_id,
                 client_secret,
                 required_org,
+                scope_mapper,
             })
         })
         .ok();
@@ -45,21 +57,27 @@ async fn main() {
     // Providers
     let storage = FileSystemStorage::new("./aquila_data");
     let gh_auth = GithubAuthProvider::new(gh_cfg);
-    let jwt_service = JwtService::new(&jwt_secret);
-    let auth = JWTServiceAuthProvider::new(jwt_service, gh_auth);
+    let auth = JWTServiceAuthProvider::new(config.jwt_service(), gh_auth);
+
+    // Check
+    let server = AquilaServer::new(config);
+    let warnings = server
+        .preflight(&storage)
+        .await
+        .expect("Invalid configuration");
+    for warning in warnings {
+        eprintln!("Warning: {warning}");
+    }
 
     // Build
-    let app = AquilaServer::new(AquilaServerConfig {
-        jwt_secret,
-        // this is the default but just to be explicit, see above.
-        callback: "/auth/callback".to_string(),
-    })
-    .build(storage, auth);
+    let app = server.build(storage, auth);
 
     // Serve
     let port = env::var("PORT").unwrap_or_else(|_| "3000".to_string());
     let addr = format!("0.0.0.0:{port}");
     println!("Server listening on http://{addr}");
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+    // The client address is needed by `write_ip_filter`.
+    let app = app.into_make_service_with_connect_info::<std::net::SocketAddr>();
     axum::serve(listener, app).await.unwrap();
 }
@@ -30,10 +30,14 @@ async fn main() {
     let bucket_name = env::var("S3_BUCKET").expect("S3_BUCKET env var required");
 
     // Providers
-    let storage = S3Storage::new(s3_client, bucket_name)
+    let mut storage = S3Storage::new(s3_client, bucket_name)
         .with_prefix("assets/v1/")
         .with_presigning(Duration::from_secs(300));
 
+    if let Some(provider) = aws_config.credentials_provider() {
+        storage = storage.with_credentials_provider(provider);
+    }
+
     // Don't use this in production! This is just for demonstration/testing purposes
     let auth = AllowAllAuth; // e.g., use GithubAuthProvider instead
 
@@ -36,6 +36,7 @@ async fn main() -> anyhow::Result<()> {
         published_at: chrono::Utc::now(),
         published_by: "simple_client_example".to_string(),
         assets,
+        ..Default::default()
     };
 
     // Publish the Manifest
@@ -10,6 +10,7 @@
 
 use aquila::prelude::*;
 use std::env;
+use std::time::Duration;
 
 #[tokio::main]
 async fn main() {
@@ -18,6 +19,11 @@ async fn main() {
     // Providers
     let storage = FileSystemStorage::new("./aquila_data");
 
+    // Remove temp files left behind by interrupted uploads
+    if let Err(e) = storage.cleanup_temp(Duration::from_secs(60 * 60)).await {
+        eprintln!("Failed to clean up temp files: {e}");
+    }
+
     // Don't use this in production! This is just for demonstration/testing purposes
     let auth = AllowAllAuth; // e.g., use GithubAuthProvider instead
 
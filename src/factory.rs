@@ -0,0 +1,511 @@
+//! Builds the backends of a server from a config file, so deployments don't need to write Rust.
+//!
+//! The file picks a storage backend and an auth provider by name, the other keys of each
+//! section are passed to it. `${NAME}` in string values is replaced with the environment
+//! variable `NAME`, e.g. for secrets:
+//!
+//! ```toml
+//! [storage]
+//! backend = "s3"
+//! bucket = "my-game-assets"
+//! prefix = "production/"
+//! presign_secs = 300
+//!
+//! [auth]
+//! provider = "github"
+//! client_id = "${GITHUB_CLIENT_ID}"
+//! client_secret = "${GITHUB_CLIENT_SECRET}"
+//! redirect_uri = "https://assets.example.com/auth/callback"
+//! scopes = "MyGameStudio=read, MyGameStudio/artists=read write"
+//! ```
+//!
+//! Backends of enabled features are registered by default:
+//!
+//! | Name | Feature | Options |
+//! |------|---------|---------|
+//! | `fs` | `fs` | `path` |
+//! | `s3` | `s3` | `bucket`, `prefix`, `presign_secs` |
+//! | `gcs` | `gcs` | `bucket`, `prefix`, `presign_secs` |
+//! | `github` | `github_auth` | `client_id`, `client_secret`, `redirect_uri`, `required_org`, `scopes` |
+//! | `static` | | `users`, a table of user id to `token` and `scopes`, see [`StaticTokenAuth`] |
+//! | `mock` | `mock_auth` | `tokens`, a table of token to scopes |
+//! | `allow_all` | `mock_auth` | none, **dev only** |
+//!
+//! Others can be added with [`ServerFactory::with_storage`] and [`ServerFactory::with_auth`].
+//! The server itself is configured with `AQUILA_*` variables, see
+//! [`AquilaServerConfig::from_env`]:
+//!
+//! ```no_run
+//! use aquila::factory::{FactoryConfig, ServerFactory};
+//! use aquila::prelude::*;
+//!
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let config = FactoryConfig::from_file("aquila.toml")?;
+//! let server_config = AquilaServerConfig::from_env()?;
+//!
+//! let backends = ServerFactory::default().build(&config, &server_config).await?;
+//! let app = AquilaServer::new(server_config).build(backends.storage, backends.auth);
+//! # Ok(())
+//! # }
+//! ```
+
+use aquila_core::dynamic::{BoxedAuthProvider, BoxedStorage};
+use aquila_core::error::StorageError;
+use aquila_server::prelude::*;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::pin::Pin;
+use std::str::FromStr;
+
+/// The parsed config file, see the [module docs](self).
+///
+/// ```
+/// use aquila::factory::{FactoryConfig, FactoryError, ServerFactory};
+/// use aquila::prelude::*;
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let config: FactoryConfig = r#"
+///     [storage]
+///     backend = "fs"
+///     path = "./aquila_data"
+///
+///     [auth]
+///     provider = "mock"
+///     tokens = { admin = ["admin"] }
+/// "#
+/// .parse()
+/// .unwrap();
+/// assert_eq!(config.storage.backend, "fs");
+///
+/// // Unknown names are rejected before anything is created.
+/// let server = AquilaServerConfig::default();
+/// let result = ServerFactory::empty().build(&config, &server).await;
+/// assert!(matches!(result, Err(FactoryError::Unknown { .. })));
+///
+/// let missing = "[storage]\nbackend = \"${AQUILA_UNSET_VAR}\"".parse::<FactoryConfig>();
+/// assert!(matches!(missing, Err(FactoryError::MissingEnv(_))));
+///
+/// // Placeholders are only expanded in values, which can contain anything.
+/// # unsafe { std::env::set_var("AQUILA_DOCTEST_PATH", "a\"b\\c\nd") };
+/// let config: FactoryConfig = r#"
+///     ## Not expanded: ${AQUILA_UNSET_VAR}
+///     [storage]
+///     backend = "fs"
+///     path = "${AQUILA_DOCTEST_PATH}"
+///
+///     [auth]
+///     provider = "mock"
+///     tokens = {}
+/// "#
+/// .parse()
+/// .unwrap();
+/// assert_eq!(config.storage.options["path"].as_str(), Some("a\"b\\c\nd"));
+/// # }
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FactoryConfig {
+    pub storage: StorageConfig,
+    pub auth: AuthConfig,
+}
+
+/// The `[storage]` section.
+#[derive(Debug, Clone, Deserialize)]
+pub struct StorageConfig {
+    /// The registered name of the backend, e.g. `s3`.
+    pub backend: String,
+
+    /// The remaining keys, passed to the backend.
+    #[serde(flatten)]
+    pub options: toml::Table,
+}
+
+/// The `[auth]` section.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AuthConfig {
+    /// The registered name of the provider, e.g. `github`.
+    pub provider: String,
+
+    /// The remaining keys, passed to the provider.
+    #[serde(flatten)]
+    pub options: toml::Table,
+}
+
+impl FactoryConfig {
+    /// Reads and parses a config file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, FactoryError> {
+        std::fs::read_to_string(path)?.parse()
+    }
+}
+
+impl FromStr for FactoryConfig {
+    type Err = FactoryError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut table: toml::Table =
+            toml::from_str(value).map_err(|e| FactoryError::Parse(e.to_string()))?;
+        for (_, value) in table.iter_mut() {
+            expand_values(value)?;
+        }
+        toml::Value::Table(table)
+            .try_into()
+            .map_err(|e| FactoryError::Parse(e.to_string()))
+    }
+}
+
+/// Expands the placeholders of all strings in `value`, after parsing, so substituted values
+/// can't change the structure of the config.
+fn expand_values(value: &mut toml::Value) -> Result<(), FactoryError> {
+    match value {
+        toml::Value::String(s) => *s = expand_env(s)?,
+        toml::Value::Array(values) => values.iter_mut().try_for_each(expand_values)?,
+        toml::Value::Table(table) => table
+            .iter_mut()
+            .try_for_each(|(_, value)| expand_values(value))?,
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Replaces `${NAME}` with the environment variable `NAME`, failing if it is unset.
+fn expand_env(value: &str) -> Result<String, FactoryError> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        let name = &rest[start + 2..start + len];
+        let var = std::env::var(name).map_err(|_| FactoryError::MissingEnv(name.to_string()))?;
+
+        expanded.push_str(&rest[..start]);
+        expanded.push_str(&var);
+        rest = &rest[start + len + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// An error building the backends, see [`ServerFactory::build`].
+#[derive(Debug)]
+pub enum FactoryError {
+    Io(std::io::Error),
+    /// The config file is not valid TOML or misses a section.
+    Parse(String),
+    /// A `${NAME}` placeholder refers to an unset environment variable.
+    MissingEnv(String),
+    /// No backend or provider is registered under the name.
+    Unknown {
+        kind: &'static str,
+        name: String,
+        known: Vec<String>,
+    },
+    /// The options of a backend or provider are invalid.
+    Options {
+        name: String,
+        message: String,
+    },
+    /// The storage backend couldn't be created, e.g. without credentials.
+    Storage(StorageError),
+}
+
+impl std::fmt::Display for FactoryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "Failed to read the config: {e}"),
+            Self::Parse(message) => write!(f, "Invalid config: {message}"),
+            Self::MissingEnv(name) => write!(f, "Environment variable {name} is not set"),
+            Self::Unknown { kind, name, known } => write!(
+                f,
+                "Unknown {kind} '{name}', expected one of: {}. Is its feature enabled?",
+                known.join(", ")
+            ),
+            Self::Options { name, message } => write!(f, "Invalid options for '{name}': {message}"),
+            Self::Storage(e) => write!(f, "Failed to create the storage backend: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for FactoryError {}
+
+impl From<std::io::Error> for FactoryError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<StorageError> for FactoryError {
+    fn from(e: StorageError) -> Self {
+        Self::Storage(e)
+    }
+}
+
+/// Deserializes the options of the backend or provider `name`.
+pub fn parse_options<T: DeserializeOwned>(
+    name: &str,
+    options: toml::Table,
+) -> Result<T, FactoryError> {
+    toml::Value::Table(options)
+        .try_into()
+        .map_err(|e| FactoryError::Options {
+            name: name.to_string(),
+            message: e.to_string(),
+        })
+}
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+type StorageConstructor =
+    Box<dyn Fn(toml::Table) -> BoxFuture<Result<BoxedStorage, FactoryError>> + Send + Sync>;
+type AuthConstructor =
+    Box<dyn Fn(toml::Table) -> Result<BoxedAuthProvider, FactoryError> + Send + Sync>;
+
+/// The backends built by [`ServerFactory::build`], ready for [`AquilaServer::build`].
+pub struct Backends {
+    pub storage: BoxedStorage,
+    /// The configured provider, also accepting tokens minted by the server.
+    pub auth: JWTServiceAuthProvider<BoxedAuthProvider>,
+}
+
+/// A registry of storage backends and auth providers by name, see the [module docs](self).
+pub struct ServerFactory {
+    storage: BTreeMap<String, StorageConstructor>,
+    auth: BTreeMap<String, AuthConstructor>,
+}
+
+impl ServerFactory {
+    /// Creates a factory without any backends.
+    pub fn empty() -> Self {
+        Self {
+            storage: BTreeMap::new(),
+            auth: BTreeMap::new(),
+        }
+    }
+
+    /// Registers a storage backend, replacing any with the same name.
+    pub fn with_storage<F>(
+        mut self,
+        name: impl Into<String>,
+        constructor: impl Fn(toml::Table) -> F + Send + Sync + 'static,
+    ) -> Self
+    where
+        F: Future<Output = Result<BoxedStorage, FactoryError>> + Send + 'static,
+    {
+        let constructor = move |options| Box::pin(constructor(options)) as BoxFuture<_>;
+        self.storage.insert(name.into(), Box::new(constructor));
+        self
+    }
+
+    /// Registers an auth provider, replacing any with the same name.
+    pub fn with_auth(
+        mut self,
+        name: impl Into<String>,
+        constructor: impl Fn(toml::Table) -> Result<BoxedAuthProvider, FactoryError>
+        + Send
+        + Sync
+        + 'static,
+    ) -> Self {
+        self.auth.insert(name.into(), Box::new(constructor));
+        self
+    }
+
+    /// Builds the backends named in `config`. The provider is wrapped in a
+    /// [`JWTServiceAuthProvider`] using the JWT settings of `server`.
+    pub async fn build(
+        &self,
+        config: &FactoryConfig,
+        server: &AquilaServerConfig,
+    ) -> Result<Backends, FactoryError> {
+        let StorageConfig { backend, options } = &config.storage;
+        let storage = self
+            .storage
+            .get(backend)
+            .ok_or_else(|| FactoryError::Unknown {
+                kind: "storage backend",
+                name: backend.clone(),
+                known: self.storage.keys().cloned().collect(),
+            })?;
+
+        let AuthConfig {
+            provider,
+            options: auth_options,
+        } = &config.auth;
+        let auth = self
+            .auth
+            .get(provider)
+            .ok_or_else(|| FactoryError::Unknown {
+                kind: "auth provider",
+                name: provider.clone(),
+                known: self.auth.keys().cloned().collect(),
+            })?;
+
+        // The provider is created first, as it can't fail on the network.
+        let auth = auth(auth_options.clone())?;
+        Ok(Backends {
+            storage: storage(options.clone()).await?,
+            auth: JWTServiceAuthProvider::new(server.jwt_service(), auth),
+        })
+    }
+}
+
+impl Default for ServerFactory {
+    /// Registers the backends of the enabled features.
+    #[allow(unused_mut)]
+    fn default() -> Self {
+        let mut factory = Self::empty();
+
+        #[cfg(feature = "fs")]
+        {
+            #[derive(Deserialize)]
+            #[serde(deny_unknown_fields)]
+            struct FsOptions {
+                path: std::path::PathBuf,
+            }
+
+            factory = factory.with_storage("fs", |options| async move {
+                let options: FsOptions = parse_options("fs", options)?;
+                Ok(BoxedStorage::new(aquila_fs::FileSystemStorage::new(
+                    options.path,
+                )))
+            });
+        }
+
+        #[cfg(feature = "s3")]
+        {
+            factory = factory.with_storage("s3", |options| async move {
+                let options: BucketOptions = parse_options("s3", options)?;
+                let mut storage = aquila_s3::S3Storage::from_env(options.bucket).await;
+                if let Some(prefix) = options.prefix {
+                    storage = storage.with_prefix(prefix);
+                }
+                if let Some(secs) = options.presign_secs {
+                    storage = storage.with_presigning(std::time::Duration::from_secs(secs));
+                }
+                Ok(BoxedStorage::new(storage))
+            });
+        }
+
+        #[cfg(feature = "gcs")]
+        {
+            factory = factory.with_storage("gcs", |options| async move {
+                let options: BucketOptions = parse_options("gcs", options)?;
+                let mut storage = aquila_gcs::GcsStorage::from_env(options.bucket).await?;
+                if let Some(prefix) = options.prefix {
+                    storage = storage.with_prefix(prefix);
+                }
+                if let Some(secs) = options.presign_secs {
+                    storage = storage.with_presigning(std::time::Duration::from_secs(secs));
+                }
+                Ok(BoxedStorage::new(storage))
+            });
+        }
+
+        {
+            #[derive(Deserialize)]
+            #[serde(deny_unknown_fields)]
+            struct StaticUser {
+                token: String,
+                scopes: Vec<String>,
+            }
+
+            #[derive(Deserialize)]
+            #[serde(deny_unknown_fields)]
+            struct StaticOptions {
+                users: BTreeMap<String, StaticUser>,
+            }
+
+            factory = factory.with_auth("static", |options| {
+                let options: StaticOptions = parse_options("static", options)?;
+                let auth = options
+                    .users
+                    .into_iter()
+                    .fold(StaticTokenAuth::default(), |auth, (id, user)| {
+                        auth.with_user(id, user.token, user.scopes)
+                    });
+                Ok(BoxedAuthProvider::new(auth))
+            });
+        }
+
+        #[cfg(feature = "github_auth")]
+        {
+            use aquila_core::traits::{ScopeMapper, ScopeTable};
+            use std::sync::Arc;
+
+            #[derive(Deserialize)]
+            #[serde(deny_unknown_fields)]
+            struct GithubOptions {
+                client_id: String,
+                client_secret: String,
+                redirect_uri: String,
+                required_org: Option<String>,
+                /// See [`ScopeTable`](aquila_core::traits::ScopeTable) for the format.
+                scopes: Option<String>,
+            }
+
+            factory = factory.with_auth("github", |options| {
+                let options: GithubOptions = parse_options("github", options)?;
+                let scope_mapper = match options.scopes {
+                    Some(scopes) => {
+                        let table: ScopeTable =
+                            scopes.parse().map_err(|message| FactoryError::Options {
+                                name: "github".into(),
+                                message,
+                            })?;
+                        Some(Arc::new(table) as Arc<dyn ScopeMapper>)
+                    }
+                    None => None,
+                };
+
+                let config = aquila_auth_github::GithubConfig {
+                    client_id: options.client_id,
+                    client_secret: options.client_secret,
+                    redirect_uri: options.redirect_uri,
+                    required_org: options.required_org,
+                    scope_mapper,
+                };
+                Ok(BoxedAuthProvider::new(
+                    aquila_auth_github::GithubAuthProvider::new(Some(config)),
+                ))
+            });
+        }
+
+        #[cfg(feature = "mock_auth")]
+        {
+            #[derive(Deserialize)]
+            #[serde(deny_unknown_fields)]
+            struct MockOptions {
+                tokens: std::collections::HashMap<String, Vec<String>>,
+            }
+
+            factory = factory
+                .with_auth("mock", |options| {
+                    let options: MockOptions = parse_options("mock", options)?;
+                    Ok(BoxedAuthProvider::new(aquila_auth_mock::MockAuth::new(
+                        options.tokens,
+                    )))
+                })
+                .with_auth("allow_all", |options| {
+                    #[derive(Deserialize)]
+                    #[serde(deny_unknown_fields)]
+                    struct NoOptions {}
+
+                    parse_options::<NoOptions>("allow_all", options)?;
+                    Ok(BoxedAuthProvider::new(aquila_auth_mock::AllowAllAuth))
+                });
+        }
+
+        factory
+    }
+}
+
+/// Options of the bucket based backends.
+#[cfg(any(feature = "s3", feature = "gcs"))]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BucketOptions {
+    bucket: String,
+    prefix: Option<String>,
+    presign_secs: Option<u64>,
+}
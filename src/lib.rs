@@ -48,6 +48,7 @@
 //! | [`bevy_aquila`](./crates/bevy_aquila) | The Bevy plugin. Registers the `aquila://` asset source and handles downloading manifests/assets. |
 //! | [`aquila_client`](./crates/aquila_client) | Async HTTP client library. Used by the CLI and other tools/plugins to interact with the server. |
 //! | [`aquila_cli`](./crates/aquila_cli) | Command-line interface for uploading assets, publishing versions, and managing tokens. |
+//! | [`aquila_server_bin`](./crates/aquila_server_bin) | The `aquila-server` binary, configured by a config file and `AQUILA_*` variables. |
 //!
 //! ### Storage Backends
 //!
@@ -56,6 +57,9 @@
 //! | [`aquila_fs`](./crates/aquila_fs) | Local filesystem storage. Stores assets using atomic writes.                                       |
 //! | [`aquila_s3`](./crates/aquila_s3) | AWS S3 storage backend using the official AWS SDK.                                                        |
 //! | [`aquila_opendal`](./crates/aquila_opendal) | Backend for [Apache OpenDAL](https://opendal.apache.org/), supporting AWS S3, GCS, Azure and more. |
+//! | [`aquila_gcs`](./crates/aquila_gcs) | Google Cloud Storage backend with resumable uploads and signed URLs. |
+//! | [`aquila_encryption`](./crates/aquila_encryption) | At-rest encryption wrapper for any storage backend. |
+//! | [`aquila_throttle`](./crates/aquila_throttle) | Concurrency limiter for any storage backend. |
 //!
 //! ### Authentication
 //!
@@ -64,6 +68,12 @@
 //! | [`aquila_auth_github`](./crates/aquila_auth_github) | OAuth2 provider for GitHub. Supports organization membership checks. |
 //! | [`aquila_auth_mock`](./crates/aquila_auth_mock) | **Dev Only**. A mock provider that allows any token to pass with admin privileges. |
 //!
+//! ### Transformers
+//!
+//! | Crate | Description |
+//! |-------|-------------|
+//! | [`aquila_image`](./crates/aquila_image) | Resizes and converts images on the fly, e.g. for thumbnails. |
+//!
 //! ## Feature Flags
 //!
 //! | Feature | Description |
@@ -73,6 +83,16 @@
 //! | **`fs`** | Storage backend for the local filesystem (`aquila_fs`). |
 //! | **`s3`** | Storage backend for AWS S3 (`aquila_s3`). |
 //! | **`opendal`** | Storage backend for OpenDAL (`aquila_opendal`). |
+//! | **`gcs`** | Storage backend for Google Cloud Storage (`aquila_gcs`). |
+//! | **`encryption`** | At-rest encryption for storage backends (`aquila_encryption`). |
+//! | **`throttle`** | Concurrency limits for storage backends (`aquila_throttle`). |
+//! | **`image`** | Image resizing for `GET /assets/{hash}/transform` (`aquila_image`). |
+//! | **`schema`** | JSON Schema for the protocol types (`schemars`), see the `export_schema` example. |
+//! | **`openapi`** | Serves an OpenAPI document at `/openapi.json` and a Swagger UI at `/docs`. |
+//! | **`tus`** | Resumable uploads via the [tus](https://tus.io) protocol at `/files`. |
+//! | **`yaml`** | YAML manifests for `POST /manifest` and the client (`aquila_core::format`). |
+//! | **`toml`** | TOML manifests for `POST /manifest` and the client (`aquila_core::format`). |
+//! | **`factory`** | Builds the backends from a config file (`aquila::factory`), see the `configured_server` example. |
 //! | **`github_auth`** | GitHub OAuth2 provider (`aquila_auth_github`). |
 //! | **`mock_auth`** | Development authentication provider (`aquila_auth_mock`). |
 //!
@@ -295,6 +315,9 @@ pub mod server {
     pub use aquila_server::*;
 }
 
+#[cfg(feature = "factory")]
+pub mod factory;
+
 #[cfg(feature = "client")]
 pub mod client {
     pub use aquila_client::*;
@@ -320,6 +343,26 @@ pub mod opendal {
     pub use aquila_opendal::*;
 }
 
+#[cfg(feature = "gcs")]
+pub mod gcs {
+    pub use aquila_gcs::*;
+}
+
+#[cfg(feature = "encryption")]
+pub mod encryption {
+    pub use aquila_encryption::*;
+}
+
+#[cfg(feature = "throttle")]
+pub mod throttle {
+    pub use aquila_throttle::*;
+}
+
+#[cfg(feature = "image")]
+pub mod image {
+    pub use aquila_image::*;
+}
+
 #[cfg(feature = "github_auth")]
 pub mod auth_github {
     pub use aquila_auth_github::*;
@@ -348,4 +391,16 @@ pub mod prelude {
 
     #[cfg(feature = "opendal")]
     pub use aquila_opendal::OpendalStorage;
+
+    #[cfg(feature = "gcs")]
+    pub use aquila_gcs::GcsStorage;
+
+    #[cfg(feature = "encryption")]
+    pub use aquila_encryption::EncryptedStorage;
+
+    #[cfg(feature = "throttle")]
+    pub use aquila_throttle::ThrottledStorage;
+
+    #[cfg(feature = "image")]
+    pub use aquila_image::ImageResizer;
 }